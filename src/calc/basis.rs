@@ -0,0 +1,133 @@
+use super::ResultsMap;
+use chrono::{DateTime, Utc};
+use log::debug;
+
+// DEX-CEX basis: on-chain price relative to the off-chain (centralized exchange) price
+#[derive(Debug)]
+pub struct BasisReport {
+    pub series: Vec<(DateTime<Utc>, f64)>, // Spread in bps per bar where both sides reported
+    pub mean_bps: f64,
+    pub volatility_bps: f64,
+    pub max_excursion_bps: f64, // Largest absolute spread
+    pub max_excursion_at: DateTime<Utc>,
+    pub half_life_periods: Option<f64>, // None when the spread is not mean reverting
+}
+
+// Build the basis series from the raw source prices, the composite `vol` column is ignored
+pub fn calculate_basis(results_map: &ResultsMap) -> Option<BasisReport> {
+    let results_map = results_map.read().unwrap();
+
+    // Every bar of the grid, so the AR(1) fit can tell consecutive bars from ones across a gap
+    let mut grid: Vec<DateTime<Utc>> = results_map.keys().copied().collect();
+    grid.sort();
+
+    // Spread per bar, with the bar's position on the grid
+    let indexed: Vec<(usize, DateTime<Utc>, f64)> = grid
+        .iter()
+        .enumerate()
+        .filter_map(|(index, timestamp)| {
+            let row = &results_map[timestamp];
+            let on_chain_prices: Vec<f64> = row
+                .prices
+                .iter()
+                .filter(|(source, _)| source.is_on_chain())
+                .map(|(_, &price)| price)
+                .collect();
+            let cex_prices: Vec<f64> = row
+                .prices
+                .iter()
+                .filter(|(source, _)| !source.is_on_chain() && !source.is_oracle())
+                .map(|(_, &price)| price)
+                .collect();
+            if on_chain_prices.is_empty() || cex_prices.is_empty() {
+                return None;
+            }
+            let dex_price = on_chain_prices.iter().sum::<f64>() / on_chain_prices.len() as f64;
+            // Average the centralized venues so no single exchange drives the basis
+            let cex_price = cex_prices.iter().sum::<f64>() / cex_prices.len() as f64;
+            if cex_price <= 0.0 {
+                return None;
+            }
+            let spread = (dex_price - cex_price) / cex_price * 10_000.0;
+            // A non-finite price on either side gives no spread
+            spread.is_finite().then_some((index, *timestamp, spread))
+        })
+        .collect();
+    let series: Vec<(DateTime<Utc>, f64)> = indexed
+        .iter()
+        .map(|&(_, timestamp, spread)| (timestamp, spread))
+        .collect();
+
+    let n = series.len();
+    if n < 2 {
+        debug!("Not enough bars with both DEX and CEX prices for a basis report");
+        return None;
+    }
+
+    let mean_bps = series.iter().map(|(_, s)| s).sum::<f64>() / n as f64;
+    let volatility_bps = (series
+        .iter()
+        .map(|(_, s)| (s - mean_bps).powi(2))
+        .sum::<f64>()
+        / (n - 1) as f64)
+        .sqrt();
+
+    let &(max_excursion_at, max_excursion_bps) = series
+        .iter()
+        .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+        .unwrap();
+
+    // Spread and the next bar's spread, for bars next to each other on the grid
+    let pairs: Vec<(f64, f64)> = indexed
+        .windows(2)
+        .filter(|w| w[1].0 == w[0].0 + 1)
+        .map(|w| (w[0].2, w[1].2))
+        .collect();
+    let half_life_periods = half_life(&pairs);
+
+    Some(BasisReport {
+        series,
+        mean_bps,
+        volatility_bps,
+        max_excursion_bps,
+        max_excursion_at,
+        half_life_periods,
+    })
+}
+
+// Half-life of mean reversion from an AR(1) fit over consecutive bars `(s(t-1), s(t))`:
+// s(t) - s(t-1) = a + b * s(t-1)
+fn half_life(pairs: &[(f64, f64)]) -> Option<f64> {
+    if pairs.len() < 2 {
+        return None;
+    }
+    let lagged: Vec<f64> = pairs.iter().map(|&(previous, _)| previous).collect();
+    let deltas: Vec<f64> = pairs
+        .iter()
+        .map(|&(previous, next)| next - previous)
+        .collect();
+
+    let n = lagged.len() as f64;
+    let mean_lagged = lagged.iter().sum::<f64>() / n;
+    let mean_delta = deltas.iter().sum::<f64>() / n;
+
+    let covariance: f64 = lagged
+        .iter()
+        .zip(&deltas)
+        .map(|(x, y)| (x - mean_lagged) * (y - mean_delta))
+        .sum();
+    let variance: f64 = lagged.iter().map(|x| (x - mean_lagged).powi(2)).sum();
+    if variance == 0.0 {
+        return None;
+    }
+
+    let beta = covariance / variance;
+    debug!("Basis AR(1) slope {}", beta);
+
+    // Only a slope in (-1, 0) decays monotonically back to the mean
+    if beta < 0.0 && beta > -1.0 {
+        Some(-(2f64.ln()) / (1.0 + beta).ln())
+    } else {
+        None
+    }
+}
@@ -0,0 +1,119 @@
+use super::basis::calculate_basis;
+use super::sources::Source;
+use super::test_util::row;
+use super::ResultsMap;
+use chrono::{Duration, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+#[test]
+fn test_basis_statistics() {
+    let results_map: ResultsMap = Arc::new(RwLock::new(HashMap::new()));
+    let base_timestamp = Utc::now();
+
+    // Dune against an average CEX price of 2000, giving spreads of 10, -5, 20 and 0 bps
+    {
+        let mut map = results_map.write().unwrap();
+        let dune_prices = [2002.0, 1999.0, 2004.0, 2000.0];
+        for (i, dune_price) in dune_prices.iter().enumerate() {
+            map.insert(
+                base_timestamp + Duration::seconds(i as i64),
+                row(&[
+                    (Source::Polygon, 1999.0),
+                    (Source::Dune, *dune_price),
+                    (Source::Kraken, 2001.0),
+                ]),
+            );
+        }
+        // A bar with no DEX price is left out of the series
+        map.insert(
+            base_timestamp + Duration::seconds(4),
+            row(&[(Source::Polygon, 2000.0)]),
+        );
+    }
+
+    let basis = calculate_basis(&results_map).expect("Basis report should be produced");
+
+    assert_eq!(basis.series.len(), 4);
+    assert!((basis.series[0].1 - 10.0).abs() < 1e-9);
+    assert!((basis.mean_bps - 6.25).abs() < 1e-9);
+    assert!((basis.volatility_bps - 11.086778913041726).abs() < 1e-9);
+    assert!((basis.max_excursion_bps - 20.0).abs() < 1e-9);
+    assert_eq!(
+        basis.max_excursion_at,
+        base_timestamp + Duration::seconds(2)
+    );
+}
+
+#[test]
+fn test_basis_half_life_of_mean_reversion() {
+    let results_map: ResultsMap = Arc::new(RwLock::new(HashMap::new()));
+    let base_timestamp = Utc::now();
+
+    // Spread halves every bar, so the half-life is one period
+    {
+        let mut map = results_map.write().unwrap();
+        let mut spread_bps = 64.0;
+        for i in 0..6 {
+            let dune_price = 1000.0 * (1.0 + spread_bps / 10_000.0);
+            map.insert(
+                base_timestamp + Duration::seconds(i),
+                row(&[(Source::Dune, dune_price), (Source::Kraken, 1000.0)]),
+            );
+            spread_bps /= 2.0;
+        }
+    }
+
+    let basis = calculate_basis(&results_map).expect("Basis report should be produced");
+    let half_life = basis.half_life_periods.expect("Spread should mean revert");
+    assert!((half_life - 1.0).abs() < 1e-9, "Half-life: {}", half_life);
+}
+
+#[test]
+fn test_basis_half_life_skips_gaps() {
+    let results_map: ResultsMap = Arc::new(RwLock::new(HashMap::new()));
+    let base_timestamp = Utc::now();
+
+    // Spread halves every bar, but the DEX misses bar 3. Fitting 16 bps to 4 bps as one step
+    // would understate the half-life.
+    {
+        let mut map = results_map.write().unwrap();
+        let mut spread_bps = 64.0;
+        for i in 0..7 {
+            let dune_price = 1000.0 * (1.0 + spread_bps / 10_000.0);
+            let prices = if i == 3 {
+                row(&[(Source::Kraken, 1000.0)])
+            } else {
+                row(&[(Source::Dune, dune_price), (Source::Kraken, 1000.0)])
+            };
+            map.insert(base_timestamp + Duration::seconds(i), prices);
+            spread_bps /= 2.0;
+        }
+    }
+
+    let basis = calculate_basis(&results_map).expect("Basis report should be produced");
+    assert_eq!(basis.series.len(), 6);
+    let half_life = basis.half_life_periods.expect("Spread should mean revert");
+    assert!((half_life - 1.0).abs() < 1e-9, "Half-life: {}", half_life);
+}
+
+#[test]
+fn test_basis_skips_unpriceable_spreads() {
+    let results_map: ResultsMap = Arc::new(RwLock::new(HashMap::new()));
+    let base_timestamp = Utc::now();
+
+    {
+        let mut map = results_map.write().unwrap();
+        for (i, cex_price) in [1000.0, 0.0, -1000.0, f64::NAN, 1000.0].iter().enumerate() {
+            map.insert(
+                base_timestamp + Duration::seconds(i as i64),
+                row(&[(Source::Dune, 1001.0), (Source::Kraken, *cex_price)]),
+            );
+        }
+    }
+
+    // Zero, negative and NaN CEX prices are left out rather than panicking the report
+    let basis = calculate_basis(&results_map).expect("Basis report should be produced");
+    assert_eq!(basis.series.len(), 2);
+    assert!((basis.max_excursion_bps - 10.0).abs() < 1e-9);
+}
@@ -1,29 +1,19 @@
-use chrono::NaiveDateTime;
+use super::quorum::{Quorum, QuorumAction, QuorumPolicy};
+use super::sources::Source;
+use super::ResultsMap;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use log::debug;
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
 
 pub fn calculate_volatility(
-    results_map: &Arc<
-        RwLock<
-            HashMap<
-                NaiveDateTime,
-                (
-                    Option<f64>,
-                    Option<f64>,
-                    Option<f64>,
-                    Option<f64>,
-                    Option<f64>,
-                ),
-            >,
-        >,
-    >,
+    results_map: &ResultsMap,
     no_of_periods: usize,
-) -> Option<f64> {
+    quorum: &QuorumPolicy,
+) -> Result<Option<f64>> {
     // Lock the map here
     let mut results_map = results_map.write().unwrap();
 
-    let mut timestamps: Vec<NaiveDateTime> = results_map.keys().cloned().collect();
+    let mut timestamps: Vec<DateTime<Utc>> = results_map.keys().cloned().collect();
     timestamps.sort(); // Sort by timestamp (ascending)
 
     // Keep most recent `no_of_periods` timestamps
@@ -33,47 +23,133 @@ pub fn calculate_volatility(
         }
     }
 
-    // Calculate `vol price` for each entry as the highest value among VW, AP, KR, and CA
-    for (_timestamp, (vw, ap, kr, ca, vol)) in results_map.iter_mut() {
-        // Collect non-None values into a vector
-        let mut values = vec![];
-        if let Some(vw_value) = *vw {
-            values.push(vw_value);
-        }
-        if let Some(ap_value) = *ap {
-            values.push(ap_value);
-        }
-        if let Some(kr_value) = *kr {
-            values.push(kr_value);
-        }
-        if let Some(ca_value) = *ca {
-            values.push(ca_value);
-        }
-
-        // Calculate the maximum value or set vol to None if the vector is empty
-        *vol = if values.is_empty() {
-            None
-        } else {
-            Some(values.into_iter().fold(f64::MAX, f64::min))
+    // Calculate `vol price` for each entry as the lowest value among the source prices
+    // that agree with each other, bars with no prices or failing the quorum are left as None
+    let mut failed_bars = vec![];
+    for (timestamp, row) in results_map.iter_mut() {
+        // Collect the reported prices into a vector
+        let values: Vec<f64> = row.prices.values().copied().collect();
+
+        row.vol = match quorum.agreeing_prices(&values) {
+            Quorum::Agreed(agreeing) => agreeing.into_iter().reduce(f64::min),
+            Quorum::NoData => None,
+            Quorum::Failed => {
+                debug!(
+                    "Bar {} failed quorum with {} source(s): {:?}",
+                    timestamp,
+                    values.len(),
+                    values
+                );
+                failed_bars.push(*timestamp);
+                None
+            }
         };
     }
 
+    if !failed_bars.is_empty() && quorum.action == QuorumAction::Fail {
+        failed_bars.sort();
+        return Err(anyhow!(
+            "{} bar(s) failed the quorum of {} source(s), first at {}",
+            failed_bars.len(),
+            quorum.min_sources,
+            failed_bars[0]
+        ));
+    }
+
     // Interpolate missing values (None) as before
-    let mut vol_values: Vec<(NaiveDateTime, f64)> = results_map
+    let mut vol_values: Vec<(DateTime<Utc>, f64)> = results_map
         .iter()
-        .map(|(timestamp, (_, _, _, _, vol))| (*timestamp, vol.unwrap_or(f64::NAN)))
+        .map(|(timestamp, row)| (*timestamp, row.vol.unwrap_or(f64::NAN)))
         .collect();
 
     // Ensure the values are sorted by timestamp for interpolation
     vol_values.sort_by_key(|&(timestamp, _)| timestamp);
 
     // Perform linear interpolation on None segments (now represented by NaN)
+    interpolate_gaps(&mut vol_values);
+
+    // Update the `volp` values back to the `results_map`
+    for (timestamp, vol_value) in &vol_values {
+        if let Some(entry) = results_map.get_mut(timestamp) {
+            entry.vol = Some(*vol_value);
+        }
+    }
+
+    Ok(volatility_of_prices(&vol_values))
+}
+
+// Lowest of the prices that agree with each other, None when no source reported or the bar
+// fails the quorum
+pub fn composite_price(quorum: &QuorumPolicy, values: &[f64]) -> Option<f64> {
+    match quorum.agreeing_prices(values) {
+        Quorum::Agreed(agreeing) => agreeing.into_iter().reduce(f64::min),
+        Quorum::NoData | Quorum::Failed => None,
+    }
+}
+
+// Volatility of a single source's own series over the same window as the composite
+#[derive(Debug)]
+pub struct SourceVolatility {
+    pub source: Source,
+    pub coverage: f64,           // Fraction of bars the source reported a price for
+    pub volatility: Option<f64>, // None when coverage is below the minimum
+}
+
+// Run the volatility estimate on each source's own prices, call after `calculate_volatility`
+// so the map is already trimmed to the estimation window
+pub fn calculate_source_volatilities(
+    results_map: &ResultsMap,
+    min_coverage: f64,
+) -> Vec<SourceVolatility> {
+    let results_map = results_map.read().unwrap();
+
+    let mut rows: Vec<_> = results_map.iter().collect();
+    rows.sort_by_key(|&(timestamp, _)| *timestamp);
+
+    Source::ALL
+        .iter()
+        .map(|&source| {
+            let mut source_values: Vec<(DateTime<Utc>, f64)> = rows
+                .iter()
+                .map(|&(timestamp, row)| {
+                    let price = row.prices.get(&source).copied();
+                    (*timestamp, price.unwrap_or(f64::NAN))
+                })
+                .collect();
+
+            let reported = source_values.iter().filter(|(_, p)| !p.is_nan()).count();
+            let coverage = if source_values.is_empty() {
+                0.0
+            } else {
+                reported as f64 / source_values.len() as f64
+            };
+
+            // Too few bars would make the interpolated series meaningless
+            let volatility = if reported < 2 || coverage < min_coverage {
+                debug!("{:?} coverage {:.2} below minimum", source, coverage);
+                None
+            } else {
+                interpolate_gaps(&mut source_values);
+                volatility_of_prices(&source_values)
+            };
+
+            SourceVolatility {
+                source,
+                coverage,
+                volatility,
+            }
+        })
+        .collect()
+}
+
+// Linear interpolation of NaN gaps, leading gaps take the first valid value
+pub fn interpolate_gaps(vol_values: &mut [(DateTime<Utc>, f64)]) {
     let mut i = 0;
 
     // Backward Interpolation
     if !vol_values.is_empty() && vol_values[0].1.is_nan() {
         let mut first_valid_value = None;
-        for &(_, value) in &vol_values {
+        for &(_, value) in vol_values.iter() {
             if !value.is_nan() {
                 first_valid_value = Some(value);
                 break;
@@ -123,14 +199,10 @@ pub fn calculate_volatility(
             i += 1;
         }
     }
+}
 
-    // Update the `volp` values back to the `results_map`
-    for (timestamp, vol_value) in &vol_values {
-        if let Some(entry) = results_map.get_mut(timestamp) {
-            entry.4 = Some(*vol_value); // Update the fifth element (vol)
-        }
-    }
-
+// Sample standard deviation of the simple returns of a price series sorted by timestamp
+fn volatility_of_prices(vol_values: &[(DateTime<Utc>, f64)]) -> Option<f64> {
     // Calculate returns: (current_vol - previous_vol) / previous_vol
     let mut returns = vec![];
     for i in 1..vol_values.len() {
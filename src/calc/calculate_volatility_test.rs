@@ -1,5 +1,9 @@
-use super::calculate_volatility::calculate_volatility;
-use chrono::{Duration, NaiveDateTime, Utc};
+use super::calculate_volatility::{calculate_source_volatilities, calculate_volatility};
+use super::quorum::{Quorum, QuorumAction, QuorumPolicy};
+use super::sources::Source;
+use super::test_util::row;
+use super::ResultsMap;
+use chrono::{Duration, Utc};
 use rand::Rng; // Import random number generation
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
@@ -9,30 +13,17 @@ fn test_calculate_volatility_with_random_prices() {
     let mut rng = rand::thread_rng();
 
     // Create an Arc<RwLock<HashMap>> to store the random prices
-    let results_map: Arc<
-        RwLock<
-            HashMap<
-                NaiveDateTime,
-                (
-                    Option<f64>,
-                    Option<f64>,
-                    Option<f64>,
-                    Option<f64>,
-                    Option<f64>,
-                ),
-            >,
-        >,
-    > = Arc::new(RwLock::new(HashMap::new()));
+    let results_map: ResultsMap = Arc::new(RwLock::new(HashMap::new()));
 
     // Generate 50 random prices between 50.0 and 150.0
     for i in 0..50 {
-        let timestamp = Utc::now().naive_utc() - Duration::seconds(i);
+        let timestamp = Utc::now() - Duration::seconds(i);
         let random_price = rng.gen_range(50.0..150.0);
 
         // Insert the random price into the HashMap within the RwLock
         {
             let mut map = results_map.write().unwrap();
-            map.insert(timestamp, (Some(random_price), None, None, None, None));
+            map.insert(timestamp, row(&[(Source::Polygon, random_price)]));
         }
     }
 
@@ -40,7 +31,7 @@ fn test_calculate_volatility_with_random_prices() {
     let no_of_periods = 50;
 
     // Call the volatility calculation function
-    match calculate_volatility(&results_map, no_of_periods) {
+    match calculate_volatility(&results_map, no_of_periods, &QuorumPolicy::default()).unwrap() {
         Some(volatility) => {
             // Ensure volatility is non-negative
             assert!(
@@ -56,51 +47,32 @@ fn test_calculate_volatility_with_random_prices() {
 #[test]
 fn test_interpolation_in_calculate_volatility() {
     // Create an Arc<RwLock<HashMap>> to store the prices with gaps (None values)
-    let results_map: Arc<
-        RwLock<
-            HashMap<
-                NaiveDateTime,
-                (
-                    Option<f64>,
-                    Option<f64>,
-                    Option<f64>,
-                    Option<f64>,
-                    Option<f64>,
-                ),
-            >,
-        >,
-    > = Arc::new(RwLock::new(HashMap::new()));
+    let results_map: ResultsMap = Arc::new(RwLock::new(HashMap::new()));
 
     // Base timestamp for the data
-    let base_timestamp = Utc::now().naive_utc();
+    let base_timestamp = Utc::now();
 
     // Add initial defined values
     {
         let mut map = results_map.write().unwrap();
-        map.insert(base_timestamp, (Some(100.0), None, None, None, None));
+        map.insert(base_timestamp, row(&[(Source::Polygon, 100.0)]));
         map.insert(
             base_timestamp + Duration::seconds(1),
-            (Some(110.0), None, None, None, None),
+            row(&[(Source::Polygon, 110.0)]),
         );
 
         // Introduce gaps with None values that should be interpolated
-        map.insert(
-            base_timestamp + Duration::seconds(2),
-            (None, None, None, None, None),
-        );
-        map.insert(
-            base_timestamp + Duration::seconds(3),
-            (None, None, None, None, None),
-        );
+        map.insert(base_timestamp + Duration::seconds(2), row(&[]));
+        map.insert(base_timestamp + Duration::seconds(3), row(&[]));
 
         // Add more defined values to provide start and end points for interpolation
         map.insert(
             base_timestamp + Duration::seconds(4),
-            (Some(130.0), None, None, None, None),
+            row(&[(Source::Polygon, 130.0)]),
         );
         map.insert(
             base_timestamp + Duration::seconds(5),
-            (Some(150.0), None, None, None, None),
+            row(&[(Source::Polygon, 150.0)]),
         );
     }
 
@@ -108,7 +80,7 @@ fn test_interpolation_in_calculate_volatility() {
     let no_of_periods = 6;
 
     // Call the volatility calculation function
-    match calculate_volatility(&results_map, no_of_periods) {
+    match calculate_volatility(&results_map, no_of_periods, &QuorumPolicy::default()).unwrap() {
         Some(volatility) => {
             // Ensure volatility is non-negative
             assert!(
@@ -120,7 +92,7 @@ fn test_interpolation_in_calculate_volatility() {
             // Check the interpolated values
             let map = results_map.read().unwrap();
             for (timestamp, entry) in map.iter() {
-                let vol = entry.4; // Assume the volatility value is in the fifth element
+                let vol = entry.vol;
                 println!("Timestamp: {:?}, VOL: {:?}", timestamp, vol);
 
                 // Ensure that the previously None values were interpolated (i.e., not None anymore)
@@ -195,3 +167,176 @@ fn test_calculate_volatility_with_given_returns() {
         expected_volatility
     );
 }
+
+#[test]
+fn test_quorum_gap_fills_disagreeing_bars() {
+    let results_map: ResultsMap = Arc::new(RwLock::new(HashMap::new()));
+    let base_timestamp = Utc::now();
+
+    {
+        let mut map = results_map.write().unwrap();
+        map.insert(
+            base_timestamp,
+            row(&[(Source::Polygon, 100.0), (Source::Dune, 100.1)]),
+        );
+        // Only one source reported, fails a quorum of 2
+        map.insert(
+            base_timestamp + Duration::seconds(1),
+            row(&[(Source::Polygon, 500.0)]),
+        );
+        // Two sources reported but 100 bps apart, fails a 50 bps tolerance
+        map.insert(
+            base_timestamp + Duration::seconds(2),
+            row(&[(Source::Polygon, 104.0), (Source::Kraken, 105.04)]),
+        );
+        map.insert(
+            base_timestamp + Duration::seconds(3),
+            row(&[
+                (Source::Polygon, 106.0),
+                (Source::Kraken, 106.01),
+                (Source::CoinApi, 120.0),
+            ]),
+        );
+    }
+
+    let quorum = QuorumPolicy {
+        min_sources: 2,
+        tolerance_bps: Some(50.0),
+        action: QuorumAction::GapFill,
+    };
+
+    let volatility = calculate_volatility(&results_map, 4, &quorum).unwrap();
+    assert!(volatility.is_some(), "Volatility calculation returned None");
+
+    // Failed bars are interpolated between the bars that passed
+    let map = results_map.read().unwrap();
+    let vol_at = |seconds| {
+        map[&(base_timestamp + Duration::seconds(seconds))]
+            .vol
+            .unwrap()
+    };
+    assert!((vol_at(0) - 100.0).abs() < 1e-9);
+    assert!((vol_at(1) - 102.0).abs() < 1e-9);
+    assert!((vol_at(2) - 104.0).abs() < 1e-9);
+    // The outlying 120.0 is excluded from the agreeing sources
+    assert!((vol_at(3) - 106.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_quorum_fail_aborts_calculation() {
+    let results_map: ResultsMap = Arc::new(RwLock::new(HashMap::new()));
+    let base_timestamp = Utc::now();
+
+    {
+        let mut map = results_map.write().unwrap();
+        map.insert(
+            base_timestamp,
+            row(&[(Source::Polygon, 100.0), (Source::Dune, 100.0)]),
+        );
+        map.insert(
+            base_timestamp + Duration::seconds(1),
+            row(&[(Source::Kraken, 101.0)]),
+        );
+    }
+
+    let quorum = QuorumPolicy {
+        min_sources: 2,
+        tolerance_bps: None,
+        action: QuorumAction::Fail,
+    };
+
+    assert!(
+        calculate_volatility(&results_map, 2, &quorum).is_err(),
+        "A bar below quorum should fail the run"
+    );
+}
+
+#[test]
+fn test_quorum_fail_allows_bars_without_prices() {
+    let results_map: ResultsMap = Arc::new(RwLock::new(HashMap::new()));
+    let base_timestamp = Utc::now();
+
+    {
+        let mut map = results_map.write().unwrap();
+        map.insert(base_timestamp, row(&[(Source::Polygon, 100.0)]));
+        // A gap nobody reported on is missing data, not a quorum failure
+        map.insert(base_timestamp + Duration::seconds(1), row(&[]));
+        map.insert(
+            base_timestamp + Duration::seconds(2),
+            row(&[(Source::Polygon, 102.0)]),
+        );
+    }
+
+    let quorum = QuorumPolicy {
+        action: QuorumAction::Fail,
+        ..QuorumPolicy::default()
+    };
+    let volatility = calculate_volatility(&results_map, 3, &quorum).unwrap();
+    assert!(volatility.is_some());
+    let map = results_map.read().unwrap();
+    let gap = map[&(base_timestamp + Duration::seconds(1))].vol.unwrap();
+    assert!((gap - 101.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_quorum_ignores_unusable_prices() {
+    let quorum = QuorumPolicy {
+        min_sources: 2,
+        tolerance_bps: Some(50.0),
+        action: QuorumAction::GapFill,
+    };
+    assert_eq!(
+        quorum.agreeing_prices(&[0.0, -5.0, 100.0, 100.2]),
+        Quorum::Agreed(vec![100.0, 100.2])
+    );
+    assert_eq!(
+        quorum.agreeing_prices(&[0.0, f64::NAN, f64::INFINITY]),
+        Quorum::NoData
+    );
+    assert_eq!(quorum.agreeing_prices(&[100.0, 120.0]), Quorum::Failed);
+}
+
+#[test]
+fn test_source_volatilities_match_composite_and_mark_low_coverage() {
+    let results_map: ResultsMap = Arc::new(RwLock::new(HashMap::new()));
+    let base_timestamp = Utc::now();
+
+    {
+        let mut map = results_map.write().unwrap();
+        for (i, price) in [100.0, 102.0, 101.0, 104.0, 103.0].iter().enumerate() {
+            // Kraken only reports on the first bar
+            let mut bar = row(&[(Source::Polygon, *price)]);
+            if i == 0 {
+                bar.prices.insert(Source::Kraken, 90.0);
+            }
+            map.insert(base_timestamp + Duration::seconds(i as i64), bar);
+        }
+    }
+
+    calculate_volatility(&results_map, 5, &QuorumPolicy::default()).unwrap();
+    let source_vols = calculate_source_volatilities(&results_map, 0.8);
+
+    let polygon = &source_vols[0];
+    assert_eq!(polygon.source, Source::Polygon);
+    assert!((polygon.coverage - 1.0).abs() < 1e-9);
+    assert!(polygon.volatility.is_some());
+
+    // Dune never reported, Kraken reported on a single bar
+    assert_eq!(source_vols[1].coverage, 0.0);
+    assert!(source_vols[1].volatility.is_none());
+    assert!((source_vols[2].coverage - 0.2).abs() < 1e-9);
+    assert!(source_vols[2].volatility.is_none());
+
+    // Without the Kraken bar the composite is the Polygon series
+    results_map
+        .write()
+        .unwrap()
+        .get_mut(&base_timestamp)
+        .unwrap()
+        .prices
+        .remove(&Source::Kraken);
+    let composite = calculate_volatility(&results_map, 5, &QuorumPolicy::default())
+        .unwrap()
+        .unwrap();
+    assert!((polygon.volatility.unwrap() - composite).abs() < 1e-12);
+}
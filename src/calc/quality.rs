@@ -0,0 +1,34 @@
+use super::sources::Source;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+// Per-source data quality counters for one run
+#[derive(Debug, Default, Clone)]
+pub struct SourceQuality {
+    pub bars_received: usize,
+    pub bars_merged: usize,
+    pub outside_window: usize, // Bars newer than the last completed bar
+    pub timestamp_failures: usize,
+}
+
+pub type QualityReport = Arc<RwLock<BTreeMap<Source, SourceQuality>>>;
+
+// Print one line per source that reported, in source column order
+pub fn print_quality_report(quality_report: &QualityReport) {
+    let quality_report = quality_report.read().unwrap();
+    println!(
+        "{:<10} {:<10} {:>9} {:>7} {:>15} {:>18}",
+        "Source", "Venue", "Received", "Merged", "Outside window", "Timestamp errors"
+    );
+    for (source, quality) in quality_report.iter() {
+        println!(
+            "{:<10} {:<10} {:>9} {:>7} {:>15} {:>18}",
+            source.label(),
+            source.venue(),
+            quality.bars_received,
+            quality.bars_merged,
+            quality.outside_window,
+            quality.timestamp_failures
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{anyhow, Result};
+use std::env;
+
+// What to do with a bar whose sources fail the quorum check
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuorumAction {
+    GapFill, // Treat the bar as missing and let interpolation fill it
+    Fail,    // Abort the run
+}
+
+impl QuorumAction {
+    // As spelled in QUORUM_ON_FAIL
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuorumAction::GapFill => "gap_fill",
+            QuorumAction::Fail => "fail",
+        }
+    }
+}
+
+// Outcome of the quorum check on one bar's prices
+#[derive(Debug, Clone, PartialEq)]
+pub enum Quorum {
+    Agreed(Vec<f64>), // The largest group of prices within tolerance, never empty
+    NoData,           // No source reported a usable price, an ordinary gap
+    Failed,           // Too few of the reported prices agree
+}
+
+// Minimum number of sources that must agree before a bar is used
+#[derive(Debug, Clone)]
+pub struct QuorumPolicy {
+    pub min_sources: usize,
+    pub tolerance_bps: Option<f64>, // None means any set of prices agree
+    pub action: QuorumAction,
+}
+
+impl Default for QuorumPolicy {
+    // A single source is enough, matching the original behaviour
+    fn default() -> Self {
+        QuorumPolicy {
+            min_sources: 1,
+            tolerance_bps: None,
+            action: QuorumAction::GapFill,
+        }
+    }
+}
+
+impl QuorumPolicy {
+    // Load QUORUM_MIN_SOURCES, QUORUM_TOLERANCE_BPS and QUORUM_ON_FAIL from the .env file
+    pub fn from_env() -> Result<Self> {
+        let mut policy = QuorumPolicy::default();
+
+        if let Ok(min_sources) = env::var("QUORUM_MIN_SOURCES") {
+            policy.min_sources = min_sources
+                .parse()
+                .map_err(|_| anyhow!("QUORUM_MIN_SOURCES must be a valid integer"))?;
+            if policy.min_sources == 0 {
+                return Err(anyhow!("QUORUM_MIN_SOURCES must be greater than 0"));
+            }
+        }
+
+        if let Ok(tolerance) = env::var("QUORUM_TOLERANCE_BPS") {
+            let tolerance: f64 = tolerance
+                .parse()
+                .map_err(|_| anyhow!("QUORUM_TOLERANCE_BPS must be a valid number"))?;
+            if !tolerance.is_finite() || tolerance < 0.0 {
+                return Err(anyhow!(
+                    "QUORUM_TOLERANCE_BPS must be a non-negative number"
+                ));
+            }
+            policy.tolerance_bps = Some(tolerance);
+        }
+
+        if let Ok(action) = env::var("QUORUM_ON_FAIL") {
+            policy.action = match action.as_str() {
+                "gap_fill" => QuorumAction::GapFill,
+                "fail" => QuorumAction::Fail,
+                _ => {
+                    return Err(anyhow!(
+                        "QUORUM_ON_FAIL must be one of: 'gap_fill' or 'fail'."
+                    ))
+                }
+            };
+        }
+
+        Ok(policy)
+    }
+
+    // Find the largest group of prices that lie within `tolerance_bps` of each other, failing
+    // if that group is smaller than `min_sources`. Prices that aren't finite and positive are
+    // ignored, so the bps spread is always defined.
+    pub fn agreeing_prices(&self, prices: &[f64]) -> Quorum {
+        let mut sorted: Vec<f64> = prices
+            .iter()
+            .copied()
+            .filter(|p| p.is_finite() && *p > 0.0)
+            .collect();
+        if sorted.is_empty() {
+            return Quorum::NoData;
+        }
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let cluster = match self.tolerance_bps {
+            None => sorted,
+            Some(tolerance) => {
+                // Sliding window over the sorted prices, widest span within tolerance wins
+                let mut best = (0, 0);
+                let mut start = 0;
+                for end in 0..sorted.len() {
+                    while (sorted[end] - sorted[start]) / sorted[start] * 10_000.0 > tolerance {
+                        start += 1;
+                    }
+                    if end + 1 - start > best.1 - best.0 {
+                        best = (start, end + 1);
+                    }
+                }
+                sorted[best.0..best.1].to_vec()
+            }
+        };
+
+        if cluster.len() >= self.min_sources {
+            Quorum::Agreed(cluster)
+        } else {
+            Quorum::Failed
+        }
+    }
+}
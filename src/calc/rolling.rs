@@ -0,0 +1,175 @@
+use super::calculate_volatility::interpolate_gaps;
+use chrono::{DateTime, Utc};
+use log::debug;
+use std::collections::VecDeque;
+
+// One bar of the window: the composite price if there is one, the interpolated price the
+// returns are taken from, and the return from the previous bar
+#[derive(Debug)]
+struct Bar {
+    timestamp: DateTime<Utc>,
+    price: Option<f64>,
+    filled: f64,
+    ret: Option<f64>, // None for the first bar of the window
+}
+
+// Count, mean and sum of squared deviations of the returns, Welford style with removal
+#[derive(Debug, Default)]
+struct RunningStats {
+    n: usize,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningStats {
+    fn add(&mut self, x: f64) {
+        self.n += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    fn remove(&mut self, x: f64) {
+        if self.n <= 1 {
+            *self = RunningStats::default();
+            return;
+        }
+        self.n -= 1;
+        let delta = x - self.mean;
+        self.mean -= delta / self.n as f64;
+        // Rounding can take a near zero sum just below zero
+        self.m2 = (self.m2 - delta * (x - self.mean)).max(0.0);
+    }
+}
+
+// Rolling window volatility updated one bar at a time. Gives the same estimate as
+// `calculate_volatility` over the latest `no_of_periods` bars: composite prices are gap filled
+// the same way and the sample standard deviation of the simple returns is kept as running
+// sums, so a new bar costs O(1) plus the length of any gap it closes.
+#[derive(Debug)]
+pub struct RollingVolatility {
+    no_of_periods: usize,
+    bars: VecDeque<Bar>, // Sorted by timestamp
+    stats: RunningStats,
+    removals: usize, // Since the sums were last rebuilt
+}
+
+impl RollingVolatility {
+    pub fn new(no_of_periods: usize) -> Self {
+        RollingVolatility {
+            no_of_periods,
+            bars: VecDeque::with_capacity(no_of_periods + 1),
+            stats: RunningStats::default(),
+            removals: 0,
+        }
+    }
+
+    // Set the composite price of the bar at `timestamp`, None for a gap. A new bar is added and
+    // the oldest dropped once the window is full, an existing bar is corrected in place. Returns
+    // whether the window changed, bars older than a full window are ignored.
+    pub fn update(&mut self, timestamp: DateTime<Utc>, price: Option<f64>) -> bool {
+        let price = price.filter(|p| p.is_finite());
+        let index = match self
+            .bars
+            .binary_search_by_key(&timestamp, |bar| bar.timestamp)
+        {
+            Ok(index) if self.bars[index].price == price => return false,
+            Ok(index) => {
+                debug!("Correcting bar {} to {:?}", timestamp, price);
+                self.bars[index].price = price;
+                index
+            }
+            Err(0) if self.bars.len() >= self.no_of_periods => return false,
+            Err(index) => {
+                self.bars.insert(
+                    index,
+                    Bar {
+                        timestamp,
+                        price,
+                        filled: f64::NAN,
+                        ret: None,
+                    },
+                );
+                index
+            }
+        };
+        self.refill(index);
+
+        while self.bars.len() > self.no_of_periods {
+            self.evict();
+        }
+        if !self.stats.mean.is_finite() || !self.stats.m2.is_finite() {
+            self.rebuild();
+        }
+        true
+    }
+
+    // Sample standard deviation of the returns, None until the window has two bars
+    pub fn volatility(&self) -> Option<f64> {
+        if self.stats.n == 0 {
+            return None;
+        }
+        Some((self.stats.m2 / (self.stats.n - 1) as f64).sqrt())
+    }
+
+    // Drop the oldest bar and its successor's return
+    fn evict(&mut self) {
+        if let Some(ret) = self.bars.get_mut(1).and_then(|bar| bar.ret.take()) {
+            self.stats.remove(ret);
+        }
+        self.bars.pop_front();
+
+        // A gap now at the front takes the first price after it instead of being interpolated
+        if self.bars.front().is_some_and(|bar| bar.price.is_none()) {
+            self.refill(0);
+        }
+
+        // Removal slowly loses precision, rebuild the sums once per window
+        self.removals += 1;
+        if self.removals >= self.no_of_periods {
+            self.rebuild();
+        }
+    }
+
+    // Re-interpolate the gaps either side of the bar at `index` and update the returns they touch
+    fn refill(&mut self, index: usize) {
+        let has_price = |bar: &Bar| bar.price.is_some();
+        let start = (0..index)
+            .rev()
+            .find(|&i| has_price(&self.bars[i]))
+            .map_or(0, |i| i + 1);
+        let end = (index + 1..self.bars.len())
+            .find(|&i| has_price(&self.bars[i]))
+            .unwrap_or(self.bars.len());
+
+        // Interpolate with the priced bars either side, as the batch path sees them
+        let from = start.saturating_sub(1);
+        let to = (end + 1).min(self.bars.len());
+        let mut values: Vec<(DateTime<Utc>, f64)> = self
+            .bars
+            .range(from..to)
+            .map(|bar| (bar.timestamp, bar.price.unwrap_or(f64::NAN)))
+            .collect();
+        interpolate_gaps(&mut values);
+        for (i, &(_, filled)) in (from..to).zip(values.iter()) {
+            self.bars[i].filled = filled;
+        }
+
+        for i in start.max(1)..to {
+            let previous = self.bars[i - 1].filled;
+            let ret = (self.bars[i].filled - previous) / previous;
+            if let Some(old) = self.bars[i].ret.replace(ret) {
+                self.stats.remove(old);
+            }
+            self.stats.add(ret);
+        }
+    }
+
+    fn rebuild(&mut self) {
+        self.stats = RunningStats::default();
+        for ret in self.bars.iter().filter_map(|bar| bar.ret) {
+            self.stats.add(ret);
+        }
+        self.removals = 0;
+    }
+}
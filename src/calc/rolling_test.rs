@@ -0,0 +1,148 @@
+use super::calculate_volatility::{calculate_volatility, composite_price};
+use super::quorum::QuorumPolicy;
+use super::rolling::RollingVolatility;
+use super::sources::Source;
+use super::{PriceRow, ResultsMap};
+use chrono::{DateTime, Duration, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+fn timestamp(i: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(1717200000, 0).unwrap() + Duration::minutes(i)
+}
+
+fn row(price: Option<f64>) -> PriceRow {
+    PriceRow {
+        prices: price.map(|p| (Source::Polygon, p)).into_iter().collect(),
+        vol: None,
+    }
+}
+
+// The batch estimate over the latest `no_of_periods` of `bars`
+fn batch(bars: &[(DateTime<Utc>, Option<f64>)], no_of_periods: usize) -> Option<f64> {
+    let results_map: ResultsMap = Arc::new(RwLock::new(HashMap::new()));
+    results_map.write().unwrap().extend(
+        bars.iter()
+            .map(|&(timestamp, price)| (timestamp, row(price))),
+    );
+    calculate_volatility(&results_map, no_of_periods, &QuorumPolicy::default()).unwrap()
+}
+
+fn assert_same(rolling: Option<f64>, batch: Option<f64>) {
+    match (rolling, batch) {
+        (Some(rolling), Some(batch)) if rolling.is_nan() || batch.is_nan() => {
+            assert!(
+                rolling.is_nan() && batch.is_nan(),
+                "{} vs {}",
+                rolling,
+                batch
+            )
+        }
+        (Some(rolling), Some(batch)) => assert!(
+            (rolling - batch).abs() <= 1e-9 * batch.abs().max(1e-12),
+            "rolling {} vs batch {}",
+            rolling,
+            batch
+        ),
+        (rolling, batch) => assert_eq!(rolling, batch),
+    }
+}
+
+// A random walk around 3000 with roughly one bar in five missing
+fn random_bars(rng: &mut StdRng, count: i64) -> Vec<(DateTime<Utc>, Option<f64>)> {
+    let mut price = 3000.0;
+    (0..count)
+        .map(|i| {
+            price *= 1.0 + rng.gen_range(-0.01..0.01);
+            let reported = rng.gen_bool(0.8);
+            (timestamp(i), reported.then_some(price))
+        })
+        .collect()
+}
+
+#[test]
+fn test_matches_batch_as_bars_arrive() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let bars = random_bars(&mut rng, 300);
+    let no_of_periods = 50;
+    let mut rolling = RollingVolatility::new(no_of_periods);
+
+    for (i, &(timestamp, price)) in bars.iter().enumerate() {
+        assert!(rolling.update(timestamp, price));
+        assert_same(rolling.volatility(), batch(&bars[..=i], no_of_periods));
+    }
+}
+
+#[test]
+fn test_matches_batch_after_late_corrections() {
+    let mut rng = StdRng::seed_from_u64(11);
+    let mut bars = random_bars(&mut rng, 120);
+    let no_of_periods = 30;
+    let mut rolling = RollingVolatility::new(no_of_periods);
+
+    for i in 0..bars.len() {
+        rolling.update(bars[i].0, bars[i].1);
+
+        // Revise one of the last few bars: a new price, a dropped price or a filled gap
+        let revised = i.saturating_sub(rng.gen_range(0..5));
+        let price = match bars[revised].1 {
+            Some(_) if rng.gen_bool(0.3) => None,
+            _ => Some(3000.0 * (1.0 + rng.gen_range(-0.02..0.02))),
+        };
+        bars[revised].1 = price;
+        rolling.update(bars[revised].0, price);
+
+        assert_same(rolling.volatility(), batch(&bars[..=i], no_of_periods));
+    }
+}
+
+#[test]
+fn test_handles_out_of_order_and_stale_bars() {
+    let no_of_periods = 4;
+    let mut rolling = RollingVolatility::new(no_of_periods);
+    let mut bars = vec![];
+    for (i, price) in [(3, 103.0), (1, 101.0), (0, 100.0), (2, 102.5), (5, 99.0)] {
+        bars.push((timestamp(i), Some(price)));
+        rolling.update(timestamp(i), Some(price));
+    }
+    bars.sort_by_key(|&(timestamp, _)| timestamp);
+    assert_same(rolling.volatility(), batch(&bars, no_of_periods));
+
+    // Older than the full window, and an unchanged bar
+    assert!(!rolling.update(timestamp(0), Some(50.0)));
+    assert!(!rolling.update(timestamp(5), Some(99.0)));
+    assert_same(rolling.volatility(), batch(&bars, no_of_periods));
+}
+
+#[test]
+fn test_gaps_before_the_first_price() {
+    let mut rolling = RollingVolatility::new(5);
+    rolling.update(timestamp(0), None);
+    // A single bar has no return, two gaps give 0 / 0 like the batch path
+    assert_eq!(rolling.volatility(), None);
+    rolling.update(timestamp(1), None);
+    assert!(rolling.volatility().unwrap().is_nan());
+
+    let bars = [
+        (timestamp(0), None),
+        (timestamp(1), None),
+        (timestamp(2), Some(100.0)),
+        (timestamp(3), Some(104.0)),
+    ];
+    rolling.update(timestamp(2), Some(100.0));
+    rolling.update(timestamp(3), Some(104.0));
+    assert_same(rolling.volatility(), batch(&bars, 5));
+}
+
+#[test]
+fn test_composite_price_follows_the_quorum() {
+    let quorum = QuorumPolicy {
+        min_sources: 2,
+        ..QuorumPolicy::default()
+    };
+    assert_eq!(composite_price(&quorum, &[101.0, 100.0]), Some(100.0));
+    assert_eq!(composite_price(&quorum, &[100.0]), None);
+    assert_eq!(composite_price(&QuorumPolicy::default(), &[]), None);
+}
@@ -0,0 +1,10 @@
+use super::sources::Source;
+use super::PriceRow;
+
+// A bar with the given source prices and no composite price yet
+pub fn row(prices: &[(Source, f64)]) -> PriceRow {
+    PriceRow {
+        prices: prices.iter().copied().collect(),
+        vol: None,
+    }
+}
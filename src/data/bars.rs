@@ -0,0 +1,19 @@
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+// Prices returned by a source adapter, along with the rows it had to drop
+#[derive(Debug, Default)]
+pub struct SourceBars {
+    pub bars: Vec<(DateTime<Utc>, f64)>,
+    pub timestamp_failures: usize, // Rows skipped because their timestamp didn't parse
+    pub truncated: bool, // A paging or history cap stopped the adapter short of the window start
+}
+
+// Adapters get numbers back as JSON numbers or numeric strings, depending on the API
+pub fn value_to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse::<f64>().ok(),
+        _ => None,
+    }
+}
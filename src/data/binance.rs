@@ -0,0 +1,102 @@
+use super::bars::SourceBars;
+use super::candles::{ohlc_bars, CandlePages};
+use super::http::{base_url, Http};
+use super::rounding::{period_duration, periods_before, BarStamp};
+use super::sources::{spot_pair, Source};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::env;
+
+// Binance stamps klines with their open time
+pub const BAR_STAMP: BarStamp = BarStamp::Open;
+
+// Most klines Binance returns per request
+const PAGE_LIMIT: usize = 1000;
+
+// Guard against paging forever if the cursor stops moving
+const MAX_PAGES: usize = 1000;
+
+// Fetch Binance spot klines for the window, priced like Kraken as the average of OHLC
+pub async fn get_binance_data(
+    http: &Http,
+    time_period: &str,
+    no_of_periods: i64,
+) -> Result<SourceBars, anyhow::Error> {
+    let symbol = binance_symbol()?;
+
+    // Binance serves second klines, unlike most venues
+    let interval = match time_period {
+        "second" => "1s",
+        "minute" => "1m",
+        "hour" => "1h",
+        "day" => "1d",
+        _ => return Err(anyhow!("Unsupported time period provided for Binance data")),
+    };
+
+    let end_time = Utc::now();
+    let period = period_duration(time_period);
+    let start_time = periods_before(end_time, time_period, no_of_periods)?;
+
+    let api_url = base_url(Source::Binance, "https://api.binance.com");
+    let pages = CandlePages {
+        source: Source::Binance,
+        page_limit: PAGE_LIMIT,
+        max_pages: MAX_PAGES,
+    };
+    let page_url = |start_time: DateTime<Utc>| {
+        format!(
+            "{}/api/v3/klines?symbol={}&interval={}&startTime={}&endTime={}&limit={}",
+            api_url,
+            symbol,
+            interval,
+            start_time.timestamp_millis(),
+            end_time.timestamp_millis(),
+            PAGE_LIMIT
+        )
+    };
+    pages
+        .fetch(
+            http,
+            start_time,
+            end_time,
+            period,
+            page_url,
+            parse_binance_klines,
+        )
+        .await
+}
+
+// Binance has no USD order books, so a USD quote maps to USDT unless BINANCE_SYMBOL is set
+pub fn binance_symbol() -> Result<String> {
+    if let Ok(symbol) = env::var("BINANCE_SYMBOL") {
+        return Ok(symbol);
+    }
+    let (base, quote) = spot_pair()?;
+    let quote = if quote == "USD" {
+        "USDT".to_string()
+    } else {
+        quote
+    };
+    Ok(format!("{}{}", base, quote))
+}
+
+// Parse a klines response into bars, along with the number of rows in the page.
+// Each kline is [open time, open, high, low, close, volume, close time, ...] with prices as strings.
+pub fn parse_binance_klines(body: &str) -> Result<(SourceBars, usize)> {
+    let rows: Vec<Vec<Value>> = match serde_json::from_str(body) {
+        Ok(rows) => rows,
+        Err(e) => {
+            // Errors come back as {"code": -1121, "msg": "Invalid symbol."}
+            let message = serde_json::from_str::<Value>(body)
+                .ok()
+                .and_then(|value| value.get("msg").and_then(Value::as_str).map(String::from));
+            return Err(match message {
+                Some(message) => anyhow!("Binance API error: {}", message),
+                None => anyhow!("Failed to parse Binance klines: {}", e),
+            });
+        }
+    };
+
+    Ok((ohlc_bars(rows.iter().map(Vec::as_slice)), rows.len()))
+}
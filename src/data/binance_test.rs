@@ -0,0 +1,27 @@
+use super::binance::parse_binance_klines;
+use chrono::{TimeZone, Utc};
+
+#[test]
+fn test_parse_recorded_binance_klines() {
+    let body = include_str!("fixtures/binance_klines.json");
+    let (source_bars, rows) = parse_binance_klines(body).unwrap();
+
+    assert_eq!(rows, 3);
+    assert_eq!(source_bars.timestamp_failures, 0);
+    assert_eq!(source_bars.bars.len(), 3);
+
+    // Stamped with the kline open time and priced at the average of OHLC
+    let (open_time, price) = source_bars.bars[0];
+    assert_eq!(
+        open_time,
+        Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap()
+    );
+    assert!((price - 2500.25).abs() < 1e-9);
+    assert!((source_bars.bars[2].1 - 2502.1).abs() < 1e-9);
+}
+
+#[test]
+fn test_parse_binance_error_response() {
+    let error = parse_binance_klines(r#"{"code":-1121,"msg":"Invalid symbol."}"#).unwrap_err();
+    assert!(error.to_string().contains("Invalid symbol."));
+}
@@ -0,0 +1,106 @@
+use super::bars::SourceBars;
+use super::candles::{ohlc_bars, CandlePages};
+use super::http::{base_url, Http};
+use super::rounding::{period_duration, periods_before, BarStamp};
+use super::sources::{spot_pair, Source};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::env;
+
+// Bitfinex stamps candles with their open time
+pub const BAR_STAMP: BarStamp = BarStamp::Open;
+
+// Most candles Bitfinex returns per request
+const PAGE_LIMIT: usize = 10_000;
+
+// Guard against paging forever if the cursor stops moving
+const MAX_PAGES: usize = 100;
+
+// Fetch Bitfinex public candles for the window, priced like Kraken as the average of OHLC
+pub async fn get_bitfinex_data(
+    http: &Http,
+    time_period: &str,
+    no_of_periods: i64,
+) -> Result<SourceBars, anyhow::Error> {
+    let symbol = bitfinex_symbol()?;
+
+    // Bitfinex has no second candles
+    let timeframe = match time_period {
+        "minute" => "1m",
+        "hour" => "1h",
+        "day" => "1D",
+        _ => {
+            return Err(anyhow!(
+                "Unsupported time period provided for Bitfinex data"
+            ))
+        }
+    };
+
+    let end_time = Utc::now();
+    let period = period_duration(time_period);
+    let start_time = periods_before(end_time, time_period, no_of_periods)?;
+
+    let api_url = base_url(Source::Bitfinex, "https://api-pub.bitfinex.com");
+    let pages = CandlePages {
+        source: Source::Bitfinex,
+        page_limit: PAGE_LIMIT,
+        max_pages: MAX_PAGES,
+    };
+    // sort=1 returns the oldest candles first so the window can be paged forward
+    let page_url = |start_time: DateTime<Utc>| {
+        format!(
+            "{}/v2/candles/trade:{}:{}/hist?start={}&end={}&limit={}&sort=1",
+            api_url,
+            timeframe,
+            symbol,
+            start_time.timestamp_millis(),
+            end_time.timestamp_millis(),
+            PAGE_LIMIT
+        )
+    };
+    pages
+        .fetch(
+            http,
+            start_time,
+            end_time,
+            period,
+            page_url,
+            parse_bitfinex_candles,
+        )
+        .await
+}
+
+// Bitfinex trading pairs are tBASEQUOTE, with a colon once either side is longer than 3
+// characters (e.g. tETHUSD, tTESTBTC:TESTUSD), unless BITFINEX_SYMBOL is set
+pub fn bitfinex_symbol() -> Result<String> {
+    if let Ok(symbol) = env::var("BITFINEX_SYMBOL") {
+        return Ok(symbol);
+    }
+    let (base, quote) = spot_pair()?;
+    if base.len() > 3 || quote.len() > 3 {
+        Ok(format!("t{}:{}", base, quote))
+    } else {
+        Ok(format!("t{}{}", base, quote))
+    }
+}
+
+// Parse a candles response into bars, along with the number of rows in the page.
+// Each candle is [time, open, close, high, low, volume] with time in epoch milliseconds.
+pub fn parse_bitfinex_candles(body: &str) -> Result<(SourceBars, usize)> {
+    let response: Value = serde_json::from_str(body)
+        .map_err(|e| anyhow!("Failed to parse Bitfinex candles: {}", e))?;
+
+    // Errors come back as ["error", 10020, "limit: invalid"]
+    let rows = match response.as_array() {
+        Some(rows) if rows.first().and_then(Value::as_str) == Some("error") => {
+            let message = rows.get(2).and_then(Value::as_str).unwrap_or("unknown");
+            return Err(anyhow!("Bitfinex API error: {}", message));
+        }
+        Some(rows) => rows,
+        None => return Err(anyhow!("Unexpected Bitfinex candles response: {}", body)),
+    };
+
+    let candles = rows.iter().filter_map(Value::as_array).map(Vec::as_slice);
+    Ok((ohlc_bars(candles), rows.len()))
+}
@@ -0,0 +1,26 @@
+use super::bitfinex::parse_bitfinex_candles;
+use chrono::{TimeZone, Utc};
+
+#[test]
+fn test_parse_recorded_bitfinex_candles() {
+    let body = include_str!("fixtures/bitfinex_candles.json");
+    let (source_bars, rows) = parse_bitfinex_candles(body).unwrap();
+
+    assert_eq!(rows, 2);
+    assert_eq!(source_bars.timestamp_failures, 0);
+
+    // [time, open, close, high, low, volume], averaged over the four prices
+    let (open_time, price) = source_bars.bars[0];
+    assert_eq!(
+        open_time,
+        Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap()
+    );
+    assert!((price - 2500.25).abs() < 1e-9);
+    assert!((source_bars.bars[1].1 - 2501.1).abs() < 1e-9);
+}
+
+#[test]
+fn test_parse_bitfinex_error_response() {
+    let error = parse_bitfinex_candles(r#"["error",10020,"limit: invalid"]"#).unwrap_err();
+    assert!(error.to_string().contains("limit: invalid"));
+}
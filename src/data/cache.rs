@@ -0,0 +1,313 @@
+use super::bars::SourceBars;
+use super::binance::binance_symbol;
+use super::bitfinex::bitfinex_symbol;
+use super::chainlink::chainlink_feed;
+use super::coinbase::coinbase_product;
+use super::dune::dune_query_key;
+use super::rounding::{period_duration, periods_before, round_to_period};
+use super::sources::{coinapi_symbol_id, Source};
+use super::store::Store;
+use super::uniswap::UniswapPool;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheMode {
+    Off,
+    On,      // Serve what the cache covers, fetch the rest
+    Offline, // Serve only from the cache, error if it can't cover the window
+}
+
+// Bars one source returned for one symbol and period, over a contiguous range of time.
+// Times are epoch milliseconds.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CacheEntry {
+    pub covered_from: i64, // Start of the earliest window fetched
+    pub covered_to: i64,   // When the latest fetch was made
+    pub bars: Vec<(i64, f64)>,
+}
+
+// On-disk cache of source bars, so repeated runs only fetch the bars that have changed
+pub struct BarCache {
+    mode: CacheMode,
+    dir: PathBuf,
+    ttl: Duration, // A fetch this recent is served as is, even though the last bar moved on
+    store: Option<Arc<Store>>, // Kept in SQLite instead of the JSON files when set
+}
+
+impl BarCache {
+    pub fn new(mode: CacheMode, dir: impl Into<PathBuf>, ttl: Duration) -> Self {
+        BarCache {
+            mode,
+            dir: dir.into(),
+            ttl,
+            store: None,
+        }
+    }
+
+    pub fn mode(&self) -> CacheMode {
+        self.mode
+    }
+
+    pub fn with_store(mut self, store: Arc<Store>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    // Load CACHE_MODE (off, on or offline, `default` when unset), CACHE_DIR and CACHE_TTL_SECS
+    pub fn from_env(default: CacheMode) -> Result<Self> {
+        let mode = match env::var("CACHE_MODE").ok().as_deref() {
+            None => default,
+            Some("off") => CacheMode::Off,
+            Some("on") => CacheMode::On,
+            Some("offline") => CacheMode::Offline,
+            Some(_) => return Err(anyhow!("CACHE_MODE must be off, on or offline")),
+        };
+        let ttl: i64 = env::var("CACHE_TTL_SECS")
+            .unwrap_or("60".to_string())
+            .parse()
+            .map_err(|_| anyhow!("CACHE_TTL_SECS must be a valid integer"))?;
+        let dir = env::var("CACHE_DIR").unwrap_or("cache".to_string());
+        Ok(BarCache::new(mode, dir, Duration::seconds(ttl)))
+    }
+
+    // Bars of `source` over the `no_of_periods` periods up to `window_end`. `fetch` is the
+    // source adapter, asked for the number of periods back from now it should fetch.
+    pub async fn fetch<F, Fut>(
+        &self,
+        source: Source,
+        time_period: &str,
+        no_of_periods: i64,
+        window_end: DateTime<Utc>,
+        fetch: F,
+    ) -> Result<SourceBars>
+    where
+        F: FnOnce(i64) -> Fut,
+        Fut: Future<Output = Result<SourceBars>>,
+    {
+        if self.mode == CacheMode::Off {
+            return fetch(no_of_periods).await;
+        }
+
+        // Open of the earliest bar the window can hold
+        let period = period_duration(time_period);
+        let window_start = periods_before(
+            round_to_period(window_end, time_period),
+            time_period,
+            no_of_periods,
+        )?;
+        let symbol = source_symbol(source, time_period)?;
+        let entry = self.load(source, &symbol, time_period);
+        let location = self.location(source, &symbol, time_period);
+        let covers_start = entry
+            .as_ref()
+            .is_some_and(|entry| entry.covered_from <= window_start.timestamp_millis());
+
+        if self.mode == CacheMode::Offline {
+            // Every completed bar of the window must have been fetched
+            let needed_to = round_to_period(window_end, time_period);
+            return match entry {
+                Some(entry) if covers_start && entry.covered_to >= needed_to.timestamp_millis() => {
+                    debug!("Serving {} from {}", source.label(), location);
+                    Ok(window_bars(&entry, window_start, window_end, 0))
+                }
+                Some(entry) => Err(anyhow!(
+                    "Cached {} bars in {} cover {} to {}, not the window {} to {}. Run with CACHE_MODE=on to fill the cache",
+                    source.label(),
+                    location,
+                    millis_to_time(entry.covered_from),
+                    millis_to_time(entry.covered_to),
+                    window_start,
+                    needed_to
+                )),
+                None => Err(anyhow!(
+                    "No cached {} bars in {}. Run with CACHE_MODE=on to fill the cache",
+                    source.label(),
+                    location
+                )),
+            };
+        }
+
+        let now = Utc::now();
+        let mut entry = match entry {
+            Some(entry)
+                if covers_start
+                    && entry.covered_to + self.ttl.num_milliseconds()
+                        >= window_end.timestamp_millis() =>
+            {
+                debug!("Serving {} from {}", source.label(), location);
+                return Ok(window_bars(&entry, window_start, window_end, 0));
+            }
+            Some(entry) if covers_start => entry,
+            // The window starts before the cached range, fetch all of it
+            _ => CacheEntry {
+                covered_from: window_start.timestamp_millis().min(now.timestamp_millis()),
+                covered_to: window_start.timestamp_millis(),
+                bars: vec![],
+            },
+        };
+
+        // Bars that opened a period before the last fetch's bar are complete, refetch the rest
+        let edge_start = round_to_period(millis_to_time(entry.covered_to), time_period) - period;
+        let periods = ((now - edge_start).num_milliseconds() as f64
+            / period.num_milliseconds() as f64)
+            .ceil() as i64;
+        debug!(
+            "Fetching {} {} periods from {} for the cache",
+            source.label(),
+            periods,
+            edge_start
+        );
+        let fetched = fetch(periods.max(1)).await?;
+
+        // An adapter that hit its paging or history cap leaves a hole between the cached bars
+        // and the fetched ones, so the cache restarts at the fetch. A fetch that merely starts
+        // late, like a thin pair without trades in the edge bar, keeps the cached history.
+        if fetched.truncated {
+            let first_fetched = fetched
+                .bars
+                .iter()
+                .map(|(timestamp, _)| *timestamp)
+                .min()
+                .unwrap_or(now);
+            if first_fetched > edge_start {
+                warn!(
+                    "{} returned bars from {} only, not from {}, dropping the older cached bars",
+                    source.label(),
+                    first_fetched,
+                    edge_start
+                );
+                entry.covered_from = first_fetched.timestamp_millis();
+            }
+        }
+
+        let edge_start = edge_start.timestamp_millis();
+        let covered_from = entry.covered_from;
+        entry
+            .bars
+            .retain(|&(timestamp, _)| timestamp >= covered_from && timestamp < edge_start);
+        entry.bars.extend(
+            fetched
+                .bars
+                .iter()
+                .map(|(timestamp, price)| (timestamp.timestamp_millis(), *price))
+                .filter(|&(timestamp, _)| timestamp >= edge_start),
+        );
+        entry.bars.sort_by_key(|&(timestamp, _)| timestamp);
+        entry.bars.dedup_by_key(|&mut (timestamp, _)| timestamp);
+        entry.covered_to = now.timestamp_millis();
+
+        self.save(source, &symbol, time_period, &entry, edge_start)?;
+        Ok(window_bars(
+            &entry,
+            window_start,
+            window_end,
+            fetched.timestamp_failures,
+        ))
+    }
+
+    fn load(&self, source: Source, symbol: &str, time_period: &str) -> Option<CacheEntry> {
+        if let Some(store) = &self.store {
+            return store
+                .load_bars(source, symbol, time_period)
+                .map_err(|e| warn!("Ignoring unreadable stored {} bars: {}", source.label(), e))
+                .ok()
+                .flatten();
+        }
+        let path = self.path(source, symbol, time_period);
+        match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str::<CacheEntry>(&json)
+                .map_err(|e| warn!("Ignoring unreadable cache file {:?}: {}", path, e))
+                .ok(),
+            Err(_) => None,
+        }
+    }
+
+    fn save(
+        &self,
+        source: Source,
+        symbol: &str,
+        time_period: &str,
+        entry: &CacheEntry,
+        edge_start: i64,
+    ) -> Result<()> {
+        if let Some(store) = &self.store {
+            return store.save_bars(source, symbol, time_period, entry, edge_start);
+        }
+        let path = self.path(source, symbol, time_period);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+
+    // Where the entry is kept, for messages
+    fn location(&self, source: Source, symbol: &str, time_period: &str) -> String {
+        match &self.store {
+            Some(_) => format!("the SQLite store ({} {})", symbol, time_period),
+            None => format!("{:?}", self.path(source, symbol, time_period)),
+        }
+    }
+
+    fn path(&self, source: Source, symbol: &str, time_period: &str) -> PathBuf {
+        let symbol: String = symbol
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        self.dir
+            .join(source.label().to_lowercase())
+            .join(format!("{}_{}.json", symbol, time_period))
+    }
+}
+
+// What each source's bars are of, so a change of pair, pool or query gets its own cache file
+// and its own rows in the stores
+pub fn source_symbol(source: Source, time_period: &str) -> Result<String> {
+    Ok(match source {
+        Source::Polygon => super::polygon2::ASSET_ID.to_string(),
+        Source::Dune => dune_query_key(time_period)?,
+        Source::Kraken => super::kraken::ASSET_ID.to_string(),
+        Source::CoinApi => coinapi_symbol_id(),
+        Source::Binance => binance_symbol()?,
+        Source::Coinbase => coinbase_product()?,
+        Source::Bitfinex => bitfinex_symbol()?,
+        Source::Uniswap => UniswapPool::from_env()?.address,
+        Source::Chainlink => chainlink_feed(),
+        Source::File => "file".to_string(),
+    })
+}
+
+fn window_bars(
+    entry: &CacheEntry,
+    window_start: DateTime<Utc>,
+    window_end: DateTime<Utc>,
+    timestamp_failures: usize,
+) -> SourceBars {
+    // Bars from before a gap in the cached range are kept on disk but not served
+    let (start, end) = (
+        window_start.timestamp_millis().max(entry.covered_from),
+        window_end.timestamp_millis(),
+    );
+    SourceBars {
+        bars: entry
+            .bars
+            .iter()
+            .filter(|&&(timestamp, _)| timestamp >= start && timestamp <= end)
+            .map(|&(timestamp, price)| (millis_to_time(timestamp), price))
+            .collect(),
+        timestamp_failures,
+        truncated: false,
+    }
+}
+
+fn millis_to_time(millis: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(millis).unwrap_or_default()
+}
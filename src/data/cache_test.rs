@@ -0,0 +1,245 @@
+use super::bars::SourceBars;
+use super::cache::{BarCache, CacheMode};
+use super::rounding::round_to_period;
+use super::sources::Source;
+use chrono::{DateTime, Duration, Utc};
+use std::cell::Cell;
+use std::fs;
+use std::path::PathBuf;
+
+fn cache_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "volatility_estimator_cache_{}_{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+// Hourly bars with price 2500 + hour, opened from `start` up to `end`
+fn hourly_bars(start: DateTime<Utc>, end: DateTime<Utc>) -> SourceBars {
+    let mut bars = vec![];
+    let mut bar_open = round_to_period(start, "hour");
+    while bar_open <= end {
+        bars.push((
+            bar_open,
+            2500.0 + bar_open.timestamp() as f64 / 3600.0 % 24.0,
+        ));
+        bar_open += Duration::hours(1);
+    }
+    SourceBars {
+        bars,
+        timestamp_failures: 0,
+        truncated: false,
+    }
+}
+
+#[tokio::test]
+async fn test_serves_repeated_runs_from_cache() {
+    let dir = cache_dir("repeated");
+    let cache = BarCache::new(CacheMode::On, &dir, Duration::seconds(60));
+    let now = Utc::now();
+    let calls = Cell::new(0);
+
+    for _ in 0..2 {
+        let bars = cache
+            .fetch(Source::Kraken, "hour", 24, now, |periods| {
+                calls.set(calls.get() + 1);
+                async move { Ok(hourly_bars(now - Duration::hours(periods), now)) }
+            })
+            .await
+            .unwrap();
+        assert_eq!(bars.bars.len(), 25);
+    }
+    assert_eq!(calls.get(), 1);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_refreshes_only_the_recent_edge() {
+    let dir = cache_dir("edge");
+    let cache = BarCache::new(CacheMode::On, &dir, Duration::zero());
+    let now = Utc::now();
+
+    // Fill the cache as if the last run was 5 hours ago
+    let earlier = now - Duration::hours(5);
+    cache
+        .fetch(Source::Kraken, "hour", 24, earlier, |_| async move {
+            Ok(hourly_bars(earlier - Duration::hours(25), earlier))
+        })
+        .await
+        .unwrap();
+    let path = dir.join("kraken").join("ETHPYUSD_hour.json");
+    let mut entry: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    entry["covered_to"] = earlier.timestamp_millis().into();
+    fs::write(&path, entry.to_string()).unwrap();
+
+    let requested = Cell::new(0);
+    let bars = cache
+        .fetch(Source::Kraken, "hour", 24, now, |periods| {
+            requested.set(periods);
+            async move { Ok(hourly_bars(now - Duration::hours(periods), now)) }
+        })
+        .await
+        .unwrap();
+
+    // Only the bars since the last completed one are fetched again
+    assert!((6..=8).contains(&requested.get()), "{}", requested.get());
+    assert_eq!(bars.bars.len(), 25);
+    assert!(bars
+        .bars
+        .windows(2)
+        .all(|pair| pair[1].0 - pair[0].0 == Duration::hours(1)));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_offline_errors_when_window_not_cached() {
+    let dir = cache_dir("offline");
+    let now = Utc::now();
+    let offline = BarCache::new(CacheMode::Offline, &dir, Duration::seconds(60));
+
+    let error = offline
+        .fetch(Source::Kraken, "hour", 24, now, |_| async {
+            panic!("offline mode fetched from the source")
+        })
+        .await
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("No cached Kraken bars"),
+        "{}",
+        error
+    );
+
+    // Cache a shorter window than the one asked for
+    BarCache::new(CacheMode::On, &dir, Duration::seconds(60))
+        .fetch(Source::Kraken, "hour", 12, now, |periods| async move {
+            Ok(hourly_bars(now - Duration::hours(periods), now))
+        })
+        .await
+        .unwrap();
+    let error = offline
+        .fetch(Source::Kraken, "hour", 24, now, |_| async {
+            panic!("offline mode fetched from the source")
+        })
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("not the window"), "{}", error);
+
+    let bars = offline
+        .fetch(Source::Kraken, "hour", 12, now, |_| async {
+            panic!("offline mode fetched from the source")
+        })
+        .await
+        .unwrap();
+    assert_eq!(bars.bars.len(), 13);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_restarts_coverage_when_fetch_falls_short() {
+    let dir = cache_dir("short");
+    let cache = BarCache::new(CacheMode::On, &dir, Duration::zero());
+    let now = Utc::now();
+
+    // Fill the cache as if the last run was 30 hours ago
+    let earlier = now - Duration::hours(30);
+    cache
+        .fetch(Source::Kraken, "hour", 24, earlier, |periods| async move {
+            Ok(hourly_bars(earlier - Duration::hours(periods), earlier))
+        })
+        .await
+        .unwrap();
+    let path = dir.join("kraken").join("ETHPYUSD_hour.json");
+    let mut entry: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    entry["covered_to"] = earlier.timestamp_millis().into();
+    fs::write(&path, entry.to_string()).unwrap();
+
+    // Asked for 30 odd periods, the source's cap only lets it reach back 10 hours
+    let requested = Cell::new(0);
+    let bars = cache
+        .fetch(Source::Kraken, "hour", 24, now, |periods| {
+            requested.set(periods);
+            async move {
+                Ok(SourceBars {
+                    truncated: true,
+                    ..hourly_bars(now - Duration::hours(10), now)
+                })
+            }
+        })
+        .await
+        .unwrap();
+    assert!(requested.get() > 10, "{}", requested.get());
+    // Only the bars actually fetched, not the stale ones before the hole
+    assert_eq!(bars.bars.len(), 11);
+    assert!(bars.bars[0].0 >= round_to_period(now - Duration::hours(10), "hour"));
+
+    // The hole is not saved as covered
+    let error = BarCache::new(CacheMode::Offline, &dir, Duration::zero())
+        .fetch(Source::Kraken, "hour", 24, now, |_| async {
+            panic!("offline mode fetched from the source")
+        })
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("not the window"), "{}", error);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_keeps_history_when_edge_fetch_is_sparse() {
+    let dir = cache_dir("sparse");
+    let cache = BarCache::new(CacheMode::On, &dir, Duration::zero());
+    let now = Utc::now();
+    let path = dir.join("kraken").join("ETHPYUSD_hour.json");
+    let rewind = |to: DateTime<Utc>| {
+        let mut entry: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        entry["covered_to"] = to.timestamp_millis().into();
+        fs::write(&path, entry.to_string()).unwrap();
+    };
+
+    // Fill the cache as if the last run was 3 hours ago
+    let earlier = now - Duration::hours(3);
+    cache
+        .fetch(Source::Kraken, "hour", 24, earlier, |periods| async move {
+            Ok(hourly_bars(earlier - Duration::hours(periods), earlier))
+        })
+        .await
+        .unwrap();
+    rewind(earlier);
+
+    // No trades at all since the last run
+    let bars = cache
+        .fetch(Source::Kraken, "hour", 24, now, |_| async {
+            Ok(SourceBars::default())
+        })
+        .await
+        .unwrap();
+    let history_start = round_to_period(now, "hour") - Duration::hours(24);
+    assert_eq!(bars.bars[0].0, history_start);
+    assert!(bars.bars.len() >= 20, "{}", bars.bars.len());
+    rewind(earlier);
+
+    // Trades again, but none in the edge bar
+    let bars = cache
+        .fetch(Source::Kraken, "hour", 24, now, |_| async move {
+            Ok(hourly_bars(now - Duration::hours(1), now))
+        })
+        .await
+        .unwrap();
+    assert_eq!(bars.bars[0].0, history_start);
+    assert_eq!(bars.bars.last().unwrap().0, round_to_period(now, "hour"));
+
+    // The window is still covered as far as the offline cache is concerned
+    let offline = BarCache::new(CacheMode::Offline, &dir, Duration::zero())
+        .fetch(Source::Kraken, "hour", 24, now, |_| async {
+            panic!("offline mode fetched from the source")
+        })
+        .await
+        .unwrap();
+    assert_eq!(offline.bars[0].0, history_start);
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,110 @@
+use super::bars::{value_to_f64, SourceBars};
+use super::http::Http;
+use super::sources::Source;
+use super::timestamp::parse_epoch;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, error};
+use serde_json::Value;
+
+// How an exchange pages its candle endpoint forward from a start time
+pub struct CandlePages {
+    pub source: Source,
+    pub page_limit: usize, // Most candles returned per request
+    pub max_pages: usize,  // Guard against paging forever if the cursor stops moving
+}
+
+impl CandlePages {
+    // Fetch candles from `start` to `end`, moving the start past the last candle of each full page.
+    // `page_url` builds the request for a start time and `parse_page` returns the bars and row count.
+    pub async fn fetch(
+        &self,
+        http: &Http,
+        mut start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        period: Duration,
+        page_url: impl Fn(DateTime<Utc>) -> String,
+        parse_page: fn(&str) -> Result<(SourceBars, usize)>,
+    ) -> Result<SourceBars> {
+        let mut source_bars = SourceBars::default();
+
+        for _ in 0..self.max_pages {
+            let url = page_url(start);
+            debug!("{} candles URL: {}", self.source.label(), url);
+
+            let body = get_candle_page(http, self.source, &url).await?;
+            let (page, rows) = parse_page(&body)?;
+            source_bars.timestamp_failures += page.timestamp_failures;
+            let last_open = page.bars.last().map(|&(timestamp, _)| timestamp);
+            source_bars.bars.extend(page.bars);
+
+            // A short page means the window is covered
+            if rows < self.page_limit {
+                return Ok(source_bars);
+            }
+            match last_open {
+                Some(last_open) if last_open + period > start => start = last_open + period,
+                _ => return Ok(source_bars),
+            }
+            if start >= end {
+                return Ok(source_bars);
+            }
+        }
+
+        Err(anyhow!(
+            "{} candles up to {} need more than {} pages, shorten the window",
+            self.source.label(),
+            end,
+            self.max_pages
+        ))
+    }
+}
+
+// Request one page of candles, failing on an error status
+pub async fn get_candle_page(http: &Http, source: Source, url: &str) -> Result<String> {
+    let response = http.send(source, http.get(url)).await?;
+    let (status, body) = (response.status, response.body);
+    if !status.is_success() {
+        error!(
+            "{} request failed with status: {} and body: {}",
+            source.label(),
+            status,
+            body
+        );
+        return Err(anyhow!(
+            "{} request failed with status {}",
+            source.label(),
+            status
+        ));
+    }
+    Ok(body)
+}
+
+// Bars from [open time, p1, p2, p3, p4, ...] candles, priced like Kraken as the average of the
+// four prices. Venues order OHLC differently, which the average doesn't care about.
+pub fn ohlc_bars<'a>(candles: impl IntoIterator<Item = &'a [Value]>) -> SourceBars {
+    let mut source_bars = SourceBars::default();
+    for candle in candles {
+        let prices: Vec<f64> = candle
+            .iter()
+            .skip(1)
+            .take(4)
+            .filter_map(value_to_f64)
+            .collect();
+        if prices.len() < 4 {
+            continue;
+        }
+
+        let Some(open_time) = candle.first().and_then(Value::as_i64).and_then(parse_epoch) else {
+            source_bars.timestamp_failures += 1;
+            continue;
+        };
+        source_bars
+            .bars
+            .push((open_time, prices.iter().sum::<f64>() / 4.0));
+    }
+    source_bars
+        .bars
+        .sort_by_key(|&(timestamp, _): &(DateTime<Utc>, f64)| timestamp);
+    source_bars
+}
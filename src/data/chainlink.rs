@@ -0,0 +1,226 @@
+use super::bars::SourceBars;
+use super::eth_rpc::{abi_words, signed_word_to_f64, EthRpc};
+use super::http::Http;
+use super::rounding::{period_duration, periods_before, round_to_period, BarStamp};
+use super::sources::Source;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use std::env;
+
+// Function selectors, the first 4 bytes of keccak256 of each signature
+const LATEST_ROUND_DATA: &str = "0xfeaf968c"; // latestRoundData()
+const GET_ROUND_DATA: &str = "0x9a6fc8f5"; // getRoundData(uint80)
+const DECIMALS: &str = "0x313ce567"; // decimals()
+const PHASE_AGGREGATORS: &str = "0xc1597304"; // phaseAggregators(uint16)
+const LATEST_ROUND: &str = "0x668a0f02"; // latestRound()
+
+// ETH / USD aggregator proxy on Ethereum
+const DEFAULT_FEED: &str = "0x5f4eC3Df9cbd43714FE2740f5E3616155c5b8419";
+
+// Rounds requested per JSON-RPC batch while walking back through history
+const ROUND_BATCH: u64 = 50;
+
+// Guard against walking a feed's entire history
+const MAX_ROUNDS: usize = 100_000;
+
+// Resampled bars are keyed by their open time
+pub const BAR_STAMP: BarStamp = BarStamp::Open;
+
+// One oracle update
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainlinkRound {
+    pub round_id: u128, // Proxy round id: phase id in the top 16 bits, aggregator round below
+    pub answer: f64,    // Scaled by the feed's decimals
+    pub updated_at: DateTime<Utc>,
+}
+
+// Read the feed's rounds over the window from ETH_RPC_URL and resample them onto the period grid
+pub async fn get_chainlink_data(
+    http: &Http,
+    time_period: &str,
+    no_of_periods: i64,
+) -> Result<SourceBars> {
+    let rpc_url = env::var("ETH_RPC_URL").map_err(|_| anyhow!("ETH_RPC_URL must be set"))?;
+    let end_time = Utc::now();
+    let window_start = periods_before(end_time, time_period, no_of_periods)?;
+
+    read_chainlink_bars(
+        http,
+        &rpc_url,
+        &chainlink_feed(),
+        time_period,
+        window_start,
+        end_time,
+    )
+    .await
+}
+
+// Read the feed's rounds in force from `window_start` to `end_time` as bars
+pub async fn read_chainlink_bars(
+    http: &Http,
+    rpc_url: &str,
+    feed: &str,
+    time_period: &str,
+    window_start: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+) -> Result<SourceBars> {
+    let rpc = EthRpc::new(http, Source::Chainlink, rpc_url);
+
+    let decimals = abi_words(&rpc.eth_call(feed, DECIMALS).await?)?
+        .first()
+        .map(|word| word_to_u128(word) as i32)
+        .ok_or_else(|| anyhow!("Chainlink feed returned no decimals"))?;
+
+    let latest = decode_round_data(&rpc.eth_call(feed, LATEST_ROUND_DATA).await?, decimals)
+        .ok_or_else(|| anyhow!("Chainlink feed returned no latest round"))?;
+    debug!("Latest Chainlink round: {:?}", latest);
+
+    // Walk back until the round in force at the window start is known
+    let mut round_id = latest.round_id;
+    let mut rounds = vec![latest];
+    while rounds
+        .last()
+        .is_some_and(|round| round.updated_at >= window_start)
+        && rounds.len() < MAX_ROUNDS
+    {
+        let mut round_ids = vec![];
+        // Stay within the current phase, the aggregator round counts down to 1
+        while (round_ids.len() as u64) < ROUND_BATCH && (round_id as u64) > 1 {
+            round_id -= 1;
+            round_ids.push(round_id);
+        }
+        if round_ids.is_empty() {
+            match previous_phase_round(&rpc, feed, round_id).await? {
+                Some(previous) => {
+                    round_id = previous;
+                    round_ids.push(round_id);
+                }
+                None => {
+                    warn!("Chainlink feed history starts inside the window");
+                    break;
+                }
+            }
+        }
+
+        let calls: Vec<String> = round_ids
+            .iter()
+            .map(|id| format!("{}{:064x}", GET_ROUND_DATA, id))
+            .collect();
+        for data in rpc.eth_call_batch(feed, &calls).await? {
+            // Reverted or incomplete rounds are skipped
+            if let Some(round) = data.and_then(|data| decode_round_data(&data, decimals)) {
+                rounds.push(round);
+            }
+        }
+    }
+
+    // Stopped by MAX_ROUNDS before reaching the round in force at the window start
+    let truncated = rounds.len() >= MAX_ROUNDS
+        && rounds
+            .last()
+            .is_some_and(|round| round.updated_at >= window_start);
+    if truncated {
+        warn!(
+            "Chainlink rounds capped at {}, window only partially covered",
+            MAX_ROUNDS
+        );
+    }
+
+    rounds.sort_by_key(|round| round.updated_at);
+    Ok(SourceBars {
+        bars: resample_rounds(&rounds, window_start, end_time, time_period),
+        timestamp_failures: 0,
+        truncated,
+    })
+}
+
+pub fn chainlink_feed() -> String {
+    env::var("CHAINLINK_FEED").unwrap_or(DEFAULT_FEED.to_string())
+}
+
+// Last round of the previous phase, None if `round_id` is in the first phase
+async fn previous_phase_round(
+    rpc: &EthRpc<'_>,
+    feed: &str,
+    round_id: u128,
+) -> Result<Option<u128>> {
+    let phase = (round_id >> 64) as u16;
+    if phase <= 1 {
+        return Ok(None);
+    }
+    let previous_phase = phase - 1;
+
+    let data = format!("{}{:064x}", PHASE_AGGREGATORS, previous_phase);
+    let words = abi_words(&rpc.eth_call(feed, &data).await?)?;
+    let aggregator = words
+        .first()
+        .map(|word| format!("0x{}", hex_bytes(&word[12..])))
+        .ok_or_else(|| anyhow!("Chainlink proxy returned no phase aggregator"))?;
+
+    let words = abi_words(&rpc.eth_call(&aggregator, LATEST_ROUND).await?)?;
+    let last_round = words.first().map(word_to_u128).unwrap_or(0);
+    debug!(
+        "Chainlink phase {} aggregator {} ends at round {}",
+        previous_phase, aggregator, last_round
+    );
+    if last_round == 0 {
+        return Ok(None);
+    }
+    Ok(Some(((previous_phase as u128) << 64) | last_round))
+}
+
+// Decode (roundId, answer, startedAt, updatedAt, answeredInRound), None for incomplete rounds
+pub fn decode_round_data(data: &str, decimals: i32) -> Option<ChainlinkRound> {
+    let words = abi_words(data).ok()?;
+    if words.len() < 5 {
+        return None;
+    }
+    let updated_at = word_to_u128(&words[3]);
+    if updated_at == 0 {
+        return None;
+    }
+    Some(ChainlinkRound {
+        round_id: word_to_u128(&words[0]),
+        answer: signed_word_to_f64(&words[1]) / 10f64.powi(decimals),
+        updated_at: DateTime::from_timestamp(updated_at as i64, 0)?,
+    })
+}
+
+// The oracle price is a step function, each bar takes the last answer updated before it closed.
+// Bars before the first known round are left out. `rounds` must be sorted by update time.
+pub fn resample_rounds(
+    rounds: &[ChainlinkRound],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    time_period: &str,
+) -> Vec<(DateTime<Utc>, f64)> {
+    let period = period_duration(time_period);
+    let mut bars = vec![];
+    let mut next_round = 0;
+    let mut answer = None;
+    let mut bar_open = round_to_period(start, time_period);
+    while bar_open <= end {
+        let bar_close = bar_open + period;
+        while next_round < rounds.len() && rounds[next_round].updated_at < bar_close {
+            answer = Some(rounds[next_round].answer);
+            next_round += 1;
+        }
+        if let Some(answer) = answer {
+            bars.push((bar_open, answer));
+        }
+        bar_open = bar_close;
+    }
+    bars
+}
+
+// Low 16 bytes of a word, enough for uint80 round ids and timestamps
+fn word_to_u128(word: &[u8; 32]) -> u128 {
+    word[16..]
+        .iter()
+        .fold(0, |value, &byte| (value << 8) | byte as u128)
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,41 @@
+use super::chainlink::{decode_round_data, resample_rounds, ChainlinkRound};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+// latestRoundData() as returned by the ETH / USD proxy, round 12345 of phase 6
+const RECORDED_ROUND: &str = "0x00000000000000000000000000000000000000000000000600000000000030390000000000000000000000000000000000000000000000000000003a36a6bc400000000000000000000000000000000000000000000000000000000066d43abc0000000000000000000000000000000000000000000000000000000066d43aee0000000000000000000000000000000000000000000000060000000000003039";
+
+fn round(updated_at: DateTime<Utc>, answer: f64) -> ChainlinkRound {
+    ChainlinkRound {
+        round_id: 0,
+        answer,
+        updated_at,
+    }
+}
+
+#[test]
+fn test_decode_round_data() {
+    let round = decode_round_data(RECORDED_ROUND, 8).unwrap();
+    assert_eq!(round.round_id, (6u128 << 64) | 12345);
+    assert!((round.answer - 2500.25).abs() < 1e-9);
+    assert_eq!(
+        round.updated_at,
+        Utc.with_ymd_and_hms(2024, 9, 1, 9, 59, 10).unwrap()
+    );
+}
+
+#[test]
+fn test_resample_rounds_onto_grid() {
+    let start = Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap();
+    let rounds = [
+        round(start - Duration::minutes(30), 2500.0), // In force when the window opens
+        round(start + Duration::minutes(70), 2510.0),
+        round(start + Duration::minutes(80), 2520.0), // Supersedes the previous within the bar
+    ];
+
+    let bars = resample_rounds(&rounds, start, start + Duration::hours(3), "hour");
+    let prices: Vec<f64> = bars.iter().map(|&(_, price)| price).collect();
+
+    assert_eq!(bars[0].0, start);
+    // Each bar carries the last answer updated before it closed
+    assert_eq!(prices, vec![2500.0, 2520.0, 2520.0, 2520.0]);
+}
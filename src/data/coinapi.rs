@@ -1,16 +1,22 @@
+use super::bars::SourceBars;
+use super::http::{base_url, Http};
+use super::rounding::{periods_before, BarStamp};
+use super::sources::{coinapi_symbol_id, Source};
+use super::timestamp::parse_timestamp;
 use anyhow::Result;
-use chrono::NaiveDateTime;
-use log::{debug, error};
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{debug, error, warn};
+use reqwest::header::HeaderMap;
 use reqwest::StatusCode;
 use serde::Deserialize;
 use std::env;
-use tokio::time::{timeout, Duration};
 
 // Define a struct for CoinAPI response
 #[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 struct CoinApiRecord {
     time_period_start: String,
+    time_period_end: String,
     price_open: f64,
     price_high: f64,
     price_low: f64,
@@ -19,10 +25,33 @@ struct CoinApiRecord {
     trades_count: u64,
 }
 
+// CoinAPI bars are stamped with `time_period_start`
+pub const BAR_STAMP: BarStamp = BarStamp::Open;
+
+// Bars per request, CoinAPI charges one request unit per 100 bars asked for
+const PAGE_LIMIT: i64 = 1000;
+
 pub async fn get_coin_api_data(
+    http: &Http,
+    time_period: &str,
+    no_of_periods: i64,
+) -> Result<SourceBars, anyhow::Error> {
+    // Load the CoinAPI key from .env
+    let api_key = env::var("COINAPI_API_KEY").expect("COINAPI_API_KEY must be set in .env file");
+
+    fetch_coin_api_data(http, &api_key, time_period, no_of_periods, Utc::now()).await
+}
+
+// Fetch the OHLCV bars over the window ending at `time_end` with the given API key
+pub async fn fetch_coin_api_data(
+    http: &Http,
+    api_key: &str,
     time_period: &str,
-) -> Result<Vec<(NaiveDateTime, f64)>, anyhow::Error> {
-    let asset_id = "BITFINEX_SPOT_ETH_USD";
+    no_of_periods: i64,
+    time_end: DateTime<Utc>,
+) -> Result<SourceBars> {
+    // Any exchange CoinAPI covers, Bitstamp by default
+    let asset_id = coinapi_symbol_id();
 
     // Convert timespan to period
     let period = match time_period {
@@ -36,94 +65,156 @@ pub async fn get_coin_api_data(
         }
     };
 
-    // Load the CoinAPI key from .env
-    let api_key = env::var("COINAPI_API_KEY").expect("COINAPI_API_KEY must be set in .env file");
-
-    // Construct the actual URL
-    let url = format!(
-        "https://rest.coinapi.io/v1/ohlcv/{}/history?period_id={}",
-        asset_id, period
-    );
+    // Request exactly the window being estimated
+    let mut time_start = periods_before(time_end, time_period, no_of_periods)?;
+    let mut remaining_periods = no_of_periods;
+
+    // Make the request to CoinAPI for the chosen exchange
+    let api_url = base_url(Source::CoinApi, "https://rest.coinapi.io");
+
+    let mut records: Vec<CoinApiRecord> = vec![];
+    while remaining_periods > 0 {
+        // Only ask for the bars still missing, a bigger limit costs more request units
+        let limit = remaining_periods.min(PAGE_LIMIT);
+
+        // Construct the actual URL
+        let url = format!(
+            "{}/v1/ohlcv/{}/history?period_id={}&time_start={}&time_end={}&limit={}",
+            api_url,
+            asset_id,
+            period,
+            time_start.to_rfc3339_opts(SecondsFormat::Secs, true),
+            time_end.to_rfc3339_opts(SecondsFormat::Secs, true),
+            limit
+        );
+
+        debug!("Constructed CoinAPI URL: {}", url);
+
+        let (page, quota_left) = get_coin_api_page(http, &url, api_key).await?;
+        let page_len = page.len() as i64;
+
+        // Continue from the end of the last bar returned
+        let next_start = page
+            .last()
+            .and_then(|record| parse_timestamp(&record.time_period_end).ok());
+        records.extend(page);
+
+        // A short page means the window is covered
+        if page_len < limit {
+            break;
+        }
+        match next_start {
+            Some(next_start) if next_start > time_start && next_start < time_end => {
+                time_start = next_start
+            }
+            _ => break,
+        }
+        remaining_periods -= page_len;
 
-    debug!("Constructed CoinAPI URL: {}", url);
+        // Stop before a call the remaining quota can't pay for
+        let next_cost = (remaining_periods.min(PAGE_LIMIT) as f64 / 100.0).ceil();
+        if quota_left.is_some_and(|left| left < next_cost) {
+            warn!("CoinAPI request quota exhausted, window only partially covered");
+            break;
+        }
+    }
 
-    // Make the request to CoinAPI for BitFinex
-    let client = reqwest::Client::new();
+    // Convert the deserialized records into the expected Vec<(DateTime<Utc>, f64)>
+    let received = records.len();
+    let exchange_rates: Vec<(DateTime<Utc>, f64)> = records
+        .into_iter()
+        .filter_map(|record| {
+            // Convert `time_period_start` to `DateTime<Utc>`
+            let datetime = parse_timestamp(&record.time_period_start).ok()?;
+
+            // Calculate the average of open, high, low, and close prices
+            let average_price =
+                (record.price_open + record.price_high + record.price_low + record.price_close)
+                    / 4.0;
+
+            Some((datetime, average_price))
+        })
+        .collect();
+
+    Ok(SourceBars {
+        timestamp_failures: received - exchange_rates.len(),
+        bars: exchange_rates,
+        truncated: false,
+    })
+}
 
-    let timeout_duration = Duration::from_secs(10);
+// Fetch one page of OHLCV records, along with the request units left in the quota
+async fn get_coin_api_page(
+    http: &Http,
+    url: &str,
+    api_key: &str,
+) -> Result<(Vec<CoinApiRecord>, Option<f64>)> {
     debug!("Sending request to CoinAPI...");
 
-    let response = timeout(timeout_duration, async {
-        client
-            .get(&url)
-            .header("X-CoinAPI-Key", &api_key)
-            .header("ACCEPT", "application/json")
-            .send()
-            .await
-    })
-    .await;
-
-    debug!("Received response from CoinAPI");
+    let request = http
+        .get(url)
+        .header("X-CoinAPI-Key", api_key)
+        .header("ACCEPT", "application/json");
+    let response = http.send(Source::CoinApi, request).await;
 
     match response {
-        Ok(Ok(response)) => {
+        Ok(response) => {
             // Debug
             debug!("Received response from CoinAPI");
 
+            let quota_left = log_rate_limit(&response.headers);
+
             // Check if the response is successful
-            if response.status() != StatusCode::OK {
-                let status = response.status();
-                let error_text = response
-                    .text()
-                    .await
-                    .unwrap_or_else(|_| "Unknown error".to_string());
+            if response.status != StatusCode::OK {
                 error!(
                     "Request failed with status: {} and body: {}",
-                    status, error_text
+                    response.status, response.body
                 );
                 return Err(anyhow::anyhow!(
                     "CoinAPI request failed with status {}",
-                    status
+                    response.status
                 ));
             }
 
             // Deserialize the response directly into a Vec<CoinApiRecord>
-            let records: Vec<CoinApiRecord> = response.json().await?;
+            let records: Vec<CoinApiRecord> = response.json()?;
             debug!("Parsed CoinAPI response successfully");
 
-            // Convert the deserialized records into the expected Vec<(NaiveDateTime, f64)>
-            let exchange_rates: Vec<(NaiveDateTime, f64)> = records
-                .into_iter()
-                .filter_map(|record| {
-                    // Convert `time_period_start` to `NaiveDateTime`
-                    let datetime = NaiveDateTime::parse_from_str(
-                        &record.time_period_start,
-                        "%Y-%m-%dT%H:%M:%S%.fZ",
-                    )
-                    .ok()?;
-
-                    // Calculate the average of open, high, low, and close prices
-                    let average_price = (record.price_open
-                        + record.price_high
-                        + record.price_low
-                        + record.price_close)
-                        / 4.0;
-
-                    Some((datetime, average_price))
-                })
-                .collect();
-
-            Ok(exchange_rates)
+            Ok((records, quota_left))
         }
-        Ok(Err(e)) => {
+        Err(e) => {
             error!("Error sending request to CoinAPI: {}", e);
             Err(anyhow::anyhow!("Error sending request to CoinAPI: {}", e))
         }
-        Err(_) => {
-            error!("Timeout occurred while trying to fetch data from CoinAPI");
-            Err(anyhow::anyhow!(
-                "Timeout occurred while trying to fetch data from CoinAPI"
-            ))
+    }
+}
+
+// Log CoinAPI's quota headers and return the request units remaining, if reported
+fn log_rate_limit(headers: &HeaderMap) -> Option<f64> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+
+    let remaining = header("x-ratelimit-remaining");
+    debug!(
+        "CoinAPI quota: limit {:?}, remaining {:?}, request cost {:?}, resets {:?}",
+        header("x-ratelimit-limit"),
+        remaining,
+        header("x-ratelimit-request-cost"),
+        header("x-ratelimit-reset")
+    );
+
+    let remaining = remaining.and_then(|value| value.parse::<f64>().ok());
+    if let Some(remaining) = remaining {
+        if remaining < 10.0 {
+            warn!(
+                "CoinAPI quota nearly exhausted: {} requests left",
+                remaining
+            );
         }
     }
+    remaining
 }
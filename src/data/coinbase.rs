@@ -0,0 +1,106 @@
+use super::bars::SourceBars;
+use super::candles::{get_candle_page, ohlc_bars};
+use super::http::{base_url, Http};
+use super::rounding::{period_duration, periods_before, BarStamp};
+use super::sources::{spot_pair, Source};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::debug;
+use serde_json::Value;
+use std::env;
+
+// Coinbase stamps candles with their open time
+pub const BAR_STAMP: BarStamp = BarStamp::Open;
+
+// Most candles Coinbase returns per request
+const PAGE_LIMIT: i64 = 300;
+
+// Fetch Coinbase Exchange candles for the window, priced like Kraken as the average of OHLC
+pub async fn get_coinbase_data(
+    http: &Http,
+    time_period: &str,
+    no_of_periods: i64,
+) -> Result<SourceBars, anyhow::Error> {
+    let product = coinbase_product()?;
+
+    // Candle size in seconds, Coinbase has no second candles
+    let granularity = match time_period {
+        "minute" => 60,
+        "hour" => 3600,
+        "day" => 86400,
+        _ => {
+            return Err(anyhow!(
+                "Unsupported time period provided for Coinbase data"
+            ))
+        }
+    };
+
+    let end_time = Utc::now();
+    let period = period_duration(time_period);
+    let mut start_time = periods_before(end_time, time_period, no_of_periods)?;
+
+    let api_url = base_url(Source::Coinbase, "https://api.exchange.coinbase.com");
+    let mut source_bars = SourceBars::default();
+
+    // Walk the window forward in chunks of at most 300 candles
+    while start_time < end_time {
+        let chunk_end = (start_time + period * PAGE_LIMIT as i32).min(end_time);
+        let url = format!(
+            "{}/products/{}/candles?granularity={}&start={}&end={}",
+            api_url,
+            product,
+            granularity,
+            start_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            chunk_end.to_rfc3339_opts(SecondsFormat::Secs, true)
+        );
+        debug!("Coinbase candles URL: {}", url);
+
+        let body = get_candle_page(http, Source::Coinbase, &url).await?;
+        let page = parse_coinbase_candles(&body)?;
+        source_bars.timestamp_failures += page.timestamp_failures;
+        source_bars.bars.extend(page.bars);
+
+        start_time = chunk_end;
+    }
+
+    source_bars
+        .bars
+        .sort_by_key(|&(timestamp, _): &(DateTime<Utc>, f64)| timestamp);
+    // Chunk bounds are inclusive, so a candle on a boundary can come back twice
+    source_bars
+        .bars
+        .dedup_by_key(|&mut (timestamp, _)| timestamp);
+    Ok(source_bars)
+}
+
+// Coinbase products are BASE-QUOTE, e.g. ETH-USD, unless COINBASE_PRODUCT is set
+pub fn coinbase_product() -> Result<String> {
+    if let Ok(product) = env::var("COINBASE_PRODUCT") {
+        return Ok(product);
+    }
+    let (base, quote) = spot_pair()?;
+    Ok(format!("{}-{}", base, quote))
+}
+
+// Parse a candles response into bars, oldest first.
+// Each candle is [time, low, high, open, close, volume] with time in epoch seconds, newest first.
+pub fn parse_coinbase_candles(body: &str) -> Result<SourceBars> {
+    let rows: Vec<Vec<Value>> = match serde_json::from_str(body) {
+        Ok(rows) => rows,
+        Err(e) => {
+            // Errors come back as {"message": "NotFound"}
+            let message = serde_json::from_str::<Value>(body).ok().and_then(|value| {
+                value
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .map(String::from)
+            });
+            return Err(match message {
+                Some(message) => anyhow!("Coinbase API error: {}", message),
+                None => anyhow!("Failed to parse Coinbase candles: {}", e),
+            });
+        }
+    };
+
+    Ok(ohlc_bars(rows.iter().map(Vec::as_slice)))
+}
@@ -0,0 +1,27 @@
+use super::coinbase::parse_coinbase_candles;
+use chrono::{TimeZone, Utc};
+
+#[test]
+fn test_parse_recorded_coinbase_candles() {
+    let body = include_str!("fixtures/coinbase_candles.json");
+    let source_bars = parse_coinbase_candles(body).unwrap();
+
+    assert_eq!(source_bars.timestamp_failures, 0);
+    assert_eq!(source_bars.bars.len(), 2);
+
+    // Candles arrive newest first and are returned oldest first
+    let (open_time, price) = source_bars.bars[0];
+    assert_eq!(
+        open_time,
+        Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap()
+    );
+    // [time, low, high, open, close, volume], averaged over the four prices
+    assert!((price - 2498.25).abs() < 1e-9);
+    assert!((source_bars.bars[1].1 - 2500.75).abs() < 1e-9);
+}
+
+#[test]
+fn test_parse_coinbase_error_response() {
+    let error = parse_coinbase_candles(r#"{"message":"NotFound"}"#).unwrap_err();
+    assert!(error.to_string().contains("NotFound"));
+}
@@ -1,26 +1,90 @@
+use super::bars::{value_to_f64, SourceBars};
+use super::dune_sql::dune_token_pair;
+use super::http::{base_url, Http};
+use super::rounding::BarStamp;
+use super::sources::Source;
+use super::timestamp::parse_timestamp;
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
 use dotenv::dotenv;
-use log::debug;
-use reqwest::Client;
+use log::{debug, warn};
 use serde::{Deserialize, Deserializer};
-use serde_json;
+use serde_json::{json, Map, Value};
 use std::env;
+use tokio::time::{sleep, Duration as StdDuration};
 
 #[derive(Deserialize, Debug)]
-struct DuneAnalyticsResponse {
+pub struct DuneAnalyticsResponse {
+    execution_ended_at: Option<String>, // When the results were computed
     result: DuneResult,
+    next_offset: Option<u64>, // Present while more pages of rows remain
+    next_uri: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
-struct DuneResult {
-    rows: Vec<CryptoPriceDataRaw>,
+struct DuneExecuteResponse {
+    execution_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DuneStatusResponse {
+    state: String,
 }
 
 #[derive(Deserialize, Debug)]
-struct CryptoPriceDataRaw {
-    tspan: String,
-    #[serde(deserialize_with = "deserialize_price")]
-    average_eth_price: f64,
+struct DuneResult {
+    rows: Vec<Map<String, Value>>, // Read through `DuneColumns`, so any query's columns work
+}
+
+// Result columns holding each field, so community queries can be used without editing their SQL
+#[derive(Debug, Clone)]
+pub struct DuneColumns {
+    pub timestamp: String,
+    pub price: String,
+    pub volume: Option<String>, // Rows with no volume traded are skipped when set
+}
+
+impl DuneColumns {
+    // Load DUNE_TIMESTAMP_COLUMN, DUNE_PRICE_COLUMN and DUNE_VOLUME_COLUMN,
+    // defaulting to the columns of the example queries in dune.sql
+    pub fn from_env() -> Self {
+        DuneColumns::from_lookup(|name| env::var(name).ok())
+    }
+
+    // As from_env, reading the variables through `lookup`
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        DuneColumns {
+            timestamp: lookup("DUNE_TIMESTAMP_COLUMN").unwrap_or("tspan".to_string()),
+            price: lookup("DUNE_PRICE_COLUMN").unwrap_or("average_eth_price".to_string()),
+            volume: lookup("DUNE_VOLUME_COLUMN"),
+        }
+    }
+}
+
+// How saved query results that are too old get handled
+#[derive(Debug, Clone)]
+pub struct StaleResults {
+    pub max_age: Duration,
+    pub rerun: bool, // Re-run the query, otherwise only warn
+}
+
+impl StaleResults {
+    // Load DUNE_MAX_RESULT_AGE_SECS and DUNE_RERUN_STALE. The age doesn't follow the period,
+    // since Dune queries take minutes to run and would otherwise be re-run on every call.
+    pub fn from_env() -> Result<Self> {
+        let max_age = match env::var("DUNE_MAX_RESULT_AGE_SECS") {
+            Ok(secs) => Duration::seconds(
+                secs.parse()
+                    .map_err(|_| anyhow!("DUNE_MAX_RESULT_AGE_SECS must be a valid integer"))?,
+            ),
+            Err(_) => DEFAULT_MAX_RESULT_AGE,
+        };
+        let rerun = env::var("DUNE_RERUN_STALE")
+            .unwrap_or("true".to_string())
+            .parse::<bool>()
+            .map_err(|_| anyhow!("DUNE_RERUN_STALE must be true or false"))?;
+        Ok(StaleResults { max_age, rerun })
+    }
 }
 
 // Enum to handle both string and float values
@@ -47,14 +111,81 @@ where
     }
 }
 
+// The Dune queries `date_trunc` block time, which stamps each bar with its open time
+pub const BAR_STAMP: BarStamp = BarStamp::Open;
+
+const DUNE_API_URL: &str = "https://api.dune.com/api/v1";
+
+// Polling backoff for query executions, doubling up to the maximum
+const POLL_INITIAL_DELAY: StdDuration = StdDuration::from_secs(1);
+const POLL_MAX_DELAY: StdDuration = StdDuration::from_secs(30);
+const POLL_TIMEOUT: StdDuration = StdDuration::from_secs(600);
+
+// Rows per results page
+const PAGE_SIZE: i64 = 1000;
+
+// Saved query results older than this are re-run
+const DEFAULT_MAX_RESULT_AGE: Duration = Duration::hours(1);
+
+// The query and token pair the bars come from, as a key for the bar cache
+pub fn dune_query_key(timespan: &str) -> Result<String> {
+    match env::var("DUNE_QUERY_ID") {
+        Ok(query_id) => {
+            let (token_sold, token_bought) = dune_token_pair()?;
+            Ok(format!("{}_{}_{}", query_id, token_sold, token_bought))
+        }
+        Err(_) => {
+            let suffix = match timespan {
+                "second" => "SEC",
+                "minute" => "MIN",
+                "hour" => "HOUR",
+                _ => "DAY",
+            };
+            Ok(env::var(format!("DUNE_QUERY_ID_{}", suffix)).unwrap_or("saved".to_string()))
+        }
+    }
+}
+
 // Function to fetch price data from Dune Analytics
 pub async fn fetch_dune_data(
+    http: &Http,
     timespan: &str,
     no_of_periods: i64,
-) -> Result<Vec<(String, f64)>, anyhow::Error> {
+) -> Result<SourceBars, anyhow::Error> {
     dotenv().ok(); // Load environment variables
 
-    // Load the appropriate query ID based on the timespan
+    if !["second", "minute", "hour", "day"].contains(&timespan) {
+        return Err(anyhow!("Unsupported timespan provided for Dune data")); // Return an error for unsupported timespans
+    }
+
+    let api_key = env::var("DUNE_API_KEY").expect("DUNE_API_KEY must be set in .env file");
+
+    debug!("Api key: {}", api_key);
+
+    let columns = DuneColumns::from_env();
+
+    // A single parameterized query is executed fresh on every run
+    if let Ok(query_id) = env::var("DUNE_QUERY_ID") {
+        // Default token pair: USDC sold for WETH on DUNE_CHAIN
+        let (token_sold, token_bought) = dune_token_pair()?;
+        let parameters = json!({
+            "period": timespan,
+            "lookback": no_of_periods,
+            "token_sold_address": token_sold,
+            "token_bought_address": token_bought,
+        });
+        let execution_id = execute_query(http, &api_key, &query_id, Some(parameters)).await?;
+        wait_for_execution(http, &api_key, &execution_id).await?;
+        let url = format!(
+            "{}/execution/{}/results",
+            base_url(Source::Dune, DUNE_API_URL),
+            execution_id
+        );
+        let response_data = get_results(http, &api_key, &url, no_of_periods).await?;
+        return parse_dune_rows(response_data, &columns);
+    }
+
+    // Otherwise read the last results of the saved query for this timespan
     let query_id = match timespan {
         "second" => {
             env::var("DUNE_QUERY_ID_SEC").expect("DUNE_QUERY_ID_SEC must be set in .env file")
@@ -65,75 +196,205 @@ pub async fn fetch_dune_data(
         "hour" => {
             env::var("DUNE_QUERY_ID_HOUR").expect("DUNE_QUERY_ID_HOUR must be set in .env file")
         }
-        "day" => env::var("DUNE_QUERY_ID_DAY").expect("DUNE_QUERY_ID_DAY must be set in .env file"),
-        _ => return Err(anyhow!("Unsupported timespan provided for Dune data")), // Return an error for unsupported timespans
+        _ => env::var("DUNE_QUERY_ID_DAY").expect("DUNE_QUERY_ID_DAY must be set in .env file"),
     };
 
-    let api_key = env::var("DUNE_API_KEY").expect("DUNE_API_KEY must be set in .env file");
-
-    debug!("Api key: {}", api_key);
+    fetch_saved_query(
+        http,
+        &api_key,
+        &query_id,
+        no_of_periods,
+        &columns,
+        &StaleResults::from_env()?,
+    )
+    .await
+}
 
+// Read the last results of a saved query, re-running it first when they are stale
+pub async fn fetch_saved_query(
+    http: &Http,
+    api_key: &str,
+    query_id: &str,
+    no_of_periods: i64,
+    columns: &DuneColumns,
+    stale: &StaleResults,
+) -> Result<SourceBars> {
     // Dune Analytics API URL with the provided query ID
     let url = format!(
-        "https://api.dune.com/api/v1/query/{}/results?limit={}",
-        query_id, no_of_periods
+        "{}/query/{}/results",
+        base_url(Source::Dune, DUNE_API_URL),
+        query_id
     );
 
     debug!("Dune Url: {}", url);
 
-    // Make the API call
-    let client = Client::new();
-    let raw_response = client
-        .get(&url)
+    let mut response_data = get_results(http, api_key, &url, no_of_periods).await?;
+
+    let ended_at = response_data
+        .execution_ended_at
+        .as_deref()
+        .and_then(|ended_at| parse_timestamp(ended_at).ok());
+
+    if is_stale(ended_at, Utc::now(), stale.max_age) {
+        if stale.rerun {
+            warn!(
+                "Dune results for query {} are stale (ended at {:?}), re-running",
+                query_id, ended_at
+            );
+            let execution_id = execute_query(http, api_key, query_id, None).await?;
+            wait_for_execution(http, api_key, &execution_id).await?;
+            response_data = get_results(http, api_key, &url, no_of_periods).await?;
+        } else {
+            warn!(
+                "Dune results for query {} are stale (ended at {:?})",
+                query_id, ended_at
+            );
+        }
+    }
+
+    parse_dune_rows(response_data, columns)
+}
+
+// Cached results older than the allowed age are missing the latest bars
+pub fn is_stale(ended_at: Option<DateTime<Utc>>, now: DateTime<Utc>, max_age: Duration) -> bool {
+    ended_at.is_none_or(|ended_at| now - ended_at > max_age)
+}
+
+// Start an execution of a saved query, returning its execution id
+async fn execute_query(
+    http: &Http,
+    api_key: &str,
+    query_id: &str,
+    parameters: Option<Value>,
+) -> Result<String> {
+    let url = format!(
+        "{}/query/{}/execute",
+        base_url(Source::Dune, DUNE_API_URL),
+        query_id
+    );
+    let body = match parameters {
+        Some(parameters) => json!({ "query_parameters": parameters }),
+        None => json!({}),
+    };
+    debug!("Executing Dune query {} with {}", query_id, body);
+
+    let request = http
+        .post(&url)
         .header("X-Dune-API-Key", api_key)
-        .send()
-        .await;
+        .json(&body);
+    // Each execution spends credits, so a lost response isn't retried into a second one
+    let response = http.send_once(Source::Dune, request).await?;
+    if !response.status.is_success() {
+        return Err(anyhow!(
+            "Dune query execution failed to start: {} - {}",
+            response.status,
+            response.body
+        ));
+    }
+
+    let execute_response: DuneExecuteResponse = response.json()?;
+    Ok(execute_response.execution_id)
+}
+
+// Poll an execution's status with exponential backoff until it completes
+async fn wait_for_execution(http: &Http, api_key: &str, execution_id: &str) -> Result<()> {
+    let url = format!(
+        "{}/execution/{}/status",
+        base_url(Source::Dune, DUNE_API_URL),
+        execution_id
+    );
+    let mut delay = POLL_INITIAL_DELAY;
+    let mut waited = StdDuration::ZERO;
+
+    loop {
+        let request = http.get(&url).header("X-Dune-API-Key", api_key);
+        let status: DuneStatusResponse = http.send(Source::Dune, request).await?.json()?;
+        debug!("Dune execution {} state {}", execution_id, status.state);
+
+        match status.state.as_str() {
+            "QUERY_STATE_COMPLETED" => return Ok(()),
+            "QUERY_STATE_FAILED" | "QUERY_STATE_CANCELLED" | "QUERY_STATE_EXPIRED" => {
+                return Err(anyhow!(
+                    "Dune execution {} ended with {}",
+                    execution_id,
+                    status.state
+                ))
+            }
+            _ => {} // Pending or executing
+        }
+
+        if waited >= POLL_TIMEOUT {
+            return Err(anyhow!(
+                "Dune execution {} did not complete within {:?}",
+                execution_id,
+                POLL_TIMEOUT
+            ));
+        }
+        sleep(delay).await;
+        waited += delay;
+        delay = (delay * 2).min(POLL_MAX_DELAY);
+    }
+}
+
+// Fetch up to `no_of_rows` result rows, following `next_offset` / `next_uri` across pages
+async fn get_results(
+    http: &Http,
+    api_key: &str,
+    results_url: &str,
+    no_of_rows: i64,
+) -> Result<DuneAnalyticsResponse> {
+    let mut url = format!("{}?limit={}", results_url, no_of_rows.clamp(1, PAGE_SIZE));
+    let mut response_data = get_results_page(http, api_key, &url).await?;
+
+    while (response_data.result.rows.len() as i64) < no_of_rows {
+        let remaining = no_of_rows - response_data.result.rows.len() as i64;
+        url = match (response_data.next_offset, &response_data.next_uri) {
+            (Some(next_offset), _) => format!(
+                "{}?limit={}&offset={}",
+                results_url,
+                remaining.min(PAGE_SIZE),
+                next_offset
+            ),
+            (None, Some(next_uri)) => next_uri.clone(),
+            (None, None) => break, // No more rows
+        };
+        debug!("Dune next page: {}", url);
+
+        let page = get_results_page(http, api_key, &url).await?;
+        if page.result.rows.is_empty() {
+            break;
+        }
+        response_data.result.rows.extend(page.result.rows);
+        response_data.next_offset = page.next_offset;
+        response_data.next_uri = page.next_uri;
+    }
+
+    Ok(response_data)
+}
+
+// Fetch and deserialize a page of results
+async fn get_results_page(http: &Http, api_key: &str, url: &str) -> Result<DuneAnalyticsResponse> {
+    // Make the API call
+    let request = http.get(url).header("X-Dune-API-Key", api_key);
+    let raw_response = http.send(Source::Dune, request).await;
 
     // Check if the request succeeded
     match raw_response {
         Ok(response) => {
-            let response_text = response.text().await?;
+            if !response.status.is_success() {
+                return Err(anyhow!(
+                    "Dune results request failed: {} - {}",
+                    response.status,
+                    response.body
+                ));
+            }
+            let response_text = response.body;
             // Deserialize response into expected struct
             let response_data: DuneAnalyticsResponse = serde_json::from_str(&response_text)
                 .map_err(|e| anyhow!("Failed to deserialize response: {}", e))?;
 
             debug!("Response: {:?}", response_data);
-
-            // Data Cleansing
-            // Extract prices and filter out non-finite values (Infinity, NaN, etc.)
-            let prices: Vec<f64> = response_data
-                .result
-                .rows
-                .iter()
-                .map(|row| row.average_eth_price)
-                .filter(|&price| price.is_finite()) // Filter out Infinity and NaN values
-                .collect();
-
-            // Calculate the average of finite prices
-            if prices.is_empty() {
-                return Err(anyhow!("No valid prices found"));
-            }
-            let avg: f64 = prices.iter().sum::<f64>() / prices.len() as f64;
-            debug!("Average price {}", avg);
-
-            // More Data Cleansing and preparation for vol calcs
-            // Filter ETH prices that are greater than $8000
-            let filtered_prices: Vec<(String, f64)> = response_data
-                .result
-                .rows
-                .into_iter()
-                .filter_map(|row| {
-                    if row.average_eth_price <= 8000.0 {
-                        Some((row.tspan, row.average_eth_price))
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-
-            debug!("Filtered prices: {:?}", filtered_prices);
-
-            Ok(filtered_prices)
+            Ok(response_data)
         }
         Err(e) => {
             // If the request failed, print the error and return it
@@ -142,3 +403,99 @@ pub async fn fetch_dune_data(
         }
     }
 }
+
+// Cleanse the result rows into bars, reading fields from the mapped columns
+pub fn parse_dune_rows(
+    response_data: DuneAnalyticsResponse,
+    columns: &DuneColumns,
+) -> Result<SourceBars> {
+    let rows = response_data.result.rows;
+    if let Some(first_row) = rows.first() {
+        for column in [
+            Some(&columns.timestamp),
+            Some(&columns.price),
+            columns.volume.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if !first_row.contains_key(column) {
+                return Err(anyhow!(
+                    "Dune result has no column '{}', found: {:?}",
+                    column,
+                    first_row.keys().collect::<Vec<_>>()
+                ));
+            }
+        }
+    }
+
+    // Data Cleansing
+    // Read prices through `deserialize_price`, so "Infinity" and friends become non-finite
+    let mut priced_rows: Vec<(&Map<String, Value>, f64)> = vec![];
+    for row in &rows {
+        let price = row
+            .get(&columns.price)
+            .filter(|value| !value.is_null())
+            .cloned()
+            .map(deserialize_price)
+            .transpose()?;
+        let Some(price) = price else {
+            continue;
+        };
+
+        // No volume traded means there was no swap to price the bar
+        if let Some(volume_column) = &columns.volume {
+            let volume = row.get(volume_column).and_then(value_to_f64).unwrap_or(0.0);
+            if volume <= 0.0 {
+                debug!("Skipping Dune row without volume: {:?}", row);
+                continue;
+            }
+        }
+        priced_rows.push((row, price));
+    }
+
+    // Extract prices and filter out non-finite values (Infinity, NaN, etc.)
+    let prices: Vec<f64> = priced_rows
+        .iter()
+        .map(|&(_, price)| price)
+        .filter(|&price| price.is_finite()) // Filter out Infinity and NaN values
+        .collect();
+
+    // Calculate the average of finite prices
+    if prices.is_empty() {
+        return Err(anyhow!("No valid prices found"));
+    }
+    let avg: f64 = prices.iter().sum::<f64>() / prices.len() as f64;
+    debug!("Average price {}", avg);
+
+    // More Data Cleansing and preparation for vol calcs
+    // Filter ETH prices that are greater than $8000
+    let mut timestamp_failures = 0;
+    let filtered_prices: Vec<(DateTime<Utc>, f64)> = priced_rows
+        .into_iter()
+        .filter(|&(_, price)| price <= 8000.0)
+        .filter_map(|(row, price)| {
+            let timestamp = match row.get(&columns.timestamp) {
+                Some(Value::String(s)) => parse_timestamp(s),
+                Some(Value::Number(n)) => parse_timestamp(&n.to_string()),
+                other => Err(anyhow!("Invalid timestamp {:?}", other)),
+            };
+            match timestamp {
+                Ok(timestamp) => Some((timestamp, price)),
+                Err(e) => {
+                    debug!("Skipping Dune row: {}", e);
+                    timestamp_failures += 1;
+                    None
+                }
+            }
+        })
+        .collect();
+
+    debug!("Filtered prices: {:?}", filtered_prices);
+
+    Ok(SourceBars {
+        bars: filtered_prices,
+        timestamp_failures,
+        truncated: false,
+    })
+}
@@ -47,4 +47,27 @@ SELECT
 FROM
   eth_prices
 GROUP BY 1
-ORDER BY tspan DESC;
\ No newline at end of file
+ORDER BY tspan DESC;
+-- PARAMETERIZED EXAMPLE BELOW
+-- save once and set DUNE_QUERY_ID=[its query id], the estimator executes it on every run with
+-- the text parameters {{period}} (second, minute, hour or day), {{lookback}} (NO_OF_PERIODS)
+-- and {{token_sold_address}} / {{token_bought_address}} (DUNE_TOKEN_SOLD / DUNE_TOKEN_BOUGHT)
+
+WITH eth_prices AS (
+  SELECT
+    block_time AS timestamp,
+    token_sold_amount / token_bought_amount AS eth_price_in_usd -- ETH price in USD
+  FROM
+   uniswap_v3_ethereum.trades
+  WHERE
+    token_sold_address = {{token_sold_address}}
+    AND token_bought_address = {{token_bought_address}}
+    AND block_time > NOW() - interval '{{lookback}}' {{period}}
+)
+SELECT
+  date_trunc('{{period}}', timestamp) AS tspan,
+  avg(eth_price_in_usd) AS average_eth_price
+FROM
+  eth_prices
+GROUP BY 1
+ORDER BY 1 DESC;
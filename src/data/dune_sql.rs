@@ -0,0 +1,144 @@
+use anyhow::{anyhow, Result};
+use std::env;
+
+// Chains with `dex.trades` coverage and a default USDC / WETH pair
+const CHAINS: [&str; 4] = ["ethereum", "arbitrum", "base", "optimism"];
+
+// What the generated query prices
+#[derive(Debug, Clone)]
+pub struct DuneSqlSpec {
+    pub period: String, // second, minute, hour or day
+    pub lookback: usize,
+    pub chain: String,
+    pub dex: String,         // uniswap_v2, uniswap_v3, curve, balancer or all
+    pub quote_token: String, // Token sold for the base, e.g. USDC
+    pub base_token: String,  // Token priced, e.g. WETH
+}
+
+impl DuneSqlSpec {
+    // Load DUNE_CHAIN, DUNE_DEX, DUNE_TOKEN_SOLD and DUNE_TOKEN_BOUGHT,
+    // defaulting to Uniswap v3 USDC / WETH on Ethereum like the examples in dune.sql
+    pub fn from_env(period: &str, lookback: usize) -> Result<Self> {
+        let (quote_token, base_token) = dune_token_pair()?;
+        Ok(DuneSqlSpec {
+            period: period.to_string(),
+            lookback,
+            dex: env::var("DUNE_DEX").unwrap_or("uniswap_v3".to_string()),
+            quote_token,
+            base_token,
+            chain: dune_chain(),
+        })
+    }
+}
+
+// Chain the Dune queries read, from DUNE_CHAIN (default ethereum)
+pub fn dune_chain() -> String {
+    env::var("DUNE_CHAIN").unwrap_or("ethereum".to_string())
+}
+
+// Token sold and token bought, from DUNE_TOKEN_SOLD and DUNE_TOKEN_BOUGHT, defaulting to
+// USDC / WETH on DUNE_CHAIN
+pub fn dune_token_pair() -> Result<(String, String)> {
+    let (default_sold, default_bought) = default_token_pair(&dune_chain())?;
+    Ok((
+        env::var("DUNE_TOKEN_SOLD").unwrap_or(default_sold.to_string()),
+        env::var("DUNE_TOKEN_BOUGHT").unwrap_or(default_bought.to_string()),
+    ))
+}
+
+// USDC and WETH addresses on each supported chain
+pub fn default_token_pair(chain: &str) -> Result<(&'static str, &'static str)> {
+    match chain {
+        "ethereum" => Ok((
+            "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eb48",
+            "0xC02aaa39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+        )),
+        "arbitrum" => Ok((
+            "0xaf88d065e77c8cC2239327C5EDb3A432268e5831",
+            "0x82aF49447D8a07e3bd95BD0d56f35241523fBab1",
+        )),
+        "base" => Ok((
+            "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913",
+            "0x4200000000000000000000000000000000000006",
+        )),
+        "optimism" => Ok((
+            "0x0b2C639c533813f4Aa9D7837CAf62653d097Ff85",
+            "0x4200000000000000000000000000000000000006",
+        )),
+        _ => Err(anyhow!("DUNE_CHAIN must be one of: {}", CHAINS.join(", "))),
+    }
+}
+
+// Build a `dex.trades` query with volume weighted prices per bar, in both swap directions.
+// Output columns match the defaults of `DuneColumns`, plus `volume` in base token units.
+pub fn generate_dune_sql(spec: &DuneSqlSpec) -> Result<String> {
+    if !["second", "minute", "hour", "day"].contains(&spec.period.as_str()) {
+        return Err(anyhow!(
+            "Period must be one of: 'second', 'minute', 'hour', or 'day'."
+        ));
+    }
+    if !CHAINS.contains(&spec.chain.as_str()) {
+        return Err(anyhow!("DUNE_CHAIN must be one of: {}", CHAINS.join(", ")));
+    }
+    for token in [&spec.quote_token, &spec.base_token] {
+        // Addresses go into the SQL unquoted, so only accept plain hex
+        let is_address = token.len() == 42
+            && token.starts_with("0x")
+            && token[2..].chars().all(|c| c.is_ascii_hexdigit());
+        if !is_address {
+            return Err(anyhow!("Invalid token address: {}", token));
+        }
+    }
+
+    let dex_filter = match spec.dex.as_str() {
+        "uniswap_v2" => "\n    AND project = 'uniswap'\n    AND version = '2'",
+        "uniswap_v3" => "\n    AND project = 'uniswap'\n    AND version = '3'",
+        "curve" => "\n    AND project = 'curve'",
+        "balancer" => "\n    AND project = 'balancer'",
+        "all" => "",
+        _ => {
+            return Err(anyhow!(
+                "DUNE_DEX must be one of: uniswap_v2, uniswap_v3, curve, balancer, all"
+            ))
+        }
+    };
+
+    Ok(format!(
+        "-- {dex} {base} priced in {quote} on {chain}, {period} bars over the last {lookback} {period}s
+-- generated by volatility_estimator, save in dune and set DUNE_QUERY_ID_* to its query id
+
+WITH swaps AS (
+  SELECT
+    block_time,
+    CASE WHEN token_bought_address = {base} THEN token_bought_amount ELSE token_sold_amount END AS base_amount,
+    CASE WHEN token_bought_address = {base} THEN token_sold_amount ELSE token_bought_amount END AS quote_amount
+  FROM
+    dex.trades
+  WHERE
+    blockchain = '{chain}'{dex_filter}
+    AND (
+      (token_sold_address = {quote} AND token_bought_address = {base})
+      OR (token_sold_address = {base} AND token_bought_address = {quote})
+    )
+    AND token_bought_amount > 0
+    AND token_sold_amount > 0
+    AND block_time > NOW() - interval '{lookback}' {period}
+)
+SELECT
+  date_trunc('{period}', block_time) AS tspan,
+  sum(quote_amount) / sum(base_amount) AS average_eth_price, -- volume weighted
+  sum(base_amount) AS volume
+FROM
+  swaps
+GROUP BY 1
+ORDER BY 1 DESC;
+",
+        dex = spec.dex,
+        base = spec.base_token,
+        quote = spec.quote_token,
+        chain = spec.chain,
+        period = spec.period,
+        lookback = spec.lookback,
+        dex_filter = dex_filter,
+    ))
+}
@@ -0,0 +1,39 @@
+use super::dune_sql::{default_token_pair, generate_dune_sql, DuneSqlSpec};
+
+fn spec(chain: &str, dex: &str) -> DuneSqlSpec {
+    let (quote_token, base_token) = default_token_pair(chain).unwrap();
+    DuneSqlSpec {
+        period: "minute".to_string(),
+        lookback: 741,
+        chain: chain.to_string(),
+        dex: dex.to_string(),
+        quote_token: quote_token.to_string(),
+        base_token: base_token.to_string(),
+    }
+}
+
+#[test]
+fn test_generated_sql_filters_chain_and_dex() {
+    let sql = generate_dune_sql(&spec("arbitrum", "uniswap_v2")).unwrap();
+    assert!(sql.contains("blockchain = 'arbitrum'"));
+    assert!(sql.contains("project = 'uniswap'"));
+    assert!(sql.contains("version = '2'"));
+    assert!(sql.contains("interval '741' minute"));
+    assert!(sql.contains("date_trunc('minute', block_time) AS tspan"));
+    // Volume weighted rather than a simple average
+    assert!(sql.contains("sum(quote_amount) / sum(base_amount) AS average_eth_price"));
+    assert!(!sql.contains("avg("));
+
+    let sql = generate_dune_sql(&spec("base", "all")).unwrap();
+    assert!(!sql.contains("project ="));
+}
+
+#[test]
+fn test_generated_sql_rejects_bad_input() {
+    let mut bad_token = spec("ethereum", "curve");
+    bad_token.base_token = "0xC02a; DROP TABLE".to_string();
+    assert!(generate_dune_sql(&bad_token).is_err());
+
+    assert!(generate_dune_sql(&spec("ethereum", "sushiswap")).is_err());
+    assert!(default_token_pair("solana").is_err());
+}
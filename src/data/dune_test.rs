@@ -0,0 +1,190 @@
+use super::bars::SourceBars;
+use super::dune::{
+    fetch_saved_query, is_stale, parse_dune_rows, DuneAnalyticsResponse, DuneColumns, StaleResults,
+};
+use super::http::{Http, HttpMode};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use reqwest::Client;
+use std::collections::HashMap;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/data/fixtures/dune");
+
+fn columns() -> DuneColumns {
+    DuneColumns {
+        timestamp: "tspan".to_string(),
+        price: "average_eth_price".to_string(),
+        volume: None,
+    }
+}
+
+fn stale_results(rerun: bool) -> StaleResults {
+    StaleResults {
+        max_age: Duration::hours(1),
+        rerun,
+    }
+}
+
+// Columns of the community query in the fixture
+fn mapped_columns() -> DuneColumns {
+    DuneColumns {
+        timestamp: "block_hour".to_string(),
+        price: "price_usd".to_string(),
+        volume: Some("volume_usd".to_string()),
+    }
+}
+
+fn parse_dune_results(body: &str, columns: &DuneColumns) -> anyhow::Result<SourceBars> {
+    let response_data: DuneAnalyticsResponse = serde_json::from_str(body).unwrap();
+    parse_dune_rows(response_data, columns)
+}
+
+#[test]
+fn test_parse_recorded_dune_results() {
+    let body = include_str!("fixtures/dune_results.json");
+    let source_bars = parse_dune_results(body, &mapped_columns()).unwrap();
+
+    // The zero volume row is skipped and the "Infinity" price cleansed out
+    assert_eq!(source_bars.timestamp_failures, 0);
+    assert_eq!(
+        source_bars.bars,
+        vec![
+            (Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap(), 2500.5),
+            (Utc.with_ymd_and_hms(2024, 9, 1, 13, 0, 0).unwrap(), 2502.25),
+        ]
+    );
+}
+
+#[test]
+fn test_parse_dune_results_without_volume_column() {
+    let body = include_str!("fixtures/dune_results.json");
+    let columns = DuneColumns {
+        volume: None,
+        ..mapped_columns()
+    };
+    let source_bars = parse_dune_results(body, &columns).unwrap();
+
+    // Without a volume column the zero volume row is kept
+    assert_eq!(source_bars.bars.len(), 3);
+    assert_eq!(
+        source_bars.bars[1],
+        (Utc.with_ymd_and_hms(2024, 9, 1, 11, 0, 0).unwrap(), 2501.5)
+    );
+}
+
+#[test]
+fn test_parse_dune_results_missing_column() {
+    let body = include_str!("fixtures/dune_results.json");
+    let columns = DuneColumns {
+        price: "average_eth_price".to_string(),
+        ..mapped_columns()
+    };
+    let error = parse_dune_results(body, &columns).unwrap_err();
+
+    assert!(
+        error.to_string().contains("no column 'average_eth_price'"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_dune_columns_from_lookup() {
+    let defaults = DuneColumns::from_lookup(|_| None);
+    assert_eq!(defaults.timestamp, "tspan");
+    assert_eq!(defaults.price, "average_eth_price");
+    assert_eq!(defaults.volume, None);
+
+    let vars = HashMap::from([
+        ("DUNE_TIMESTAMP_COLUMN", "block_hour"),
+        ("DUNE_PRICE_COLUMN", "price_usd"),
+        ("DUNE_VOLUME_COLUMN", "volume_usd"),
+    ]);
+    let columns = DuneColumns::from_lookup(|name| vars.get(name).map(|value| value.to_string()));
+    assert_eq!(columns.timestamp, "block_hour");
+    assert_eq!(columns.price, "price_usd");
+    assert_eq!(columns.volume.as_deref(), Some("volume_usd"));
+}
+
+#[test]
+fn test_results_are_stale_past_the_max_age() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+    let max_age = Duration::hours(1);
+
+    assert!(!is_stale(Some(now - Duration::minutes(59)), now, max_age));
+    assert!(!is_stale(Some(now - max_age), now, max_age));
+    assert!(is_stale(Some(now - Duration::minutes(61)), now, max_age));
+    // Results with no execution time can't be trusted
+    assert!(is_stale(None, now, max_age));
+}
+
+#[tokio::test]
+async fn test_reruns_stale_results_and_polls_until_complete() {
+    // Stale results, then execute, executing, completed and the fresh results
+    let http = Http::new(
+        HttpMode::Replay,
+        Client::new(),
+        format!("{}/rerun", FIXTURES),
+    );
+    let source_bars = fetch_saved_query(&http, "test", "1234", 2, &columns(), &stale_results(true))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        source_bars.bars,
+        vec![
+            (DateTime::from_timestamp(1717200000, 0).unwrap(), 2500.0),
+            (DateTime::from_timestamp(1717200060, 0).unwrap(), 2501.0),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_keeps_stale_results_when_rerun_is_off() {
+    // Only the first, stale, response is read
+    let http = Http::new(
+        HttpMode::Replay,
+        Client::new(),
+        format!("{}/rerun", FIXTURES),
+    );
+    let source_bars =
+        fetch_saved_query(&http, "test", "1234", 2, &columns(), &stale_results(false))
+            .await
+            .unwrap();
+
+    assert_eq!(
+        source_bars.bars,
+        vec![(DateTime::from_timestamp(1717200000, 0).unwrap(), 2500.0)]
+    );
+}
+
+#[tokio::test]
+async fn test_failed_rerun_is_an_error() {
+    let http = Http::new(
+        HttpMode::Replay,
+        Client::new(),
+        format!("{}/failed", FIXTURES),
+    );
+    let error = fetch_saved_query(&http, "test", "1234", 2, &columns(), &stale_results(true))
+        .await
+        .unwrap_err();
+
+    assert!(
+        error.to_string().contains("QUERY_STATE_FAILED"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_results_error_status() {
+    let http = Http::new(
+        HttpMode::Replay,
+        Client::new(),
+        format!("{}/unauthorized", FIXTURES),
+    );
+    let error = fetch_saved_query(&http, "test", "1234", 2, &columns(), &stale_results(true))
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("401"), "{}", error);
+}
@@ -0,0 +1,272 @@
+use super::http::Http;
+use super::sources::Source;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use log::debug;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+// Minimal Ethereum JSON-RPC client, works with any node or hosted endpoint
+pub struct EthRpc<'a> {
+    http: &'a Http,
+    source: Source, // Requests are recorded under the source reading the chain
+    url: String,
+}
+
+// One entry of an `eth_getLogs` result
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcLog {
+    pub topics: Vec<String>,
+    pub data: String,
+    pub block_number: String,
+    #[serde(default)]
+    pub block_timestamp: Option<String>, // Only some nodes include it
+}
+
+impl<'a> EthRpc<'a> {
+    pub fn new(http: &'a Http, source: Source, url: &str) -> Self {
+        EthRpc {
+            http,
+            source,
+            url: url.to_string(),
+        }
+    }
+
+    // Send a single request and unwrap its result
+    pub async fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let request = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+        let response: Value = self
+            .http
+            .send(self.source, self.http.post(&self.url).json(&request))
+            .await?
+            .json()?;
+        rpc_result(response)
+    }
+
+    // Send several requests in one JSON-RPC batch, results come back in request order
+    pub async fn batch(&self, requests: &[(&str, Value)]) -> Result<Vec<Value>> {
+        self.batch_results(requests).await?.into_iter().collect()
+    }
+
+    // Like `batch`, but one failed request (e.g. a reverted eth_call) doesn't fail the others
+    pub async fn batch_results(&self, requests: &[(&str, Value)]) -> Result<Vec<Result<Value>>> {
+        if requests.is_empty() {
+            return Ok(vec![]);
+        }
+        let body: Vec<Value> = requests
+            .iter()
+            .enumerate()
+            .map(|(id, (method, params))| {
+                json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params})
+            })
+            .collect();
+        let responses: Vec<Value> = self
+            .http
+            .send(self.source, self.http.post(&self.url).json(&body))
+            .await?
+            .json()?;
+
+        // Batch responses may arrive in any order
+        let mut by_id: HashMap<u64, Value> = responses
+            .into_iter()
+            .filter_map(|response| Some((response.get("id")?.as_u64()?, response)))
+            .collect();
+        Ok((0..requests.len() as u64)
+            .map(|id| {
+                let response = by_id
+                    .remove(&id)
+                    .ok_or_else(|| anyhow!("Missing response {} in JSON-RPC batch", id))?;
+                rpc_result(response)
+            })
+            .collect())
+    }
+
+    pub async fn block_number(&self) -> Result<u64> {
+        let result = self.call("eth_blockNumber", json!([])).await?;
+        hex_to_u64(result.as_str().unwrap_or_default())
+    }
+
+    pub async fn block_timestamp(&self, block: u64) -> Result<DateTime<Utc>> {
+        let block = self
+            .call("eth_getBlockByNumber", json!([to_hex(block), false]))
+            .await?;
+        block_time(&block)
+    }
+
+    // Timestamps for many blocks at once, in a single batch
+    pub async fn block_timestamps(&self, blocks: &[u64]) -> Result<HashMap<u64, DateTime<Utc>>> {
+        let requests: Vec<(&str, Value)> = blocks
+            .iter()
+            .map(|&block| ("eth_getBlockByNumber", json!([to_hex(block), false])))
+            .collect();
+        let results = self.batch(&requests).await?;
+        blocks
+            .iter()
+            .zip(results)
+            .map(|(&block, result)| Ok((block, block_time(&result)?)))
+            .collect()
+    }
+
+    // First block at or after `time`, by binary search over block timestamps
+    pub async fn block_at_or_after(&self, time: DateTime<Utc>) -> Result<u64> {
+        let (mut low, mut high) = (0, self.block_number().await?);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.block_timestamp(mid).await? < time {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        debug!("First block at or after {} is {}", time, low);
+        Ok(low)
+    }
+
+    pub async fn get_logs(
+        &self,
+        address: &str,
+        topics: &[&str],
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<RpcLog>> {
+        let filter = json!({
+            "address": address,
+            "topics": topics,
+            "fromBlock": to_hex(from_block),
+            "toBlock": to_hex(to_block),
+        });
+        let result = self.call("eth_getLogs", json!([filter])).await?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    // Read-only contract call against the latest block
+    pub async fn eth_call(&self, to: &str, data: &str) -> Result<String> {
+        let result = self
+            .call("eth_call", json!([{"to": to, "data": data}, "latest"]))
+            .await?;
+        result
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| anyhow!("eth_call returned no data"))
+    }
+
+    // Several contract calls in one batch, None where a call reverted
+    pub async fn eth_call_batch(&self, to: &str, calls: &[String]) -> Result<Vec<Option<String>>> {
+        let requests: Vec<(&str, Value)> = calls
+            .iter()
+            .map(|data| ("eth_call", json!([{"to": to, "data": data}, "latest"])))
+            .collect();
+        Ok(self
+            .batch_results(&requests)
+            .await?
+            .into_iter()
+            .map(|result| {
+                result
+                    .ok()
+                    .and_then(|value| value.as_str().map(String::from))
+            })
+            .collect())
+    }
+}
+
+// Error object returned by the node, kept typed so callers can tell failures apart
+#[derive(Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "JSON-RPC error {}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+// The node refused eth_getLogs for returning too many results or spanning too many blocks.
+// Told apart by message, as Infura also answers rate limits with -32005.
+pub fn is_log_range_error(error: &anyhow::Error) -> bool {
+    let Some(error) = error.downcast_ref::<RpcError>() else {
+        return false;
+    };
+    let message = error.message.to_lowercase();
+    ["more than", "too many", "range", "response size"]
+        .iter()
+        .any(|phrase| message.contains(phrase))
+}
+
+// Unwrap a JSON-RPC response, surfacing the node's own error message
+fn rpc_result(mut response: Value) -> Result<Value> {
+    if let Some(error) = response.get("error") {
+        let message = error
+            .get("message")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown error");
+        let code = error.get("code").and_then(Value::as_i64).unwrap_or(0);
+        return Err(RpcError {
+            code,
+            message: message.to_string(),
+        }
+        .into());
+    }
+    match response.get_mut("result") {
+        Some(result) => Ok(result.take()),
+        None => Err(anyhow!("JSON-RPC response has no result")),
+    }
+}
+
+fn block_time(block: &Value) -> Result<DateTime<Utc>> {
+    let timestamp = block
+        .get("timestamp")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("Block not found or missing timestamp"))?;
+    DateTime::from_timestamp(hex_to_u64(timestamp)? as i64, 0)
+        .ok_or_else(|| anyhow!("Block timestamp out of range: {}", timestamp))
+}
+
+pub fn to_hex(value: u64) -> String {
+    format!("0x{:x}", value)
+}
+
+pub fn hex_to_u64(hex: &str) -> Result<u64> {
+    u64::from_str_radix(hex.trim_start_matches("0x"), 16)
+        .map_err(|e| anyhow!("Invalid hex quantity {}: {}", hex, e))
+}
+
+// Split ABI encoded data into its 32 byte words
+pub fn abi_words(data: &str) -> Result<Vec<[u8; 32]>> {
+    let hex = data.trim_start_matches("0x");
+    if !hex.len().is_multiple_of(64) {
+        return Err(anyhow!("ABI data is not a whole number of words"));
+    }
+    (0..hex.len() / 64)
+        .map(|i| {
+            let mut word = [0u8; 32];
+            for (j, byte) in word.iter_mut().enumerate() {
+                let start = i * 64 + j * 2;
+                *byte = u8::from_str_radix(&hex[start..start + 2], 16)
+                    .map_err(|e| anyhow!("Invalid hex in ABI data: {}", e))?;
+            }
+            Ok(word)
+        })
+        .collect()
+}
+
+// Unsigned 256 bit word as f64, exact up to 2^53 and rounded beyond
+pub fn word_to_f64(word: &[u8; 32]) -> f64 {
+    word.iter()
+        .fold(0.0, |value, &byte| value * 256.0 + byte as f64)
+}
+
+// Two's complement signed 256 bit word (int256, int24 ...) as f64
+pub fn signed_word_to_f64(word: &[u8; 32]) -> f64 {
+    if word[0] & 0x80 == 0 {
+        return word_to_f64(word);
+    }
+    // Negative: magnitude is the bitwise complement plus one
+    let complement: [u8; 32] = word.map(|byte| !byte);
+    -(word_to_f64(&complement) + 1.0)
+}
@@ -0,0 +1,273 @@
+use super::bars::SourceBars;
+use super::rounding::BarStamp;
+use super::timestamp::{parse_epoch, parse_local_timestamp};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use log::debug;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::Field;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tokio::task;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileFormat {
+    Csv,
+    Parquet,
+}
+
+// Which columns of the file hold the timestamp and the price, or the OHLC prices
+#[derive(Debug, Clone)]
+pub struct FileColumns {
+    pub timestamp: String,
+    pub price: String,
+    pub ohlc: Option<[String; 4]>, // Priced as the average of OHLC when set, like the exchanges
+    pub volume: Option<String>,    // Rows with no volume are skipped when set
+}
+
+#[derive(Debug, Clone)]
+pub struct FileSource {
+    pub paths: Vec<PathBuf>,
+    pub format: Option<FileFormat>, // None picks the format from each file's extension
+    pub columns: FileColumns,
+    pub timezone: Tz,        // For timestamps written without an offset
+    pub bar_stamp: BarStamp, // Open unless FILE_BAR_STAMP=close
+}
+
+impl FileSource {
+    // Load FILE_SOURCE_PATH (comma separated) and its format, columns and timezone,
+    // None when no file is configured
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(paths) = env::var("FILE_SOURCE_PATH") else {
+            return Ok(None);
+        };
+        let paths: Vec<PathBuf> = paths
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .collect();
+
+        let format = match env::var("FILE_FORMAT").ok().as_deref() {
+            None => None,
+            Some("csv") => Some(FileFormat::Csv),
+            Some("parquet") => Some(FileFormat::Parquet),
+            Some(_) => return Err(anyhow!("FILE_FORMAT must be csv or parquet")),
+        };
+
+        let ohlc = match (
+            env::var("FILE_OPEN_COLUMN"),
+            env::var("FILE_HIGH_COLUMN"),
+            env::var("FILE_LOW_COLUMN"),
+            env::var("FILE_CLOSE_COLUMN"),
+        ) {
+            (Ok(open), Ok(high), Ok(low), Ok(close)) => Some([open, high, low, close]),
+            (Err(_), Err(_), Err(_), Err(_)) => None,
+            _ => {
+                return Err(anyhow!(
+                    "Set all of FILE_OPEN_COLUMN, FILE_HIGH_COLUMN, FILE_LOW_COLUMN and FILE_CLOSE_COLUMN, or none"
+                ))
+            }
+        };
+
+        let timezone = env::var("FILE_TIMEZONE").unwrap_or("UTC".to_string());
+        let timezone: Tz = timezone
+            .parse()
+            .map_err(|_| anyhow!("FILE_TIMEZONE is not an IANA time zone: {}", timezone))?;
+
+        let bar_stamp = match env::var("FILE_BAR_STAMP").ok().as_deref() {
+            None | Some("open") => BarStamp::Open,
+            Some("close") => BarStamp::Close,
+            Some(_) => return Err(anyhow!("FILE_BAR_STAMP must be open or close")),
+        };
+
+        Ok(Some(FileSource {
+            paths,
+            format,
+            columns: FileColumns {
+                timestamp: env::var("FILE_TIMESTAMP_COLUMN").unwrap_or("timestamp".to_string()),
+                price: env::var("FILE_PRICE_COLUMN").unwrap_or("price".to_string()),
+                ohlc,
+                volume: env::var("FILE_VOLUME_COLUMN").ok(),
+            },
+            timezone,
+            bar_stamp,
+        }))
+    }
+}
+
+// A value read from either file format
+#[derive(Debug, Clone)]
+enum Cell {
+    Text(String),
+    Number(f64),
+    Time(DateTime<Utc>),
+}
+
+// Read every configured file into one bar per period, on a blocking thread as the files may be
+// large. The bars are stamped with their open time.
+pub async fn get_file_data(source: &FileSource, time_period: &str) -> Result<SourceBars> {
+    let source = source.clone();
+    let time_period = time_period.to_string();
+    task::spawn_blocking(move || read_file_bars(&source, &time_period)).await?
+}
+
+fn read_file_bars(source: &FileSource, time_period: &str) -> Result<SourceBars> {
+    let mut source_bars = SourceBars::default();
+    for path in &source.paths {
+        let format = match source.format {
+            Some(format) => format,
+            None => format_from_extension(path)?,
+        };
+        debug!("Reading {:?} as {:?}", path, format);
+
+        let file_bars = match format {
+            FileFormat::Csv => read_csv(File::open(path)?, &source.columns, source.timezone)?,
+            FileFormat::Parquet => {
+                read_parquet(File::open(path)?, &source.columns, source.timezone)?
+            }
+        };
+        source_bars.bars.extend(file_bars.bars);
+        source_bars.timestamp_failures += file_bars.timestamp_failures;
+    }
+    source_bars.bars = last_price_per_bar(source_bars.bars, source.bar_stamp, time_period);
+    Ok(source_bars)
+}
+
+// Rows finer than the period, e.g. ticks at TIME_PERIOD=minute, collapse into their bar at the
+// price of the latest row, which a later row in the files wins on a tie
+pub fn last_price_per_bar(
+    rows: Vec<(DateTime<Utc>, f64)>,
+    bar_stamp: BarStamp,
+    time_period: &str,
+) -> Vec<(DateTime<Utc>, f64)> {
+    let mut bars: BTreeMap<DateTime<Utc>, (DateTime<Utc>, f64)> = BTreeMap::new();
+    for (timestamp, price) in rows {
+        let bar_open = bar_stamp.to_bar_open(timestamp, time_period);
+        match bars.get(&bar_open) {
+            Some(&(latest, _)) if latest > timestamp => {}
+            _ => {
+                bars.insert(bar_open, (timestamp, price));
+            }
+        }
+    }
+    bars.into_iter()
+        .map(|(bar_open, (_, price))| (bar_open, price))
+        .collect()
+}
+
+fn format_from_extension(path: &Path) -> Result<FileFormat> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => Ok(FileFormat::Csv),
+        Some("parquet") => Ok(FileFormat::Parquet),
+        _ => Err(anyhow!(
+            "Can't tell the format of {:?}, set FILE_FORMAT",
+            path
+        )),
+    }
+}
+
+// Read a CSV file with a header row
+pub fn read_csv(reader: impl Read, columns: &FileColumns, timezone: Tz) -> Result<SourceBars> {
+    let mut reader = csv::Reader::from_reader(reader);
+    let headers = reader.headers()?.clone();
+
+    let mut source_bars = SourceBars::default();
+    for record in reader.records() {
+        let record = record?;
+        let row: HashMap<&str, Cell> = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(header, value)| (header, Cell::Text(value.to_string())))
+            .collect();
+        push_row(&mut source_bars, &row, columns, timezone)?;
+    }
+    Ok(source_bars)
+}
+
+// Read a Parquet file row by row, timestamp columns may be strings, epochs or Parquet timestamps
+pub fn read_parquet(file: File, columns: &FileColumns, timezone: Tz) -> Result<SourceBars> {
+    let reader = SerializedFileReader::new(file)?;
+
+    let mut source_bars = SourceBars::default();
+    for row in reader.get_row_iter(None)? {
+        let row = row?;
+        let row: HashMap<&str, Cell> = row
+            .get_column_iter()
+            .filter_map(|(name, field)| Some((name.as_str(), field_to_cell(field)?)))
+            .collect();
+        push_row(&mut source_bars, &row, columns, timezone)?;
+    }
+    Ok(source_bars)
+}
+
+fn field_to_cell(field: &Field) -> Option<Cell> {
+    match field {
+        Field::Null => Some(Cell::Text(String::new())),
+        Field::Str(s) => Some(Cell::Text(s.clone())),
+        Field::Float(n) => Some(Cell::Number(*n as f64)),
+        Field::Double(n) => Some(Cell::Number(*n)),
+        Field::Int(n) => Some(Cell::Number(*n as f64)),
+        Field::Long(n) => Some(Cell::Number(*n as f64)),
+        Field::UInt(n) => Some(Cell::Number(*n as f64)),
+        Field::ULong(n) => Some(Cell::Number(*n as f64)),
+        Field::Decimal(_) => field.to_string().parse().ok().map(Cell::Number),
+        Field::TimestampMillis(ms) => DateTime::from_timestamp_millis(*ms).map(Cell::Time),
+        Field::TimestampMicros(us) => DateTime::from_timestamp_micros(*us).map(Cell::Time),
+        Field::Date(days) => DateTime::from_timestamp(*days as i64 * 86_400, 0).map(Cell::Time),
+        _ => None,
+    }
+}
+
+// Price one row and add it to the bars, a missing column is an error rather than a skipped row
+fn push_row(
+    source_bars: &mut SourceBars,
+    row: &HashMap<&str, Cell>,
+    columns: &FileColumns,
+    timezone: Tz,
+) -> Result<()> {
+    let number = |column: &str| -> Result<Option<f64>> {
+        match row.get(column) {
+            Some(Cell::Number(n)) => Ok(Some(*n)),
+            Some(Cell::Text(s)) if s.trim().is_empty() => Ok(None),
+            Some(Cell::Text(s)) => Ok(s.trim().parse().ok()),
+            Some(Cell::Time(_)) => Ok(None),
+            None => Err(anyhow!("Column {} not found in file", column)),
+        }
+    };
+
+    let price = match &columns.ohlc {
+        Some(ohlc) => {
+            let mut prices = vec![];
+            for column in ohlc {
+                prices.extend(number(column)?);
+            }
+            (prices.len() == 4).then(|| prices.iter().sum::<f64>() / 4.0)
+        }
+        None => number(&columns.price)?,
+    };
+    let Some(price) = price.filter(|price| price.is_finite()) else {
+        return Ok(());
+    };
+    if let Some(volume) = &columns.volume {
+        if number(volume)?.unwrap_or(0.0) <= 0.0 {
+            return Ok(());
+        }
+    }
+
+    let timestamp = match row.get(columns.timestamp.as_str()) {
+        Some(Cell::Time(time)) => Some(*time),
+        Some(Cell::Number(n)) => parse_epoch(*n as i64),
+        Some(Cell::Text(s)) => parse_local_timestamp(s, timezone).ok(),
+        None => return Err(anyhow!("Column {} not found in file", columns.timestamp)),
+    };
+    match timestamp {
+        Some(timestamp) => source_bars.bars.push((timestamp, price)),
+        None => source_bars.timestamp_failures += 1,
+    }
+    Ok(())
+}
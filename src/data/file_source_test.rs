@@ -0,0 +1,177 @@
+use super::file_source::{
+    get_file_data, last_price_per_bar, read_csv, read_parquet, FileColumns, FileSource,
+};
+use super::rounding::BarStamp;
+use chrono::{TimeZone, Utc};
+use parquet::data_type::{DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use std::fs::File;
+use std::sync::Arc;
+
+#[test]
+fn test_read_ohlcv_csv_in_local_time() {
+    let columns = FileColumns {
+        timestamp: "date".to_string(),
+        price: "price".to_string(),
+        ohlc: Some([
+            "open".to_string(),
+            "high".to_string(),
+            "low".to_string(),
+            "close".to_string(),
+        ]),
+        volume: Some("volume".to_string()),
+    };
+    let csv = include_str!("fixtures/ohlcv_new_york.csv");
+    let source_bars = read_csv(csv.as_bytes(), &columns, chrono_tz::America::New_York).unwrap();
+
+    // The zero volume row is skipped and the unparseable date is counted
+    assert_eq!(source_bars.bars.len(), 2);
+    assert_eq!(source_bars.timestamp_failures, 1);
+
+    // 06:00 in New York is 10:00 UTC in September
+    let (timestamp, price) = source_bars.bars[0];
+    assert_eq!(
+        timestamp,
+        Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap()
+    );
+    assert!((price - 2500.25).abs() < 1e-9);
+}
+
+#[test]
+fn test_missing_column_is_an_error() {
+    let columns = FileColumns {
+        timestamp: "time".to_string(),
+        price: "close".to_string(),
+        ohlc: None,
+        volume: None,
+    };
+    let csv = "timestamp,close\n2024-09-01T10:00:00Z,2500.0\n";
+    assert!(read_csv(csv.as_bytes(), &columns, chrono_tz::UTC).is_err());
+}
+
+#[test]
+fn test_read_parquet_timestamps() {
+    let path = std::env::temp_dir().join(format!(
+        "volatility_estimator_test_{}.parquet",
+        std::process::id()
+    ));
+    let start = Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap();
+
+    // Write a small file with a Parquet timestamp column and a price column
+    {
+        let schema = parse_message_type(
+            "message bars { REQUIRED INT64 ts (TIMESTAMP(MILLIS,true)); REQUIRED DOUBLE vwap; }",
+        )
+        .unwrap();
+        let properties = Arc::new(WriterProperties::builder().build());
+        let mut writer =
+            SerializedFileWriter::new(File::create(&path).unwrap(), Arc::new(schema), properties)
+                .unwrap();
+        let mut row_group = writer.next_row_group().unwrap();
+
+        let mut column = row_group.next_column().unwrap().unwrap();
+        let times: Vec<i64> = (0..3)
+            .map(|i| start.timestamp_millis() + i * 3_600_000)
+            .collect();
+        column
+            .typed::<Int64Type>()
+            .write_batch(&times, None, None)
+            .unwrap();
+        column.close().unwrap();
+
+        let mut column = row_group.next_column().unwrap().unwrap();
+        column
+            .typed::<DoubleType>()
+            .write_batch(&[2500.0, 2510.0, 2505.0], None, None)
+            .unwrap();
+        column.close().unwrap();
+
+        row_group.close().unwrap();
+        writer.close().unwrap();
+    }
+
+    let columns = FileColumns {
+        timestamp: "ts".to_string(),
+        price: "vwap".to_string(),
+        ohlc: None,
+        volume: None,
+    };
+    let source_bars = read_parquet(File::open(&path).unwrap(), &columns, chrono_tz::UTC);
+    std::fs::remove_file(&path).unwrap();
+    let source_bars = source_bars.unwrap();
+
+    assert_eq!(source_bars.bars.len(), 3);
+    assert_eq!(source_bars.bars[0], (start, 2500.0));
+    assert_eq!(
+        source_bars.bars[2].0,
+        Utc.with_ymd_and_hms(2024, 9, 1, 12, 0, 0).unwrap()
+    );
+}
+
+#[test]
+fn test_rows_collapse_to_one_bar_per_period() {
+    let at = |minute, second| {
+        Utc.with_ymd_and_hms(2024, 9, 1, 10, minute, second)
+            .unwrap()
+    };
+    let rows = vec![
+        (at(0, 10), 2500.0),
+        (at(0, 50), 2502.0),
+        (at(0, 30), 2501.0), // Out of order, not the latest in its bar
+        (at(1, 0), 2503.0),
+        (at(1, 0), 2504.0), // Same time, the later row wins
+    ];
+
+    assert_eq!(
+        last_price_per_bar(rows.clone(), BarStamp::Open, "minute"),
+        vec![(at(0, 0), 2502.0), (at(1, 0), 2504.0)]
+    );
+    // Stamped with their close, the row at 10:01:00 ends the first bar
+    assert_eq!(
+        last_price_per_bar(rows, BarStamp::Close, "minute"),
+        vec![(at(0, 0), 2504.0)]
+    );
+}
+
+#[tokio::test]
+async fn test_file_data_counts_distinct_bars() {
+    let path = std::env::temp_dir().join(format!(
+        "volatility_estimator_ticks_{}.csv",
+        std::process::id()
+    ));
+    std::fs::write(
+        &path,
+        "timestamp,price\n\
+         2024-09-01T10:00:05Z,2500.0\n\
+         2024-09-01T10:00:35Z,2501.0\n\
+         2024-09-01T10:01:15Z,2502.0\n\
+         not a time,2503.0\n",
+    )
+    .unwrap();
+    let source = FileSource {
+        paths: vec![path.clone()],
+        format: None,
+        columns: FileColumns {
+            timestamp: "timestamp".to_string(),
+            price: "price".to_string(),
+            ohlc: None,
+            volume: None,
+        },
+        timezone: chrono_tz::UTC,
+        bar_stamp: BarStamp::Open,
+    };
+    let source_bars = get_file_data(&source, "minute").await;
+    std::fs::remove_file(&path).unwrap();
+    let source_bars = source_bars.unwrap();
+
+    assert_eq!(
+        source_bars.bars,
+        vec![
+            (Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap(), 2501.0),
+            (Utc.with_ymd_and_hms(2024, 9, 1, 10, 1, 0).unwrap(), 2502.0),
+        ]
+    );
+    assert_eq!(source_bars.timestamp_failures, 1);
+}
@@ -0,0 +1,384 @@
+use super::http_client::ClientSettings;
+use super::retry::{
+    backoff_delay, is_retryable, retry_after, CircuitBreaker, SourcePolicy, TokenBucket,
+};
+use super::sources::Source;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use log::{debug, warn};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Request, RequestBuilder, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::time::sleep;
+
+// Headers worth keeping in a recording, the rest are noise in the fixture files
+const RECORDED_HEADERS: [&str; 6] = [
+    "content-type",
+    "retry-after",
+    "x-ratelimit-limit",
+    "x-ratelimit-remaining",
+    "x-ratelimit-request-cost",
+    "x-ratelimit-reset",
+];
+
+// Whether requests go to the network, and whether responses are saved or served from disk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HttpMode {
+    Live,
+    Record, // Live, saving every response
+    Replay, // Serve saved responses, no network
+}
+
+// A response read in full, whether it came from the network or a recording
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_str(&self.body)
+            .map_err(|e| anyhow!("Failed to parse response from {}: {}", self.status, e))
+    }
+}
+
+// One saved response. Requests are matched by their order within a source, since URLs carry
+// the current time and would never match on a later run.
+#[derive(Serialize, Deserialize, Debug)]
+struct RecordedResponse {
+    method: String,
+    url: String, // For reference only, with API keys removed
+    status: u16,
+    headers: BTreeMap<String, String>,
+    body: String,
+}
+
+// Which failures a live request is resent after
+#[derive(Clone, Copy)]
+enum Retries {
+    Transient, // Connection errors and retryable statuses
+    OnConnect, // Only failures to connect, when the server never saw the request
+}
+
+// Rate limit and circuit breaker state of one source
+struct SourceLimits {
+    policy: SourcePolicy,
+    bucket: TokenBucket,
+    breaker: CircuitBreaker,
+}
+
+impl SourceLimits {
+    fn new(policy: SourcePolicy) -> Self {
+        SourceLimits {
+            bucket: TokenBucket::new(&policy, Instant::now()),
+            breaker: CircuitBreaker::new(&policy),
+            policy,
+        }
+    }
+}
+
+// Sends every adapter's requests, recording or replaying them per source. Live requests are
+// rate limited, retried and circuit broken per source.
+pub struct Http {
+    mode: HttpMode,
+    client: Client, // Shared by every source, see ClientSettings
+    fixtures_dir: PathBuf,
+    sequence: Mutex<HashMap<Source, usize>>, // Requests made so far by each source
+    limits: Mutex<HashMap<Source, SourceLimits>>, // Defaults for sources without a policy
+}
+
+impl Http {
+    pub fn new(mode: HttpMode, client: Client, fixtures_dir: impl Into<PathBuf>) -> Self {
+        Http {
+            mode,
+            client,
+            fixtures_dir: fixtures_dir.into(),
+            sequence: Mutex::new(HashMap::new()),
+            limits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Use `policy` for `source` instead of its defaults
+    pub fn with_policy(self, source: Source, policy: SourcePolicy) -> Self {
+        self.limits
+            .lock()
+            .unwrap()
+            .insert(source, SourceLimits::new(policy));
+        self
+    }
+
+    // Load HTTP_MODE (live, record or replay), HTTP_FIXTURES_DIR, the client settings and
+    // every source's limits
+    pub fn from_env() -> Result<Self> {
+        let mode = match env::var("HTTP_MODE").unwrap_or("live".to_string()).as_str() {
+            "live" => HttpMode::Live,
+            "record" => HttpMode::Record,
+            "replay" => HttpMode::Replay,
+            _ => return Err(anyhow!("HTTP_MODE must be live, record or replay")),
+        };
+        let fixtures_dir = env::var("HTTP_FIXTURES_DIR").unwrap_or("fixtures/http".to_string());
+        let mut http = Http::new(mode, ClientSettings::from_env()?.build()?, fixtures_dir);
+
+        // Every source's limits are read up front, so a bad setting stops the run here
+        for source in Source::ALL {
+            http = http.with_policy(source, SourcePolicy::from_env(source)?);
+        }
+        Ok(http)
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    // Send a request for `source`, or serve its next recorded response in replay mode
+    pub async fn send(&self, source: Source, request: RequestBuilder) -> Result<HttpResponse> {
+        self.send_with(source, request, Retries::Transient).await
+    }
+
+    // Like `send`, for a request that mustn't run twice, e.g. one starting paid work. It's only
+    // resent when the connection failed, as a lost or late response may mean it already ran.
+    pub async fn send_once(&self, source: Source, request: RequestBuilder) -> Result<HttpResponse> {
+        self.send_with(source, request, Retries::OnConnect).await
+    }
+
+    async fn send_with(
+        &self,
+        source: Source,
+        request: RequestBuilder,
+        retries: Retries,
+    ) -> Result<HttpResponse> {
+        let (client, request) = request.build_split();
+        let request = request?;
+        let index = self.next_index(source);
+        let path = self
+            .fixtures_dir
+            .join(source.label().to_lowercase())
+            .join(format!("{:04}.json", index));
+
+        if self.mode == HttpMode::Replay {
+            let recorded: RecordedResponse =
+                serde_json::from_str(&fs::read_to_string(&path).map_err(|e| {
+                    anyhow!(
+                        "No recorded {} response #{} at {:?} for {}: {}",
+                        source.label(),
+                        index,
+                        path,
+                        redact_url(source, request.url()),
+                        e
+                    )
+                })?)?;
+            if recorded.method != request.method().as_str()
+                || path_of(&recorded.url) != path_of(&redact_url(source, request.url()))
+            {
+                warn!(
+                    "Replaying {} {} for {} {}",
+                    recorded.method,
+                    recorded.url,
+                    request.method(),
+                    redact_url(source, request.url())
+                );
+            }
+            let mut headers = HeaderMap::new();
+            for (name, value) in &recorded.headers {
+                if let (Ok(name), Ok(value)) = (
+                    HeaderName::from_bytes(name.as_bytes()),
+                    HeaderValue::from_str(value),
+                ) {
+                    headers.insert(name, value);
+                }
+            }
+            return Ok(HttpResponse {
+                status: StatusCode::from_u16(recorded.status)?,
+                headers,
+                body: recorded.body,
+            });
+        }
+
+        let method = request.method().to_string();
+        let url = redact_url(source, request.url());
+        let response = self.execute(source, &client, request, retries).await?;
+
+        if self.mode == HttpMode::Record {
+            let recorded = RecordedResponse {
+                method,
+                url,
+                status: response.status.as_u16(),
+                headers: RECORDED_HEADERS
+                    .iter()
+                    .filter_map(|&name| {
+                        let value = response.headers.get(name)?.to_str().ok()?;
+                        Some((name.to_string(), value.to_string()))
+                    })
+                    .collect(),
+                body: response.body.clone(),
+            };
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(&path, serde_json::to_string_pretty(&recorded)?)?;
+            debug!(
+                "Recorded {} response #{} to {:?}",
+                source.label(),
+                index,
+                path
+            );
+        }
+        Ok(response)
+    }
+
+    // Send a live request within the source's rate limit, retrying transient failures
+    async fn execute(
+        &self,
+        source: Source,
+        client: &Client,
+        request: Request,
+        retries: Retries,
+    ) -> Result<HttpResponse> {
+        let policy = self.with_limits(source, |limits| {
+            match limits.breaker.check(Instant::now()) {
+                Some(wait) => Err(anyhow!(
+                    "{} circuit breaker is open after repeated failures, retrying in {}s",
+                    source.label(),
+                    wait.as_secs()
+                )),
+                None => Ok(limits.policy.clone()),
+            }
+        })?;
+
+        let mut retry = 0;
+        loop {
+            let wait = self.with_limits(source, |limits| limits.bucket.take(Instant::now()));
+            if !wait.is_zero() {
+                debug!("Rate limiting {} for {:?}", source.label(), wait);
+                sleep(wait).await;
+            }
+
+            let attempt = request.try_clone().ok_or_else(|| {
+                anyhow!(
+                    "Request to {} can't be retried",
+                    redact_url(source, request.url())
+                )
+            })?;
+            let outcome = match client.execute(attempt).await {
+                Ok(response) => {
+                    let status = response.status();
+                    let headers = response.headers().clone();
+                    response.text().await.map(|body| HttpResponse {
+                        status,
+                        headers,
+                        body,
+                    })
+                }
+                // The error's URL may hold an API key
+                Err(e) => Err(e.without_url()),
+            };
+
+            let (delay, reason) = match &outcome {
+                Ok(response) if !is_retryable(response.status) => {
+                    self.with_limits(source, |limits| limits.breaker.record_success());
+                    return outcome.map_err(Into::into);
+                }
+                Ok(response) => (
+                    retry_after(&response.headers, Utc::now())
+                        .unwrap_or_else(|| backoff_delay(&policy, retry)),
+                    response.status.to_string(),
+                ),
+                Err(e) => (backoff_delay(&policy, retry), e.to_string()),
+            };
+
+            let resend = match (&outcome, retries) {
+                (_, Retries::Transient) => true,
+                (Err(e), Retries::OnConnect) => e.is_connect(),
+                (Ok(_), Retries::OnConnect) => false,
+            };
+
+            // Not safe to resend, out of retries, or asked to wait longer than is worth it
+            if !resend || retry >= policy.max_retries || delay > policy.max_delay {
+                let opened = self.with_limits(source, |limits| {
+                    limits.breaker.record_failure(Instant::now())
+                });
+                if opened {
+                    warn!(
+                        "{} failed {} times in a row, pausing it for {:?}",
+                        source.label(),
+                        policy.breaker_threshold,
+                        policy.breaker_cooldown
+                    );
+                }
+                return outcome.map_err(Into::into);
+            }
+
+            retry += 1;
+            warn!(
+                "{} request failed ({}), retry {} of {} in {:?}",
+                source.label(),
+                reason,
+                retry,
+                policy.max_retries,
+                delay
+            );
+            sleep(delay).await;
+        }
+    }
+
+    fn with_limits<T>(&self, source: Source, f: impl FnOnce(&mut SourceLimits) -> T) -> T {
+        let mut limits = self.limits.lock().unwrap();
+        f(limits
+            .entry(source)
+            .or_insert_with(|| SourceLimits::new(SourcePolicy::defaults(source))))
+    }
+
+    fn next_index(&self, source: Source) -> usize {
+        let mut sequence = self.sequence.lock().unwrap();
+        let index = sequence.entry(source).or_insert(0);
+        *index += 1;
+        *index - 1
+    }
+}
+
+// Base URL of a source's API, {SOURCE}_BASE_URL points an adapter at a mock server
+pub fn base_url(source: Source, default: &str) -> String {
+    let name = format!("{}_BASE_URL", source.label().to_uppercase());
+    env::var(name)
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or(default.to_string())
+}
+
+// Drop query parameters that look like credentials before a URL is logged or saved. JSON-RPC
+// endpoints often carry their key in the path, so the ETH_RPC_URL sources' URLs are dropped whole.
+pub fn redact_url(source: Source, url: &Url) -> String {
+    if matches!(source, Source::Uniswap | Source::Chainlink) {
+        return "[ETH_RPC_URL]".to_string();
+    }
+    let mut url = url.clone();
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| !name.to_lowercase().contains("key"))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if pairs.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+fn path_of(url: &str) -> String {
+    Url::parse(url)
+        .map(|url| url.path().to_string())
+        .unwrap_or_default()
+}
@@ -0,0 +1,121 @@
+use anyhow::{anyhow, Result};
+use reqwest::tls::Version;
+use reqwest::{Certificate, Client, NoProxy, Proxy};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Settings of the one HTTP client shared by every source
+#[derive(Debug, Clone)]
+pub struct ClientSettings {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration, // Longest wait for the next bytes of a response
+    pub timeout: Duration,      // Longest a whole request may take
+    pub user_agent: String,
+    pub proxy: Option<String>, // Used for both http and https, instead of HTTPS_PROXY and friends
+    pub proxy_username: Option<String>,
+    pub proxy_password: Option<String>,
+    pub no_proxy: Option<String>, // Comma separated hosts that bypass the proxy
+    pub ca_cert: Option<PathBuf>, // Extra PEM root certificate, e.g. a corporate proxy's CA
+    pub min_tls_version: Option<Version>,
+    pub accept_invalid_certs: bool,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        ClientSettings {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            timeout: Duration::from_secs(120),
+            // Some APIs (Coinbase) reject requests without a User-Agent
+            user_agent: concat!("volatility_estimator/", env!("CARGO_PKG_VERSION")).to_string(),
+            proxy: None,
+            proxy_username: None,
+            proxy_password: None,
+            no_proxy: None,
+            ca_cert: None,
+            min_tls_version: None,
+            accept_invalid_certs: false,
+        }
+    }
+}
+
+impl ClientSettings {
+    // Load HTTP_CONNECT_TIMEOUT_SECS, HTTP_READ_TIMEOUT_SECS, HTTP_TIMEOUT_SECS, HTTP_USER_AGENT,
+    // HTTP_PROXY_URL (with HTTP_PROXY_USERNAME, HTTP_PROXY_PASSWORD and HTTP_NO_PROXY),
+    // HTTP_CA_CERT, HTTP_MIN_TLS_VERSION and HTTP_ACCEPT_INVALID_CERTS
+    pub fn from_env() -> Result<Self> {
+        let defaults = ClientSettings::default();
+        let seconds = |name: &str, default: Duration| -> Result<Duration> {
+            match env::var(name) {
+                Ok(value) => value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|secs| *secs > 0.0)
+                    .map(Duration::from_secs_f64)
+                    .ok_or_else(|| anyhow!("{} must be a positive number of seconds", name)),
+                Err(_) => Ok(default),
+            }
+        };
+
+        let min_tls_version = match env::var("HTTP_MIN_TLS_VERSION").ok().as_deref() {
+            None => None,
+            Some("1.2") => Some(Version::TLS_1_2),
+            Some("1.3") => Some(Version::TLS_1_3),
+            Some(_) => return Err(anyhow!("HTTP_MIN_TLS_VERSION must be 1.2 or 1.3")),
+        };
+
+        Ok(ClientSettings {
+            connect_timeout: seconds("HTTP_CONNECT_TIMEOUT_SECS", defaults.connect_timeout)?,
+            read_timeout: seconds("HTTP_READ_TIMEOUT_SECS", defaults.read_timeout)?,
+            timeout: seconds("HTTP_TIMEOUT_SECS", defaults.timeout)?,
+            user_agent: env::var("HTTP_USER_AGENT").unwrap_or(defaults.user_agent),
+            proxy: env::var("HTTP_PROXY_URL").ok(),
+            proxy_username: env::var("HTTP_PROXY_USERNAME").ok(),
+            proxy_password: env::var("HTTP_PROXY_PASSWORD").ok(),
+            no_proxy: env::var("HTTP_NO_PROXY").ok(),
+            ca_cert: env::var("HTTP_CA_CERT").ok().map(PathBuf::from),
+            min_tls_version,
+            accept_invalid_certs: env::var("HTTP_ACCEPT_INVALID_CERTS")
+                .unwrap_or("false".to_string())
+                .parse()
+                .expect("HTTP_ACCEPT_INVALID_CERTS must be true or false"),
+        })
+    }
+
+    pub fn build(&self) -> Result<Client> {
+        let mut builder = Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout)
+            .timeout(self.timeout)
+            .user_agent(&self.user_agent)
+            .danger_accept_invalid_certs(self.accept_invalid_certs);
+
+        if let Some(proxy_url) = &self.proxy {
+            let mut proxy = Proxy::all(proxy_url)
+                .map_err(|e| anyhow!("Invalid HTTP_PROXY_URL {}: {}", proxy_url, e))?;
+            if let Some(username) = &self.proxy_username {
+                proxy = proxy.basic_auth(username, self.proxy_password.as_deref().unwrap_or(""));
+            }
+            if let Some(no_proxy) = &self.no_proxy {
+                proxy = proxy.no_proxy(NoProxy::from_string(no_proxy));
+            }
+            builder = builder.proxy(proxy);
+        }
+
+        if let Some(path) = &self.ca_cert {
+            let pem = fs::read(path)
+                .map_err(|e| anyhow!("Failed to read HTTP_CA_CERT {:?}: {}", path, e))?;
+            let cert = Certificate::from_pem(&pem)
+                .map_err(|e| anyhow!("HTTP_CA_CERT {:?} is not a PEM certificate: {}", path, e))?;
+            builder = builder.add_root_certificate(cert);
+        }
+
+        if let Some(version) = self.min_tls_version {
+            builder = builder.min_tls_version(version);
+        }
+
+        Ok(builder.build()?)
+    }
+}
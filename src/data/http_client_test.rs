@@ -0,0 +1,86 @@
+use super::http_client::ClientSettings;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+
+#[tokio::test]
+async fn test_sends_through_proxy() {
+    // A proxy receives the absolute URL of the target in the request line
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buffer = [0u8; 4096];
+        let read = socket.read(&mut buffer).await.unwrap();
+        let _ = sender.send(String::from_utf8_lossy(&buffer[..read]).to_string());
+        let _ = socket
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+            .await;
+    });
+
+    let client = ClientSettings {
+        proxy: Some(proxy),
+        proxy_username: Some("user".to_string()),
+        proxy_password: Some("secret".to_string()),
+        ..ClientSettings::default()
+    }
+    .build()
+    .unwrap();
+    let response = client
+        .get("http://api.example.com/v1/ohlcv")
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let request = receiver.await.unwrap();
+    assert!(
+        request.starts_with("GET http://api.example.com/v1/ohlcv HTTP/1.1"),
+        "{}",
+        request
+    );
+    // base64("user:secret")
+    assert!(request.contains("proxy-authorization: Basic dXNlcjpzZWNyZXQ="));
+    assert!(request.contains("user-agent: volatility_estimator/"));
+}
+
+#[tokio::test]
+async fn test_times_out_slow_responses() {
+    // Accepts the connection but never answers
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (_socket, _) = listener.accept().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    });
+
+    let client = ClientSettings {
+        read_timeout: Duration::from_millis(100),
+        ..ClientSettings::default()
+    }
+    .build()
+    .unwrap();
+    let started = Instant::now();
+    let error = client.get(&url).send().await.unwrap_err();
+    assert!(error.is_timeout(), "{}", error);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_rejects_bad_settings() {
+    let settings = ClientSettings {
+        proxy: Some("not a url".to_string()),
+        ..ClientSettings::default()
+    };
+    assert!(settings.build().is_err());
+
+    let settings = ClientSettings {
+        ca_cert: Some(PathBuf::from("/nonexistent/ca.pem")),
+        ..ClientSettings::default()
+    };
+    let error = settings.build().unwrap_err();
+    assert!(error.to_string().contains("HTTP_CA_CERT"), "{}", error);
+}
@@ -0,0 +1,496 @@
+use super::binance::get_binance_data;
+use super::binance::parse_binance_klines;
+use super::bitfinex::get_bitfinex_data;
+use super::candles::CandlePages;
+use super::chainlink::read_chainlink_bars;
+use super::coinapi::fetch_coin_api_data;
+use super::coinbase::get_coinbase_data;
+use super::http::{redact_url, Http, HttpMode};
+use super::kraken::{get_kraken_data, get_kraken_trades};
+use super::polygon2::fetch_polygon_data;
+use super::sources::Source;
+use super::uniswap::{read_uniswap_bars, PoolVersion, UniswapPool};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use reqwest::{Client, Url};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/data/fixtures/http");
+
+// Responses of the same requests failing
+const ERROR_FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/data/fixtures/http_errors");
+
+// Windows served over several pages
+const PAGED_FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/data/fixtures/paged");
+
+// Replayed JSON-RPC requests never reach this
+const RPC_URL: &str = "http://127.0.0.1:8545";
+
+#[tokio::test]
+async fn test_replays_recorded_kraken_ohlc() {
+    let http = Http::new(HttpMode::Replay, Client::new(), FIXTURES);
+    let source_bars = get_kraken_data(&http, "minute", 2).await.unwrap();
+
+    assert_eq!(source_bars.timestamp_failures, 0);
+    assert_eq!(
+        source_bars.bars,
+        vec![
+            (DateTime::from_timestamp(1717200000, 0).unwrap(), 2500.0),
+            (DateTime::from_timestamp(1717200060, 0).unwrap(), 2501.0),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_replays_recorded_error_status() {
+    let http = Http::new(HttpMode::Replay, Client::new(), FIXTURES);
+    let error = get_binance_data(&http, "minute", 2).await.unwrap_err();
+
+    assert!(error.to_string().contains("451"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_replays_polygon_plan_limitation() {
+    let http = Http::new(HttpMode::Replay, Client::new(), FIXTURES);
+    let error = fetch_polygon_data(&http, "test", "second", 120)
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("plan limitations"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_replays_polygon_next_url_pages() {
+    let http = Http::new(HttpMode::Replay, Client::new(), PAGED_FIXTURES);
+    let source_bars = fetch_polygon_data(&http, "test", "minute", 3)
+        .await
+        .unwrap();
+
+    // Both pages, in order, and nothing asked for past the last one
+    assert_eq!(
+        source_bars.bars,
+        vec![
+            (DateTime::from_timestamp(1717200000, 0).unwrap(), 2500.25),
+            (DateTime::from_timestamp(1717200060, 0).unwrap(), 2501.75),
+            (DateTime::from_timestamp(1717200120, 0).unwrap(), 2503.0),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_replays_kraken_trade_pages() {
+    let http = Http::new(HttpMode::Replay, Client::new(), PAGED_FIXTURES);
+    let start = DateTime::from_timestamp(1717200000, 0).unwrap();
+    let end = DateTime::from_timestamp(1717200090, 0).unwrap();
+    let (trades, timestamp_failures) = get_kraken_trades(&http, "ETHPYUSD", start, end, 5)
+        .await
+        .unwrap();
+
+    // Three pages followed through `last`, stopping at the first trade past the end
+    assert_eq!(timestamp_failures, 0);
+    let prices: Vec<f64> = trades.iter().map(|&(_, price, _)| price).collect();
+    assert_eq!(prices, vec![2500.0, 2501.0, 2502.0, 2503.0, 2504.0]);
+    assert_eq!(
+        trades[3].0,
+        DateTime::from_timestamp_micros(1717200061250000).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_kraken_trades_error_when_pages_run_out() {
+    let http = Http::new(HttpMode::Replay, Client::new(), PAGED_FIXTURES);
+    let start = DateTime::from_timestamp(1717200000, 0).unwrap();
+    let end = DateTime::from_timestamp(1717200090, 0).unwrap();
+    let error = get_kraken_trades(&http, "ETHPYUSD", start, end, 2)
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("more than 2 pages"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_kraken_trades_stop_when_cursor_stalls() {
+    // The only page hands back the cursor it was given, so there is nothing newer to page to
+    let http = Http::new(
+        HttpMode::Replay,
+        Client::new(),
+        concat!(env!("CARGO_MANIFEST_DIR"), "/src/data/fixtures/paged_idle"),
+    );
+    let start = DateTime::from_timestamp(1717200000, 0).unwrap();
+    let end = DateTime::from_timestamp(1717300000, 0).unwrap();
+    let (trades, _) = get_kraken_trades(&http, "ETHPYUSD", start, end, 5)
+        .await
+        .unwrap();
+
+    assert_eq!(trades, vec![(start, 2500.0, 0.5)]);
+}
+
+#[tokio::test]
+async fn test_missing_recording_is_an_error() {
+    // Only the first Kraken response is recorded
+    let http = Http::new(HttpMode::Replay, Client::new(), FIXTURES);
+    get_kraken_data(&http, "minute", 2).await.unwrap();
+    let error = get_kraken_data(&http, "minute", 2).await.unwrap_err();
+
+    assert!(
+        error.to_string().contains("No recorded Kraken response #1"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_redacts_api_keys() {
+    let url = Url::parse("https://api.polygon.io/v2/aggs?adjusted=true&apiKey=secret").unwrap();
+    assert_eq!(
+        redact_url(Source::Polygon, &url),
+        "https://api.polygon.io/v2/aggs?adjusted=true"
+    );
+
+    let url = Url::parse("https://api.polygon.io/v2/aggs?apiKey=secret").unwrap();
+    assert_eq!(
+        redact_url(Source::Polygon, &url),
+        "https://api.polygon.io/v2/aggs"
+    );
+}
+
+#[test]
+fn test_redacts_rpc_urls_entirely() {
+    // Hosted nodes take the key as part of the path
+    let url = Url::parse("https://eth-mainnet.g.alchemy.com/v2/secret").unwrap();
+    for source in [Source::Uniswap, Source::Chainlink] {
+        assert_eq!(redact_url(source, &url), "[ETH_RPC_URL]");
+    }
+}
+
+#[tokio::test]
+async fn test_replays_recorded_coinapi_ohlcv() {
+    let http = Http::new(HttpMode::Replay, Client::new(), FIXTURES);
+    let source_bars = fetch_coin_api_data(
+        &http,
+        "test",
+        "minute",
+        2,
+        Utc.with_ymd_and_hms(2024, 9, 1, 10, 2, 0).unwrap(),
+    )
+    .await
+    .unwrap();
+
+    // Stamped with `time_period_start` and priced at the average of OHLC
+    assert_eq!(source_bars.timestamp_failures, 0);
+    assert_eq!(
+        source_bars.bars,
+        vec![
+            (Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap(), 2500.5),
+            (Utc.with_ymd_and_hms(2024, 9, 1, 10, 1, 0).unwrap(), 2501.5),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_replays_coinapi_pages() {
+    // 1000 bars, then the 2 still missing from where the first page ended
+    let paged_end = Utc.with_ymd_and_hms(2024, 9, 1, 16, 42, 0).unwrap();
+    let http = Http::new(HttpMode::Replay, Client::new(), PAGED_FIXTURES);
+    let source_bars = fetch_coin_api_data(&http, "test", "minute", 1002, paged_end)
+        .await
+        .unwrap();
+
+    assert_eq!(source_bars.bars.len(), 1002);
+    assert!(source_bars
+        .bars
+        .windows(2)
+        .all(|pair| pair[1].0 - pair[0].0 == chrono::Duration::minutes(1)));
+    assert_eq!(
+        source_bars.bars[1001].0,
+        Utc.with_ymd_and_hms(2024, 9, 1, 16, 41, 0).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_coinapi_stops_paging_on_low_quota() {
+    // 5 request units left, too few for the next 1000 bars, so only one page is fetched
+    let paged_end = Utc.with_ymd_and_hms(2024, 9, 2, 9, 20, 0).unwrap();
+    let http = Http::new(
+        HttpMode::Replay,
+        Client::new(),
+        concat!(env!("CARGO_MANIFEST_DIR"), "/src/data/fixtures/paged_quota"),
+    );
+    let source_bars = fetch_coin_api_data(&http, "test", "minute", 2000, paged_end)
+        .await
+        .unwrap();
+
+    assert_eq!(source_bars.bars.len(), 1000);
+}
+
+#[tokio::test]
+async fn test_replays_coinapi_invalid_key() {
+    let http = Http::new(HttpMode::Replay, Client::new(), ERROR_FIXTURES);
+    let error = fetch_coin_api_data(
+        &http,
+        "test",
+        "minute",
+        2,
+        Utc.with_ymd_and_hms(2024, 9, 1, 10, 2, 0).unwrap(),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(error.to_string().contains("401"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_replays_recorded_coinbase_candles() {
+    let http = Http::new(HttpMode::Replay, Client::new(), FIXTURES);
+    let source_bars = get_coinbase_data(&http, "minute", 2).await.unwrap();
+
+    // Newest first on the wire, oldest first once parsed
+    assert_eq!(
+        source_bars.bars,
+        vec![
+            (Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap(), 2500.5),
+            (Utc.with_ymd_and_hms(2024, 9, 1, 10, 1, 0).unwrap(), 2501.5),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_replays_coinbase_error_status() {
+    let http = Http::new(HttpMode::Replay, Client::new(), ERROR_FIXTURES);
+    let error = get_coinbase_data(&http, "minute", 2).await.unwrap_err();
+
+    assert!(error.to_string().contains("404"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_replays_recorded_bitfinex_candles() {
+    let http = Http::new(HttpMode::Replay, Client::new(), FIXTURES);
+    let source_bars = get_bitfinex_data(&http, "minute", 2).await.unwrap();
+
+    assert_eq!(
+        source_bars.bars,
+        vec![
+            (Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap(), 2500.5),
+            (Utc.with_ymd_and_hms(2024, 9, 1, 10, 1, 0).unwrap(), 2501.5),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_replays_bitfinex_error_status() {
+    let http = Http::new(HttpMode::Replay, Client::new(), ERROR_FIXTURES);
+    let error = get_bitfinex_data(&http, "minute", 2).await.unwrap_err();
+
+    assert!(error.to_string().contains("500"), "{}", error);
+}
+
+// Binance klines paged two at a time, so a five minute window takes three requests
+fn binance_pages(max_pages: usize) -> CandlePages {
+    CandlePages {
+        source: Source::Binance,
+        page_limit: 2,
+        max_pages,
+    }
+}
+
+fn binance_page_url(start_time: DateTime<Utc>, end_time: DateTime<Utc>) -> String {
+    format!(
+        "https://api.binance.com/api/v3/klines?symbol=ETHUSDT&interval=1m&startTime={}&endTime={}&limit=2",
+        start_time.timestamp_millis(),
+        end_time.timestamp_millis()
+    )
+}
+
+#[tokio::test]
+async fn test_replays_candle_pages() {
+    let http = Http::new(HttpMode::Replay, Client::new(), PAGED_FIXTURES);
+    let start = Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap();
+    let end = start + Duration::minutes(5);
+    let source_bars = binance_pages(5)
+        .fetch(
+            &http,
+            start,
+            end,
+            Duration::minutes(1),
+            |start_time| binance_page_url(start_time, end),
+            parse_binance_klines,
+        )
+        .await
+        .unwrap();
+
+    // Each page starts one minute past the last kline of the one before
+    let opens: Vec<_> = source_bars.bars.iter().map(|&(open, _)| open).collect();
+    let expected: Vec<_> = (0..5).map(|i| start + Duration::minutes(i)).collect();
+    assert_eq!(opens, expected);
+}
+
+#[tokio::test]
+async fn test_candle_pages_error_when_pages_run_out() {
+    let http = Http::new(HttpMode::Replay, Client::new(), PAGED_FIXTURES);
+    let start = Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap();
+    let end = start + Duration::minutes(5);
+    let error = binance_pages(2)
+        .fetch(
+            &http,
+            start,
+            end,
+            Duration::minutes(1),
+            |start_time| binance_page_url(start_time, end),
+            parse_binance_klines,
+        )
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("more than 2 pages"), "{}", error);
+}
+
+fn uniswap_pool() -> UniswapPool {
+    UniswapPool {
+        address: "0x88e6A0c2dDD26FEEb64F039a2c41296fcB3f5640".to_string(),
+        version: PoolVersion::V3,
+        token0_decimals: 6,
+        token1_decimals: 18,
+        base_is_token1: true,
+    }
+}
+
+#[tokio::test]
+async fn test_replays_recorded_uniswap_swaps() {
+    // A three block chain: the search for the window's first block, then the logs, one of
+    // them without a timestamp so its block is looked up
+    let http = Http::new(HttpMode::Replay, Client::new(), FIXTURES);
+    let window_start = Utc.with_ymd_and_hms(2024, 9, 1, 10, 4, 0).unwrap();
+    let source_bars = read_uniswap_bars(
+        &http,
+        RPC_URL,
+        &uniswap_pool(),
+        2000,
+        "minute",
+        window_start,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(source_bars.timestamp_failures, 0);
+    assert_eq!(
+        source_bars.bars,
+        vec![
+            (Utc.with_ymd_and_hms(2024, 9, 1, 10, 4, 0).unwrap(), 2500.25),
+            (Utc.with_ymd_and_hms(2024, 9, 1, 10, 5, 0).unwrap(), 2500.25),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_replays_uniswap_rpc_error() {
+    let http = Http::new(HttpMode::Replay, Client::new(), ERROR_FIXTURES);
+    let window_start = Utc.with_ymd_and_hms(2024, 9, 1, 10, 4, 0).unwrap();
+    let error = read_uniswap_bars(
+        &http,
+        RPC_URL,
+        &uniswap_pool(),
+        2000,
+        "minute",
+        window_start,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(
+        error.to_string().contains("daily request count exceeded"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_uniswap_halves_range_on_too_many_results() {
+    // The first eth_getLogs is refused for returning too many results and retried with half the range
+    let http = Http::new(
+        HttpMode::Replay,
+        Client::new(),
+        concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/data/fixtures/uniswap_range"
+        ),
+    );
+    let window_start = Utc.with_ymd_and_hms(2024, 9, 1, 10, 4, 0).unwrap();
+    let source_bars = read_uniswap_bars(
+        &http,
+        RPC_URL,
+        &uniswap_pool(),
+        2000,
+        "minute",
+        window_start,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(source_bars.bars.len(), 2);
+}
+
+#[tokio::test]
+async fn test_uniswap_fails_on_other_log_errors() {
+    // A rate limited eth_getLogs fails the read instead of being retried with a smaller range
+    let http = Http::new(
+        HttpMode::Replay,
+        Client::new(),
+        concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/data/fixtures/uniswap_limited"
+        ),
+    );
+    let window_start = Utc.with_ymd_and_hms(2024, 9, 1, 10, 4, 0).unwrap();
+    let error = read_uniswap_bars(
+        &http,
+        RPC_URL,
+        &uniswap_pool(),
+        2000,
+        "minute",
+        window_start,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(
+        error.to_string().contains("daily request count exceeded"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_replays_recorded_chainlink_rounds() {
+    // The latest round is inside the window, so the rounds before it are read back in a batch
+    // where all but the first revert
+    let http = Http::new(HttpMode::Replay, Client::new(), FIXTURES);
+    let start = Utc.with_ymd_and_hms(2024, 9, 1, 9, 58, 0).unwrap();
+    let end = Utc.with_ymd_and_hms(2024, 9, 1, 10, 1, 0).unwrap();
+    let source_bars = read_chainlink_bars(&http, RPC_URL, "0xfeed", "minute", start, end)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        source_bars.bars,
+        vec![
+            (start, 2499.75),
+            (Utc.with_ymd_and_hms(2024, 9, 1, 9, 59, 0).unwrap(), 2500.25),
+            (Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap(), 2500.25),
+            (end, 2500.25),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_replays_chainlink_reverted_call() {
+    let http = Http::new(HttpMode::Replay, Client::new(), ERROR_FIXTURES);
+    let start = Utc.with_ymd_and_hms(2024, 9, 1, 9, 58, 0).unwrap();
+    let end = Utc.with_ymd_and_hms(2024, 9, 1, 10, 1, 0).unwrap();
+    let error = read_chainlink_bars(&http, RPC_URL, "0xfeed", "minute", start, end)
+        .await
+        .unwrap_err();
+
+    assert!(
+        error.to_string().contains("execution reverted"),
+        "{}",
+        error
+    );
+}
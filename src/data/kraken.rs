@@ -1,6 +1,12 @@
+use super::bars::{value_to_f64, SourceBars};
+use super::http::{base_url, Http};
+use super::rounding::{period_duration, periods_before, round_to_period, BarStamp};
+use super::sources::Source;
+use super::timestamp::{parse_epoch, parse_timestamp};
+use super::trade_bars::{build_trade_bars, Trade};
 use anyhow::Result;
-use chrono::{NaiveDateTime, TimeZone, Utc};
-use reqwest::Client;
+use chrono::{DateTime, Utc};
+use log::{debug, error};
 use serde::Deserialize;
 use serde_json::Value;
 use std::collections::HashMap;
@@ -8,60 +14,102 @@ use std::collections::HashMap;
 // Define a struct to hold the response from the Kraken API
 #[derive(Deserialize, Debug)]
 struct KrakenApiResponse {
-    result: KrakenResult,
+    #[serde(default)]
+    error: Vec<String>,
+    result: Option<KrakenResult>,
 }
 
+// OHLC and Trades share a layout: rows keyed by pair, plus a `last` cursor
 #[derive(Deserialize, Debug)]
 struct KrakenResult {
-    #[serde(rename = "last")]
-    _last: Value, // We don't need this field, so use `Value` to skip it
+    last: Value, // OHLC: last bar time in seconds, Trades: last trade time in nanoseconds
     #[serde(flatten)]
     ohlc: HashMap<String, Vec<Vec<Value>>>,
 }
 
+// Kraken's ETH / PYUSD pair
+pub const ASSET_ID: &str = "ETHPYUSD";
+
+const KRAKEN_API_URL: &str = "https://api.kraken.com";
+
+// Kraken's OHLC endpoint only ever returns the most recent 720 bars
+const MAX_OHLC_BARS: i64 = 720;
+
+// Each Trades call returns up to 1000 trades, cap the pages walked for one window
+const MAX_TRADE_PAGES: usize = 500;
+
+// Kraken stamps OHLC bars with their open time
+pub const BAR_STAMP: BarStamp = BarStamp::Open;
+
 // Function to fetch Kraken OHLC data
 pub async fn get_kraken_data(
+    http: &Http,
     time_period: &str,
-) -> Result<Vec<(NaiveDateTime, f64)>, anyhow::Error> {
-    let asset_id = "ETHPYUSD";
+    no_of_periods: i64,
+) -> Result<SourceBars, anyhow::Error> {
+    let asset_id = ASSET_ID;
+
+    // Start of the requested window
+    let window_start = periods_before(Utc::now(), time_period, no_of_periods)?;
 
     // Convert time_period to the correct interval in minutes for Kraken API
     let interval_minutes = match time_period {
         "minute" => 1, // 1 minute
         "hour" => 60,  // 60 minutes
         "day" => 1440, // 1440 minutes (24 hours)
+        "second" => {
+            // No sub-minute OHLC on Kraken, so build the bars from raw trades
+            let (trades, timestamp_failures) =
+                get_kraken_trades(http, asset_id, window_start, Utc::now(), MAX_TRADE_PAGES)
+                    .await?;
+            let bars = build_trade_bars(&trades, period_duration(time_period))
+                .into_iter()
+                .map(|(bar_open, bar)| {
+                    debug!(
+                        "Kraken trade bar {} vwap {} volume {} trades {}",
+                        bar_open, bar.vwap, bar.volume, bar.trade_count
+                    );
+                    (bar_open, bar.vwap)
+                })
+                .collect();
+            return Ok(SourceBars {
+                bars,
+                timestamp_failures,
+                truncated: false,
+            });
+        }
         _ => {
             return Err(anyhow::anyhow!(
                 "Unsupported time period provided for Kraken data"
             ))
-        } // Return an error for unsupported time periods (like seconds)
+        } // Return an error for unsupported time periods
     };
 
     // Construct the actual URL
     let url = format!(
-        "https://api.kraken.com/0/public/OHLC?pair={}&interval={}",
-        asset_id, interval_minutes
+        "{}/0/public/OHLC?pair={}&interval={}&since={}",
+        base_url(Source::Kraken, KRAKEN_API_URL),
+        asset_id,
+        interval_minutes,
+        window_start.timestamp()
     );
 
-    let client = Client::new();
-
     // Make the request to API
-    let response = client
-        .get(&url)
-        .send()
+    let response = http
+        .send(Source::Kraken, http.get(&url))
         .await?
-        .json::<KrakenApiResponse>()
-        .await?;
+        .json::<KrakenApiResponse>()?;
+    let result = kraken_result(response)?;
 
     // Extract OHLC data
-    let ohlc_data = response
-        .result
+    let ohlc_data = result
         .ohlc
         .get(asset_id)
         .ok_or_else(|| anyhow::anyhow!("No OHLC data found for the specified pair"))?;
 
-    // Parse the average of OHLC into vec(NaiveDateTime, f64)
-    let parsed_ohlc: Vec<(NaiveDateTime, f64)> = ohlc_data
+    // Parse the average of OHLC into vec(DateTime<Utc>, f64)
+    let mut timestamp_failures = 0;
+    let mut parsed_ohlc: Vec<(DateTime<Utc>, f64)> = ohlc_data
         .iter()
         .filter_map(|ohlc| {
             if ohlc.len() < 5 {
@@ -69,14 +117,15 @@ pub async fn get_kraken_data(
             }
 
             // ohlc[0] contains the timestamp, which may be an integer or string
-            let timestamp = match &ohlc[0] {
-                Value::Number(n) => n.as_i64().unwrap_or(0),
-                Value::String(s) => s.parse::<i64>().unwrap_or(0),
-                _ => return None,
+            let datetime = match &ohlc[0] {
+                Value::Number(n) => n.as_i64().and_then(parse_epoch),
+                Value::String(s) => parse_timestamp(s).ok(),
+                _ => None,
+            };
+            let Some(datetime) = datetime else {
+                timestamp_failures += 1;
+                return None;
             };
-
-            // Convert timestamp to NaiveDateTime using Utc and then naive_utc
-            let datetime = Utc.timestamp_opt(timestamp, 0).single()?.naive_utc();
 
             // ohlc[1] to ohlc[4] contain the open, high, low, and close prices
             let open_price = match &ohlc[1] {
@@ -110,5 +159,131 @@ pub async fn get_kraken_data(
         })
         .collect();
 
-    Ok(parsed_ohlc)
+    // OHLC history stops at 720 bars, rebuild anything older from the Trades endpoint
+    let oldest_bar = parsed_ohlc.iter().map(|&(timestamp, _)| timestamp).min();
+    let history_end = oldest_bar.unwrap_or_else(Utc::now);
+    let mut truncated = false;
+    if no_of_periods > MAX_OHLC_BARS && history_end > window_start {
+        debug!(
+            "Kraken OHLC starts at {}, fetching trades back to {}",
+            history_end, window_start
+        );
+        match get_kraken_trades(http, asset_id, window_start, history_end, MAX_TRADE_PAGES).await {
+            Ok((trades, trade_failures)) => {
+                // Price the rebuilt bars like the OHLC bars, as the average of o, h, l and c.
+                // The bar holding `history_end` is already covered by the OHLC endpoint.
+                let history_end_bar = round_to_period(history_end, time_period);
+                parsed_ohlc.extend(
+                    build_trade_bars(&trades, period_duration(time_period))
+                        .into_iter()
+                        .filter(|&(bar_open, _)| bar_open < history_end_bar)
+                        .map(|(bar_open, bar)| (bar_open, bar.ohlc_average())),
+                );
+                timestamp_failures += trade_failures;
+            }
+            Err(e) => {
+                // Keep the OHLC bars, the window is just partially covered
+                error!("Failed to fetch Kraken trade history: {}", e);
+                truncated = true;
+            }
+        }
+    }
+
+    Ok(SourceBars {
+        bars: parsed_ohlc,
+        timestamp_failures,
+        truncated,
+    })
+}
+
+// Unwrap the result of a Kraken response, surfacing the API's own error messages
+fn kraken_result(response: KrakenApiResponse) -> Result<KrakenResult> {
+    if !response.error.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Kraken API error: {}",
+            response.error.join(", ")
+        ));
+    }
+    response
+        .result
+        .ok_or_else(|| anyhow::anyhow!("No result in Kraken API response"))
+}
+
+// Page forward through public trades from `start` until `end`, using `last` as the cursor.
+// Returns the trades and the number of rows whose time couldn't be read. Trades only page
+// forward, so running out of pages is an error rather than a window missing its newest bars.
+pub async fn get_kraken_trades(
+    http: &Http,
+    asset_id: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    max_pages: usize,
+) -> Result<(Vec<Trade>, usize)> {
+    let mut trades = vec![];
+    let mut timestamp_failures = 0;
+    // Trades accepts `since` in nanoseconds
+    let mut since = start.timestamp_nanos_opt().unwrap_or(0).to_string();
+
+    for _ in 0..max_pages {
+        let url = format!(
+            "{}/0/public/Trades?pair={}&since={}",
+            base_url(Source::Kraken, KRAKEN_API_URL),
+            asset_id,
+            since
+        );
+        debug!("Kraken trades URL: {}", url);
+
+        let response = http
+            .send(Source::Kraken, http.get(&url))
+            .await?
+            .json::<KrakenApiResponse>()?;
+        let result = kraken_result(response)?;
+
+        let rows = result
+            .ohlc
+            .get(asset_id)
+            .ok_or_else(|| anyhow::anyhow!("No trade data found for the specified pair"))?;
+
+        // Each trade is [price, volume, time, buy/sell, market/limit, misc, trade_id]
+        let mut reached_end = rows.is_empty();
+        for row in rows {
+            let (Some(price), Some(volume)) = (
+                row.first().and_then(value_to_f64),
+                row.get(1).and_then(value_to_f64),
+            ) else {
+                continue;
+            };
+            let Some(timestamp) = row
+                .get(2)
+                .and_then(value_to_f64)
+                .and_then(|time| DateTime::from_timestamp_micros((time * 1_000_000.0) as i64))
+            else {
+                timestamp_failures += 1;
+                continue;
+            };
+            if timestamp >= end {
+                reached_end = true;
+                break;
+            }
+            trades.push((timestamp, price, volume));
+        }
+
+        let next_since = match &result.last {
+            Value::String(s) => s.clone(),
+            Value::Number(n) => n.to_string(),
+            _ => return Err(anyhow::anyhow!("Missing `last` cursor in Kraken trades")),
+        };
+        // The cursor stops moving once there are no newer trades
+        if reached_end || next_since == since {
+            return Ok((trades, timestamp_failures));
+        }
+        since = next_since;
+    }
+
+    Err(anyhow::anyhow!(
+        "Kraken trades from {} to {} need more than {} pages, shorten the window",
+        start,
+        end,
+        max_pages
+    ))
 }
@@ -0,0 +1,135 @@
+use super::bars::SourceBars;
+use super::cache::{BarCache, CacheMode};
+use super::http::{Http, HttpMode};
+use super::quality::QualityReport;
+use super::rounding::BarStamp;
+use super::sources::Source;
+use super::{last_bar_open, merge_source_bars, PriceRow, ResultsMap, SourceFetcher};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use reqwest::Client;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+fn at(minute: u32, second: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 9, 1, 10, minute, second)
+        .unwrap()
+}
+
+#[test]
+fn test_last_bar_open_excludes_partial_bar() {
+    // 10:05:30 is inside the bar opened at 10:05, which is still open
+    assert_eq!(last_bar_open(at(5, 30), "minute", true), at(4, 0));
+    assert_eq!(last_bar_open(at(5, 30), "minute", false), at(5, 0));
+}
+
+#[test]
+fn test_merge_skips_bars_after_last_bar_open() {
+    let mut map: HashMap<DateTime<Utc>, PriceRow> = HashMap::new();
+    let source_bars = SourceBars {
+        bars: vec![(at(3, 0), 2500.0), (at(4, 0), 2501.0), (at(5, 0), 2502.0)],
+        ..Default::default()
+    };
+
+    let quality = merge_source_bars(
+        &mut map,
+        source_bars,
+        BarStamp::Open,
+        "minute",
+        last_bar_open(at(5, 30), "minute", true),
+        Source::Kraken,
+    );
+
+    // The partial 10:05 bar is counted but never reaches the map
+    assert_eq!(quality.bars_received, 3);
+    assert_eq!(quality.bars_merged, 2);
+    assert_eq!(quality.outside_window, 1);
+    assert!(!map.contains_key(&at(5, 0)));
+    assert_eq!(map[&at(4, 0)].prices[&Source::Kraken], 2501.0);
+}
+
+#[test]
+fn test_merge_normalizes_close_stamps() {
+    let mut map: HashMap<DateTime<Utc>, PriceRow> = HashMap::new();
+    let source_bars = SourceBars {
+        bars: vec![(at(4, 0), 2500.0), (at(5, 0), 2501.0)],
+        ..Default::default()
+    };
+
+    let quality = merge_source_bars(
+        &mut map,
+        source_bars,
+        BarStamp::Close,
+        "minute",
+        at(4, 0),
+        Source::Polygon,
+    );
+
+    // Stamped at their close, the bars belong to 10:03 and 10:04
+    assert_eq!(quality.bars_merged, 2);
+    assert_eq!(quality.outside_window, 0);
+    assert_eq!(
+        map[&(at(4, 0) - Duration::minutes(1))].prices[&Source::Polygon],
+        2500.0
+    );
+    assert_eq!(map[&at(4, 0)].prices[&Source::Polygon], 2501.0);
+}
+
+#[test]
+fn test_merge_counts_timestamp_failures() {
+    let mut map: HashMap<DateTime<Utc>, PriceRow> = HashMap::new();
+    let source_bars = SourceBars {
+        bars: vec![(at(4, 0), 2500.0)],
+        timestamp_failures: 2,
+        truncated: false,
+    };
+
+    let quality = merge_source_bars(
+        &mut map,
+        source_bars,
+        BarStamp::Open,
+        "minute",
+        at(4, 0),
+        Source::Kraken,
+    );
+
+    // Rows dropped for their timestamp still count as received
+    assert_eq!(quality.bars_received, 3);
+    assert_eq!(quality.bars_merged, 1);
+    assert_eq!(quality.timestamp_failures, 2);
+}
+
+#[tokio::test]
+async fn test_fetcher_reports_timestamp_failures() {
+    let results_map: ResultsMap = Arc::new(RwLock::new(HashMap::new()));
+    let quality_report: QualityReport = Arc::new(RwLock::new(BTreeMap::new()));
+    let http = Arc::new(Http::new(HttpMode::Replay, Client::new(), ""));
+    let bar_cache = Arc::new(BarCache::new(CacheMode::Off, "", Duration::zero()));
+    let fetcher = SourceFetcher {
+        results_map: &results_map,
+        quality_report: &quality_report,
+        http: &http,
+        bar_cache: &bar_cache,
+        time_period: "minute",
+        no_of_periods: 2,
+        window_end: at(5, 30),
+        last_bar_open: at(4, 0),
+    };
+
+    fetcher
+        .fetch(Source::Kraken, BarStamp::Open, |_, _, _| async {
+            Ok(SourceBars {
+                bars: vec![(at(3, 0), 2500.0), (at(4, 0), 2501.0), (at(5, 0), 2502.0)],
+                timestamp_failures: 1,
+                truncated: false,
+            })
+        })
+        .await;
+
+    let quality_report = quality_report.read().unwrap();
+    let quality = &quality_report[&Source::Kraken];
+    assert_eq!(quality.bars_received, 4);
+    assert_eq!(quality.bars_merged, 2);
+    assert_eq!(quality.outside_window, 1);
+    assert_eq!(quality.timestamp_failures, 1);
+    assert_eq!(results_map.read().unwrap().len(), 2);
+}
@@ -1,14 +1,17 @@
+use super::bars::SourceBars;
+use super::http::{base_url, Http};
+use super::rounding::BarStamp;
+use super::sources::Source;
 use anyhow::{anyhow, Result};
-use chrono::{Duration, NaiveDateTime, Utc}; // Make sure to import chrono::Duration
+use chrono::{DateTime, Duration, Utc}; // Make sure to import chrono::Duration
 use log::{debug, error};
-use reqwest::Client;
 use serde::Deserialize;
 use std::env;
-use std::time::Duration as StdDuration; // Rename to avoid conflict with `chrono::Duration`
 
 #[derive(Deserialize, Debug)]
 struct PolygonApiResponse {
     results: Option<Vec<PolygonData>>,
+    next_url: Option<String>, // Present while more pages of results remain
 }
 
 #[derive(Deserialize, Debug)]
@@ -25,27 +28,49 @@ struct PolygonData {
     vw: f64, // Volume-weighted average price
 }
 
+// Polygon's ETH / USD crypto ticker
+pub const ASSET_ID: &str = "X:ETHUSD";
+
+// Polygon aggregates are stamped with the start of the aggregate window
+pub const BAR_STAMP: BarStamp = BarStamp::Open;
+
+// Largest page Polygon serves, anything bigger is split across `next_url` pages
+const PAGE_LIMIT: usize = 50_000;
+
+// Guard against a `next_url` chain that never ends
+const MAX_PAGES: usize = 100;
+
 pub async fn get_polygon_data(
+    http: &Http,
     time_period: &str,
     no_of_periods: i64,
-) -> Result<Vec<(NaiveDateTime, f64)>, anyhow::Error> {
-    let asset_id = "X:ETHUSD";
-
-    // Set the API key and URL
+) -> Result<SourceBars, anyhow::Error> {
+    // Set the API key
     let api_key = env::var("POLYGON_API_KEY").expect("POLYGON_API_KEY not found in .env");
 
     debug!("Api key: {}", api_key);
 
-    let api_url = format!("https://api.polygon.io/v2/aggs/ticker/{}/range", asset_id);
+    fetch_polygon_data(http, &api_key, time_period, no_of_periods).await
+}
 
-    debug!("Api url: {}", api_url);
+// Fetch the aggregates over the window with the given API key
+pub async fn fetch_polygon_data(
+    http: &Http,
+    api_key: &str,
+    time_period: &str,
+    no_of_periods: i64,
+) -> Result<SourceBars> {
+    let asset_id = ASSET_ID;
 
-    let client = Client::new();
+    let api_url = format!(
+        "{}/v2/aggs/ticker/{}/range",
+        base_url(Source::Polygon, "https://api.polygon.io"),
+        asset_id
+    );
 
-    // Default multiplier to 1
-    let multiplier = 1;
+    debug!("Api url: {}", api_url);
 
-    // Calculate the start and end dates for API based on timespan and no_of_periods
+    // Calculate the start and end times for API based on timespan and no_of_periods
     let end_date = Utc::now();
     let start_date = match time_period {
         "second" => end_date - Duration::seconds(no_of_periods),
@@ -55,37 +80,75 @@ pub async fn get_polygon_data(
         _ => return Err(anyhow!("Invalid timespan provided")), // Return an error for invalid timespan
     };
 
-    // Format the dates as required by API (in this case, assuming "YYYY-MM-DD")
-    let start_date_str = start_date.format("%Y-%m-%d").to_string();
-    let end_date_str = end_date.format("%Y-%m-%d").to_string();
+    // Polygon accepts millisecond timestamps as well as dates, so the window is exact
+    let start_millis = start_date.timestamp_millis();
+    let end_millis = end_date.timestamp_millis();
 
-    debug!("Start date string: {}", start_date_str);
-    debug!("End date string: {}", end_date_str);
+    debug!("Start time: {} ({})", start_date, start_millis);
+    debug!("End time: {} ({})", end_date, end_millis);
 
-    // Build the final query URL
-    let url = format!(
-        "{}/{}/{}/{}/{}?apiKey={}",
-        api_url, multiplier, time_period, start_date_str, end_date_str, api_key
+    // Build the final query URL, one bar per period to match the estimate's grid
+    let mut url = format!(
+        "{}/1/{}/{}/{}?adjusted=true&sort=asc&limit={}&apiKey={}",
+        api_url, time_period, start_millis, end_millis, PAGE_LIMIT, api_key
     );
 
-    debug!("Polygon API request URL: {}", url);
+    let mut data: Vec<PolygonData> = vec![];
+    for page in 0..MAX_PAGES {
+        debug!("Polygon API request URL (page {}): {}", page, url);
+
+        let api_response = get_polygon_page(http, &url).await?;
+        if let Some(results) = api_response.results {
+            data.extend(results);
+        } else if page == 0 {
+            error!("No results field in Polygon API response.");
+            return Err(anyhow!("No results in Polygon API response"));
+        }
+
+        // `next_url` carries the cursor but not the API key
+        match api_response.next_url {
+            Some(next_url) => url = format!("{}&apiKey={}", next_url, api_key),
+            None => break,
+        }
+    }
+
+    if data.is_empty() {
+        error!("Polygon API returned an empty results array.");
+        return Err(anyhow!("Polygon API returned an empty results array"));
+    }
+
+    let received = data.len();
+    let parsed_data: Vec<(DateTime<Utc>, f64)> = data
+        .into_iter()
+        .filter_map(|d| DateTime::from_timestamp_millis(d.timestamp).map(|dt| (dt, d.vw)))
+        .collect();
+
+    if parsed_data.is_empty() {
+        error!("Parsed data is empty after processing Polygon API response.");
+        return Err(anyhow!(
+            "Parsed data is empty after processing Polygon API response"
+        ));
+    }
+
+    debug!("Parsed data: {:?}", parsed_data);
+    Ok(SourceBars {
+        timestamp_failures: received - parsed_data.len(),
+        bars: parsed_data,
+        truncated: false,
+    })
+}
 
-    let response = client
-        .get(&url)
-        .timeout(StdDuration::from_secs(10)) // Set a timeout for the request
-        .send()
-        .await;
+// Fetch one page of aggregates, turning Polygon's error responses into errors
+async fn get_polygon_page(http: &Http, url: &str) -> Result<PolygonApiResponse> {
+    let response = http.send(Source::Polygon, http.get(url)).await;
 
     match response {
         Ok(resp) => {
             debug!("Received response: {:?}", resp);
-            if !resp.status().is_success() {
+            if !resp.status.is_success() {
                 // Attempt to parse the error response
-                let status_code = resp.status();
-                let error_text = resp
-                    .text()
-                    .await
-                    .unwrap_or_else(|_| "Failed to read error response".to_string());
+                let status_code = resp.status;
+                let error_text = resp.body;
 
                 // Try to deserialize the error response
                 if let Ok(error_response) = serde_json::from_str::<ErrorResponse>(&error_text) {
@@ -125,37 +188,7 @@ pub async fn get_polygon_data(
             }
 
             // Parse the JSON response
-            let api_response: PolygonApiResponse = resp.json().await?;
-
-            // Check if the results field is present and non-empty
-            if let Some(data) = api_response.results {
-                if data.is_empty() {
-                    error!("Polygon API returned an empty results array.");
-                    return Err(anyhow!("Polygon API returned an empty results array"));
-                }
-
-                let parsed_data: Vec<(NaiveDateTime, f64)> = data
-                    .into_iter()
-                    .filter_map(|d| {
-                        #[allow(deprecated)]
-                        NaiveDateTime::from_timestamp_opt(d.timestamp / 1000, 0)
-                            .map(|naive_dt| (naive_dt, d.vw))
-                    })
-                    .collect();
-
-                if parsed_data.is_empty() {
-                    error!("Parsed data is empty after processing Polygon API response.");
-                    return Err(anyhow!(
-                        "Parsed data is empty after processing Polygon API response"
-                    ));
-                }
-
-                debug!("Parsed data: {:?}", parsed_data);
-                Ok(parsed_data)
-            } else {
-                error!("No results field in Polygon API response.");
-                Err(anyhow!("No results in Polygon API response"))
-            }
+            resp.json()
         }
         Err(e) => {
             error!("Failed to send request to Polygon API: {}", e);
@@ -0,0 +1,379 @@
+use super::sources::Source;
+use super::store::EstimateRecord;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use std::env;
+use tokio_postgres::{Client, NoTls};
+
+// One source's merged bar: its open time, the source, the symbol it was fetched for and its price
+pub type SinkBar = (DateTime<Utc>, Source, String, f64);
+
+// Table names, each optionally schema qualified
+#[derive(Debug, Clone)]
+pub struct SinkTables {
+    pub bars: String,
+    pub prices: String,
+    pub estimates: String,
+    pub source_estimates: String,
+}
+
+impl Default for SinkTables {
+    fn default() -> Self {
+        SinkTables {
+            bars: "source_bars".to_string(),
+            prices: "composite_prices".to_string(),
+            estimates: "volatility_estimates".to_string(),
+            source_estimates: "source_volatility_estimates".to_string(),
+        }
+    }
+}
+
+impl SinkTables {
+    // Load POSTGRES_BARS_TABLE, POSTGRES_PRICES_TABLE, POSTGRES_ESTIMATES_TABLE and
+    // POSTGRES_SOURCE_ESTIMATES_TABLE
+    pub fn from_env() -> Result<Self> {
+        let defaults = SinkTables::default();
+        let table = |name: &str, default: String| -> Result<String> {
+            let table = env::var(name).unwrap_or(default);
+            quote_table(&table).map_err(|e| anyhow!("{}: {}", name, e))?;
+            Ok(table)
+        };
+        Ok(SinkTables {
+            bars: table("POSTGRES_BARS_TABLE", defaults.bars)?,
+            prices: table("POSTGRES_PRICES_TABLE", defaults.prices)?,
+            estimates: table("POSTGRES_ESTIMATES_TABLE", defaults.estimates)?,
+            source_estimates: table("POSTGRES_SOURCE_ESTIMATES_TABLE", defaults.source_estimates)?,
+        })
+    }
+}
+
+// Writes each run's bars, composite prices and estimates to PostgreSQL, a row already written
+// by an earlier run is updated in place
+pub struct PostgresSink {
+    client: Client,
+    bars: String, // Quoted table names
+    prices: String,
+    estimates: String,
+    source_estimates: String,
+}
+
+impl PostgresSink {
+    // Connect and create any missing tables, as TimescaleDB hypertables if `timescale`
+    pub async fn connect(url: &str, tables: &SinkTables, timescale: bool) -> Result<Self> {
+        let (bars, prices, estimates, source_estimates) = (
+            quote_table(&tables.bars)?,
+            quote_table(&tables.prices)?,
+            quote_table(&tables.estimates)?,
+            quote_table(&tables.source_estimates)?,
+        );
+
+        let (client, connection) = tokio_postgres::connect(url, NoTls)
+            .await
+            .map_err(|e| anyhow!("Failed to connect to POSTGRES_URL: {}", e))?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                warn!("PostgreSQL connection closed: {}", e);
+            }
+        });
+
+        let sink = PostgresSink {
+            client,
+            bars,
+            prices,
+            estimates,
+            source_estimates,
+        };
+
+        // Schemas of schema qualified table names
+        let mut schemas: Vec<String> = [
+            &tables.bars,
+            &tables.prices,
+            &tables.estimates,
+            &tables.source_estimates,
+        ]
+        .iter()
+        .filter_map(|table| table.split_once('.'))
+        .map(|(schema, _)| format!("\"{}\"", schema))
+        .collect();
+        schemas.sort();
+        schemas.dedup();
+
+        sink.create_schema(&schemas, timescale).await?;
+        Ok(sink)
+    }
+
+    // Whether the connection has dropped, e.g. after a database restart
+    pub fn is_closed(&self) -> bool {
+        self.client.is_closed()
+    }
+
+    async fn create_schema(&self, schemas: &[String], timescale: bool) -> Result<()> {
+        for schema in schemas {
+            self.client
+                .batch_execute(&format!("CREATE SCHEMA IF NOT EXISTS {}", schema))
+                .await?;
+        }
+        self.client
+            .batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {bars} (
+                    time_period TEXT NOT NULL,
+                    source TEXT NOT NULL,
+                    symbol TEXT NOT NULL,
+                    timestamp TIMESTAMPTZ NOT NULL,
+                    price DOUBLE PRECISION NOT NULL,
+                    updated_at TIMESTAMPTZ NOT NULL,
+                    PRIMARY KEY (time_period, source, symbol, timestamp)
+                );
+                CREATE TABLE IF NOT EXISTS {prices} (
+                    time_period TEXT NOT NULL,
+                    timestamp TIMESTAMPTZ NOT NULL,
+                    composite DOUBLE PRECISION,
+                    vol DOUBLE PRECISION,
+                    updated_at TIMESTAMPTZ NOT NULL,
+                    PRIMARY KEY (time_period, timestamp)
+                );
+                CREATE TABLE IF NOT EXISTS {estimates} (
+                    time_period TEXT NOT NULL,
+                    no_of_periods BIGINT NOT NULL,
+                    window_end TIMESTAMPTZ NOT NULL,
+                    run_at TIMESTAMPTZ NOT NULL,
+                    volatility DOUBLE PRECISION,
+                    quorum_min_sources BIGINT NOT NULL,
+                    quorum_tolerance_bps DOUBLE PRECISION,
+                    quorum_on_fail TEXT NOT NULL,
+                    exclude_partial_bar BOOLEAN NOT NULL,
+                    PRIMARY KEY (time_period, no_of_periods, window_end)
+                );
+                CREATE TABLE IF NOT EXISTS {source_estimates} (
+                    time_period TEXT NOT NULL,
+                    no_of_periods BIGINT NOT NULL,
+                    window_end TIMESTAMPTZ NOT NULL,
+                    source TEXT NOT NULL,
+                    coverage DOUBLE PRECISION NOT NULL,
+                    volatility DOUBLE PRECISION,
+                    bars_received BIGINT,
+                    bars_merged BIGINT,
+                    outside_window BIGINT,
+                    timestamp_failures BIGINT,
+                    PRIMARY KEY (time_period, no_of_periods, window_end, source)
+                );",
+                bars = self.bars,
+                prices = self.prices,
+                estimates = self.estimates,
+                source_estimates = self.source_estimates
+            ))
+            .await?;
+
+        if timescale {
+            for (table, time_column) in [
+                (&self.bars, "timestamp"),
+                (&self.prices, "timestamp"),
+                (&self.estimates, "window_end"),
+                (&self.source_estimates, "window_end"),
+            ] {
+                self.client
+                    .execute(
+                        "SELECT create_hypertable($1::text::regclass, $2::text::name, if_not_exists => TRUE)",
+                        &[table, &time_column],
+                    )
+                    .await
+                    .map_err(|e| anyhow!("Failed to make {} a hypertable: {}", table, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Write one run: each source's merged bars under the symbol they were fetched for, the
+    // composite prices and the estimates
+    pub async fn write(&mut self, record: &EstimateRecord, source_bars: &[SinkBar]) -> Result<()> {
+        let now = Utc::now();
+        let tx = self.client.transaction().await?;
+
+        let statement = tx
+            .prepare(&format!(
+                "INSERT INTO {} (time_period, source, symbol, timestamp, price, updated_at)
+                 VALUES ($1, $2, $3, $4, $5, $6)
+                 ON CONFLICT (time_period, source, symbol, timestamp)
+                 DO UPDATE SET price = excluded.price, updated_at = excluded.updated_at",
+                self.bars
+            ))
+            .await?;
+        for (timestamp, source, symbol, price) in source_bars {
+            tx.execute(
+                &statement,
+                &[
+                    &record.time_period,
+                    &source.label(),
+                    symbol,
+                    timestamp,
+                    price,
+                    &now,
+                ],
+            )
+            .await?;
+        }
+
+        let statement = tx
+            .prepare(&format!(
+                "INSERT INTO {} (time_period, timestamp, composite, vol, updated_at)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (time_period, timestamp)
+                 DO UPDATE SET composite = excluded.composite, vol = excluded.vol,
+                               updated_at = excluded.updated_at",
+                self.prices
+            ))
+            .await?;
+        for (timestamp, composite, vol) in &record.prices {
+            tx.execute(
+                &statement,
+                &[&record.time_period, timestamp, composite, vol, &now],
+            )
+            .await?;
+        }
+
+        let no_of_periods = record.no_of_periods as i64;
+        tx.execute(
+            &format!(
+                "INSERT INTO {} (time_period, no_of_periods, window_end, run_at, volatility,
+                     quorum_min_sources, quorum_tolerance_bps, quorum_on_fail,
+                     exclude_partial_bar)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                 ON CONFLICT (time_period, no_of_periods, window_end)
+                 DO UPDATE SET run_at = excluded.run_at, volatility = excluded.volatility,
+                     quorum_min_sources = excluded.quorum_min_sources,
+                     quorum_tolerance_bps = excluded.quorum_tolerance_bps,
+                     quorum_on_fail = excluded.quorum_on_fail,
+                     exclude_partial_bar = excluded.exclude_partial_bar",
+                self.estimates
+            ),
+            &[
+                &record.time_period,
+                &no_of_periods,
+                &record.window_end,
+                &record.run_at,
+                &record.volatility,
+                &(record.quorum.min_sources as i64),
+                &record.quorum.tolerance_bps,
+                &record.quorum.action.as_str(),
+                &record.exclude_partial_bar,
+            ],
+        )
+        .await?;
+
+        // Sources that reported in this run, as in the SQLite store
+        let statement = tx
+            .prepare(&format!(
+                "INSERT INTO {} (time_period, no_of_periods, window_end, source, coverage,
+                     volatility, bars_received, bars_merged, outside_window, timestamp_failures)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                 ON CONFLICT (time_period, no_of_periods, window_end, source)
+                 DO UPDATE SET coverage = excluded.coverage, volatility = excluded.volatility,
+                     bars_received = excluded.bars_received, bars_merged = excluded.bars_merged,
+                     outside_window = excluded.outside_window,
+                     timestamp_failures = excluded.timestamp_failures",
+                self.source_estimates
+            ))
+            .await?;
+        for source_vol in &record.source_volatilities {
+            let quality = record.quality.get(&source_vol.source);
+            if quality.is_none() && source_vol.coverage == 0.0 {
+                continue;
+            }
+            tx.execute(
+                &statement,
+                &[
+                    &record.time_period,
+                    &no_of_periods,
+                    &record.window_end,
+                    &source_vol.source.label(),
+                    &source_vol.coverage,
+                    &source_vol.volatility,
+                    &quality.map(|q| q.bars_received as i64),
+                    &quality.map(|q| q.bars_merged as i64),
+                    &quality.map(|q| q.outside_window as i64),
+                    &quality.map(|q| q.timestamp_failures as i64),
+                ],
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+        debug!(
+            "Wrote {} source bars and {} composite prices to PostgreSQL",
+            source_bars.len(),
+            record.prices.len()
+        );
+        Ok(())
+    }
+}
+
+// The sink at POSTGRES_URL, connected on first write and again after the connection drops,
+// so an unreachable database doesn't stop the estimates
+pub struct PostgresOutput {
+    url: String,
+    tables: SinkTables,
+    timescale: bool,
+    sink: Option<PostgresSink>,
+}
+
+impl PostgresOutput {
+    pub fn new(url: &str, tables: SinkTables, timescale: bool) -> Self {
+        PostgresOutput {
+            url: url.to_string(),
+            tables,
+            timescale,
+            sink: None,
+        }
+    }
+
+    // Load POSTGRES_URL (None when unset), the table names and POSTGRES_TIMESCALE
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(url) = env::var("POSTGRES_URL") else {
+            return Ok(None);
+        };
+        let timescale: bool = env::var("POSTGRES_TIMESCALE")
+            .unwrap_or("false".to_string())
+            .parse()
+            .map_err(|_| anyhow!("POSTGRES_TIMESCALE must be true or false"))?;
+        Ok(Some(PostgresOutput::new(
+            &url,
+            SinkTables::from_env()?,
+            timescale,
+        )))
+    }
+
+    // Write one run, reconnecting first if there is no open connection
+    pub async fn write(&mut self, record: &EstimateRecord, source_bars: &[SinkBar]) -> Result<()> {
+        let sink = match &mut self.sink {
+            Some(sink) if !sink.is_closed() => sink,
+            _ => {
+                debug!("Connecting to PostgreSQL");
+                self.sink = None;
+                let sink = PostgresSink::connect(&self.url, &self.tables, self.timescale).await?;
+                self.sink.insert(sink)
+            }
+        };
+        sink.write(record, source_bars).await
+    }
+}
+
+// Quote a table name, optionally schema qualified, rejecting anything but letters, digits and _
+fn quote_table(table: &str) -> Result<String> {
+    let parts: Vec<&str> = table.split('.').collect();
+    if parts.len() > 2
+        || parts.iter().any(|part| {
+            part.is_empty() || !part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+    {
+        return Err(anyhow!(
+            "table name {:?} must be letters, digits and _, optionally as schema.table",
+            table
+        ));
+    }
+    Ok(parts
+        .iter()
+        .map(|part| format!("\"{}\"", part))
+        .collect::<Vec<_>>()
+        .join("."))
+}
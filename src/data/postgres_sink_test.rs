@@ -0,0 +1,139 @@
+use super::calculate_volatility::SourceVolatility;
+use super::postgres_sink::{PostgresOutput, PostgresSink, SinkTables};
+use super::quality::SourceQuality;
+use super::quorum::QuorumPolicy;
+use super::sources::Source;
+use super::store::EstimateRecord;
+use chrono::{DateTime, Duration};
+use std::collections::BTreeMap;
+use std::env;
+use tokio_postgres::NoTls;
+
+// These tests need a database to write to, e.g.
+// POSTGRES_TEST_URL="host=localhost user=postgres dbname=postgres"
+fn test_url() -> Option<String> {
+    let url = env::var("POSTGRES_TEST_URL").ok();
+    if url.is_none() {
+        println!("POSTGRES_TEST_URL not set, skipping");
+    }
+    url
+}
+
+fn record(volatility: f64, price: f64) -> EstimateRecord {
+    let window_end = DateTime::from_timestamp(1717200000, 0).unwrap();
+    EstimateRecord {
+        run_at: window_end,
+        window_end,
+        time_period: "minute".to_string(),
+        no_of_periods: 2,
+        quorum: QuorumPolicy::default(),
+        exclude_partial_bar: true,
+        volatility: Some(volatility),
+        prices: vec![
+            (window_end - Duration::minutes(2), Some(price), Some(price)),
+            (window_end - Duration::minutes(1), None, Some(price)),
+        ],
+        quality: BTreeMap::from([(Source::Kraken, SourceQuality::default())]),
+        source_volatilities: vec![SourceVolatility {
+            source: Source::Kraken,
+            coverage: 0.5,
+            volatility: None,
+        }],
+    }
+}
+
+#[tokio::test]
+async fn test_upserts_runs_into_configured_tables() {
+    let Some(url) = test_url() else {
+        return;
+    };
+    let schema = format!("volatility_test_{}", std::process::id());
+    let tables = SinkTables {
+        bars: format!("{}.bars", schema),
+        prices: format!("{}.prices", schema),
+        estimates: format!("{}.estimates", schema),
+        source_estimates: format!("{}.source_estimates", schema),
+    };
+    let (client, connection) = tokio_postgres::connect(&url, NoTls).await.unwrap();
+    tokio::spawn(connection);
+
+    // The same window written twice, the second run revising a price and the estimate
+    for (volatility, price) in [(0.01, 2500.0), (0.02, 2501.0)] {
+        let mut sink = PostgresSink::connect(&url, &tables, false).await.unwrap();
+        let record = record(volatility, price);
+        let source_bars = vec![(
+            record.prices[0].0,
+            Source::Kraken,
+            "ETHPYUSD".to_string(),
+            price,
+        )];
+        sink.write(&record, &source_bars).await.unwrap();
+    }
+
+    // Another pair from the same source is kept apart from the first
+    let mut sink = PostgresSink::connect(&url, &tables, false).await.unwrap();
+    let record = record(0.02, 2501.0);
+    let source_bars = vec![(
+        record.prices[0].0,
+        Source::Kraken,
+        "ETHUSD".to_string(),
+        2502.0,
+    )];
+    sink.write(&record, &source_bars).await.unwrap();
+
+    let count = |table: &str| format!("SELECT COUNT(*) FROM {}.{}", schema, table);
+    for (table, expected) in [
+        ("bars", 2),
+        ("prices", 2),
+        ("estimates", 1),
+        ("source_estimates", 1),
+    ] {
+        let rows: i64 = client.query_one(&count(table), &[]).await.unwrap().get(0);
+        assert_eq!(rows, expected, "{}", table);
+    }
+    let row = client
+        .query_one(
+            &format!(
+                "SELECT e.volatility, b.price FROM {0}.estimates e, {0}.bars b
+                 WHERE b.symbol = 'ETHPYUSD'",
+                schema
+            ),
+            &[],
+        )
+        .await
+        .unwrap();
+    assert_eq!(row.get::<_, f64>(0), 0.02);
+    assert_eq!(row.get::<_, f64>(1), 2501.0);
+
+    client
+        .batch_execute(&format!("DROP SCHEMA {} CASCADE", schema))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_rejects_unsafe_table_names() {
+    let tables = SinkTables {
+        bars: "bars; DROP TABLE x".to_string(),
+        ..SinkTables::default()
+    };
+    let error = PostgresSink::connect("host=localhost", &tables, false)
+        .await
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("must be letters"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_unreachable_database_fails_each_write() {
+    let mut output = PostgresOutput::new(
+        "host=127.0.0.1 port=1 user=postgres connect_timeout=1",
+        SinkTables::default(),
+        false,
+    );
+    // Every write tries to connect again rather than giving up on the sink
+    for _ in 0..2 {
+        let error = output.write(&record(0.01, 2500.0), &[]).await.unwrap_err();
+        assert!(error.to_string().contains("POSTGRES_URL"), "{}", error);
+    }
+}
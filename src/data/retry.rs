@@ -0,0 +1,206 @@
+use super::sources::Source;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+use std::env;
+use std::time::{Duration, Instant};
+
+// How hard a source may be called, tuned to each provider's free or public plan
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourcePolicy {
+    pub rate_limit: f64,            // Requests per second, sustained
+    pub burst: f64,                 // Requests that may be sent back to back
+    pub max_retries: u32,           // Retries after the first attempt
+    pub base_delay: Duration,       // First backoff, doubled on each retry
+    pub max_delay: Duration,        // Longer waits, even if asked for by Retry-After, give up
+    pub breaker_threshold: u32,     // Failed requests in a row before the source is paused
+    pub breaker_cooldown: Duration, // How long a paused source is left alone
+}
+
+impl SourcePolicy {
+    pub fn defaults(source: Source) -> Self {
+        let (rate_limit, burst) = match source {
+            Source::Polygon => (5.0 / 60.0, 5.0), // Free plan: 5 calls per minute
+            Source::Dune => (40.0 / 60.0, 5.0),
+            Source::Kraken => (0.9, 1.0), // Public endpoints: about one call per second
+            Source::CoinApi => (1.0, 5.0),
+            Source::Binance => (10.0, 10.0),
+            Source::Coinbase => (6.0, 1.0), // Public endpoints: 10 per second, bursts get 429s
+            Source::Bitfinex => (0.45, 1.0), // Candles: 30 calls per minute
+            Source::Uniswap | Source::Chainlink => (25.0, 25.0), // Hosted JSON-RPC node
+            Source::File => (f64::INFINITY, 1.0),
+        };
+        SourcePolicy {
+            rate_limit,
+            burst,
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            breaker_threshold: 5,
+            breaker_cooldown: Duration::from_secs(60),
+        }
+    }
+
+    // Defaults overridden by {SOURCE}_RATE_LIMIT, {SOURCE}_RATE_BURST, {SOURCE}_MAX_RETRIES,
+    // {SOURCE}_RETRY_DELAY_MS, {SOURCE}_RETRY_MAX_DELAY_MS, {SOURCE}_BREAKER_THRESHOLD and
+    // {SOURCE}_BREAKER_COOLDOWN_SECS, e.g. POLYGON_RATE_LIMIT=100 on a paid plan
+    pub fn from_env(source: Source) -> Result<Self> {
+        SourcePolicy::from_lookup(source, |name| env::var(name).ok())
+    }
+
+    // As from_env, reading the variables through `lookup`
+    pub fn from_lookup(source: Source, lookup: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let prefix = source.label().to_uppercase();
+        let number = |name: &str| -> Result<Option<f64>> {
+            let Some(value) = lookup(&format!("{}_{}", prefix, name)) else {
+                return Ok(None);
+            };
+            match value.trim().parse::<f64>() {
+                Ok(number) if number >= 0.0 => Ok(Some(number)), // Also false for NaN
+                _ => Err(anyhow!(
+                    "{}_{} must be a non-negative number, got {}",
+                    prefix,
+                    name,
+                    value
+                )),
+            }
+        };
+
+        let mut policy = SourcePolicy::defaults(source);
+        if let Some(rate_limit) = number("RATE_LIMIT")? {
+            policy.rate_limit = rate_limit;
+        }
+        if let Some(burst) = number("RATE_BURST")? {
+            policy.burst = burst.max(1.0);
+        }
+        if let Some(max_retries) = number("MAX_RETRIES")? {
+            policy.max_retries = max_retries as u32;
+        }
+        if let Some(ms) = number("RETRY_DELAY_MS")? {
+            policy.base_delay = Duration::from_millis(ms as u64);
+        }
+        if let Some(ms) = number("RETRY_MAX_DELAY_MS")? {
+            policy.max_delay = Duration::from_millis(ms as u64);
+        }
+        if let Some(threshold) = number("BREAKER_THRESHOLD")? {
+            policy.breaker_threshold = threshold as u32;
+        }
+        if let Some(secs) = number("BREAKER_COOLDOWN_SECS")? {
+            policy.breaker_cooldown = Duration::try_from_secs_f64(secs)
+                .map_err(|_| anyhow!("{}_BREAKER_COOLDOWN_SECS is too large", prefix))?;
+        }
+        Ok(policy)
+    }
+}
+
+// Token bucket holding up to `burst` requests and refilled at `rate_limit` per second
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(policy: &SourcePolicy, now: Instant) -> Self {
+        TokenBucket {
+            rate: policy.rate_limit,
+            capacity: policy.burst,
+            tokens: policy.burst,
+            updated: now,
+        }
+    }
+
+    // Take a token and return how long to wait before using it. Tokens may be taken ahead of
+    // time, so concurrent callers queue up behind each other rather than all waking at once.
+    pub fn take(&mut self, now: Instant) -> Duration {
+        if !self.rate.is_finite() || self.rate <= 0.0 {
+            return Duration::ZERO;
+        }
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+// Stops calling a source after repeated failures, then lets a single request through
+// once the cooldown has passed to see if it has recovered
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(policy: &SourcePolicy) -> Self {
+        CircuitBreaker {
+            threshold: policy.breaker_threshold.max(1),
+            cooldown: policy.breaker_cooldown,
+            failures: 0,
+            open_until: None,
+        }
+    }
+
+    // None if a request may be sent, otherwise how long until the source is tried again
+    pub fn check(&self, now: Instant) -> Option<Duration> {
+        match self.open_until {
+            Some(open_until) if now < open_until => Some(open_until - now),
+            _ => None,
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+        self.open_until = None;
+    }
+
+    // Returns true when this failure opens the breaker
+    pub fn record_failure(&mut self, now: Instant) -> bool {
+        self.failures += 1;
+        if self.failures >= self.threshold {
+            self.open_until = Some(now + self.cooldown);
+            return true;
+        }
+        false
+    }
+}
+
+// Rate limits and server errors are worth another attempt, other errors won't change
+pub fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status.is_server_error()
+}
+
+// Exponential backoff for the given retry, starting at 0
+pub fn backoff_delay(policy: &SourcePolicy, retry: u32) -> Duration {
+    policy
+        .base_delay
+        .saturating_mul(2u32.saturating_pow(retry))
+        .min(policy.max_delay)
+}
+
+// Wait asked for by a Retry-After header, in seconds or as an HTTP date
+pub fn retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
@@ -0,0 +1,187 @@
+use super::http::{Http, HttpMode};
+use super::retry::{backoff_delay, retry_after, CircuitBreaker, SourcePolicy, TokenBucket};
+use super::sources::Source;
+use chrono::DateTime;
+use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+use reqwest::{Client, StatusCode};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+fn policy() -> SourcePolicy {
+    SourcePolicy {
+        rate_limit: 2.0,
+        burst: 2.0,
+        max_retries: 2,
+        base_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(100),
+        breaker_threshold: 2,
+        breaker_cooldown: Duration::from_secs(60),
+    }
+}
+
+// Serve `responses` in order to one connection each, repeating the last, and count requests
+async fn serve(responses: Vec<&'static str>) -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&requests);
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buffer = [0u8; 4096];
+            let _ = socket.read(&mut buffer).await;
+            let index = counter.fetch_add(1, Ordering::SeqCst);
+            let response = responses[index.min(responses.len() - 1)];
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    });
+    (url, requests)
+}
+
+const UNAVAILABLE: &str =
+    "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+const RATE_LIMITED: &str = "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 3600\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+#[test]
+fn test_token_bucket_allows_burst_then_paces() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(&policy(), start);
+
+    assert_eq!(bucket.take(start), Duration::ZERO);
+    assert_eq!(bucket.take(start), Duration::ZERO);
+    // Bucket empty, the next token arrives after 1 / rate
+    assert_eq!(bucket.take(start), Duration::from_millis(500));
+    // Tokens taken ahead queue up behind each other
+    assert_eq!(bucket.take(start), Duration::from_millis(1000));
+    // Refilled after waiting
+    let later = start + Duration::from_secs(10);
+    assert_eq!(bucket.take(later), Duration::ZERO);
+}
+
+#[test]
+fn test_circuit_breaker_opens_after_threshold() {
+    let now = Instant::now();
+    let mut breaker = CircuitBreaker::new(&policy());
+
+    assert!(!breaker.record_failure(now));
+    assert_eq!(breaker.check(now), None);
+    assert!(breaker.record_failure(now));
+    assert_eq!(breaker.check(now), Some(Duration::from_secs(60)));
+    // Half open once the cooldown has passed
+    assert_eq!(breaker.check(now + Duration::from_secs(61)), None);
+
+    breaker.record_success();
+    assert!(!breaker.record_failure(now));
+}
+
+#[test]
+fn test_backoff_doubles_up_to_max() {
+    let policy = policy();
+    assert_eq!(backoff_delay(&policy, 0), Duration::from_millis(10));
+    assert_eq!(backoff_delay(&policy, 2), Duration::from_millis(40));
+    assert_eq!(backoff_delay(&policy, 10), Duration::from_millis(100));
+}
+
+#[test]
+fn test_policy_overrides_from_lookup() {
+    let vars = HashMap::from([
+        ("POLYGON_RATE_LIMIT", "100"),
+        ("POLYGON_MAX_RETRIES", "5"),
+        ("POLYGON_BREAKER_COOLDOWN_SECS", "0.5"),
+    ]);
+    let policy = SourcePolicy::from_lookup(Source::Polygon, |name| {
+        vars.get(name).map(|value| value.to_string())
+    })
+    .unwrap();
+    assert_eq!(policy.rate_limit, 100.0);
+    assert_eq!(policy.max_retries, 5);
+    assert_eq!(policy.breaker_cooldown, Duration::from_millis(500));
+    assert_eq!(policy.burst, SourcePolicy::defaults(Source::Polygon).burst);
+}
+
+#[test]
+fn test_policy_rejects_bad_values() {
+    for (name, value) in [
+        ("KRAKEN_BREAKER_COOLDOWN_SECS", "-1"),
+        ("KRAKEN_MAX_RETRIES", "-3"),
+        ("KRAKEN_RATE_LIMIT", "fast"),
+        ("KRAKEN_RETRY_DELAY_MS", "NaN"),
+        ("KRAKEN_BREAKER_COOLDOWN_SECS", "inf"),
+    ] {
+        let result = SourcePolicy::from_lookup(Source::Kraken, |var| {
+            (var == name).then(|| value.to_string())
+        });
+        assert!(result.is_err(), "{}={} was accepted", name, value);
+    }
+}
+
+#[test]
+fn test_parses_retry_after() {
+    let now = DateTime::from_timestamp(1717200000, 0).unwrap();
+    let mut headers = HeaderMap::new();
+    assert_eq!(retry_after(&headers, now), None);
+
+    headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+    assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(120)));
+
+    // 2024-06-01T00:00:30Z
+    headers.insert(
+        RETRY_AFTER,
+        HeaderValue::from_static("Sat, 01 Jun 2024 00:00:30 GMT"),
+    );
+    assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(30)));
+}
+
+#[tokio::test]
+async fn test_retries_server_errors() {
+    let (url, requests) = serve(vec![UNAVAILABLE, OK]).await;
+    let http = Http::new(HttpMode::Live, Client::new(), "").with_policy(Source::Kraken, policy());
+
+    let response = http.send(Source::Kraken, http.get(&url)).await.unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "ok");
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_gives_up_on_long_retry_after() {
+    let (url, requests) = serve(vec![RATE_LIMITED]).await;
+    let http = Http::new(HttpMode::Live, Client::new(), "").with_policy(Source::Kraken, policy());
+
+    let response = http.send(Source::Kraken, http.get(&url)).await.unwrap();
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_stops_calling_a_failing_source() {
+    let (url, requests) = serve(vec![UNAVAILABLE]).await;
+    let policy = SourcePolicy {
+        max_retries: 0,
+        ..policy()
+    };
+    let http = Http::new(HttpMode::Live, Client::new(), "").with_policy(Source::Kraken, policy);
+
+    for _ in 0..2 {
+        let response = http.send(Source::Kraken, http.get(&url)).await.unwrap();
+        assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+    let error = http.send(Source::Kraken, http.get(&url)).await.unwrap_err();
+    assert!(error.to_string().contains("circuit breaker"), "{}", error);
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_send_once_does_not_resend_server_errors() {
+    let (url, requests) = serve(vec![UNAVAILABLE, OK]).await;
+    let http = Http::new(HttpMode::Live, Client::new(), "").with_policy(Source::Dune, policy());
+
+    let response = http.send_once(Source::Dune, http.post(&url)).await.unwrap();
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
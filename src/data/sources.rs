@@ -0,0 +1,153 @@
+// Every price source that can fill a column of the results map
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Source {
+    Polygon,
+    Dune,
+    Kraken,
+    CoinApi,
+    Binance,
+    Coinbase,
+    Bitfinex,
+    Uniswap,
+    Chainlink,
+    File,
+}
+
+impl Source {
+    // In output column order
+    pub const ALL: [Source; 10] = [
+        Source::Polygon,
+        Source::Dune,
+        Source::Kraken,
+        Source::CoinApi,
+        Source::Binance,
+        Source::Coinbase,
+        Source::Bitfinex,
+        Source::Uniswap,
+        Source::Chainlink,
+        Source::File,
+    ];
+
+    // Column label used in the printed output, the provider the data is fetched from
+    pub fn label(self) -> &'static str {
+        match self {
+            Source::Polygon => "Polygon",
+            Source::Dune => "Dune",
+            Source::Kraken => "Kraken",
+            Source::CoinApi => "CoinAPI",
+            Source::Binance => "Binance",
+            Source::Coinbase => "Coinbase",
+            Source::Bitfinex => "Bitfinex",
+            Source::Uniswap => "Uniswap", // Swap logs read over JSON-RPC
+            Source::Chainlink => "Chainlink",
+            Source::File => "File", // Local CSV or Parquet data
+        }
+    }
+
+    // Where the prices trade, which differs from the provider for the data vendors
+    pub fn venue(self) -> String {
+        match self {
+            Source::Polygon => "Multiple".to_string(), // Polygon's crypto aggregates span exchanges
+            Source::Dune => "DEX".to_string(),
+            Source::Chainlink => "Oracle".to_string(),
+            Source::File => std::env::var("FILE_SOURCE_VENUE").unwrap_or("Local".to_string()),
+            Source::CoinApi => coinapi_venue(&coinapi_symbol_id()),
+            source => source.label().to_string(),
+        }
+    }
+
+    // On-chain (DEX) prices, as opposed to centralized exchange prices
+    pub fn is_on_chain(self) -> bool {
+        matches!(self, Source::Dune | Source::Uniswap)
+    }
+
+    // Oracle feeds aggregate other venues, so they sit on neither side of the DEX-CEX basis
+    pub fn is_oracle(self) -> bool {
+        matches!(self, Source::Chainlink)
+    }
+}
+
+// Base and quote asset priced by the exchange sources, from SPOT_PAIR (default ETH/USD).
+// Each exchange adapter maps the pair onto its own symbol format.
+pub fn spot_pair() -> anyhow::Result<(String, String)> {
+    let pair = std::env::var("SPOT_PAIR").unwrap_or("ETH/USD".to_string());
+    match pair.split_once('/') {
+        Some((base, quote)) if !base.is_empty() && !quote.is_empty() => {
+            Ok((base.to_uppercase(), quote.to_uppercase()))
+        }
+        _ => Err(anyhow::anyhow!(
+            "SPOT_PAIR must be BASE/QUOTE, e.g. ETH/USD, got {}",
+            pair
+        )),
+    }
+}
+
+// CoinAPI symbol to fetch, any of its EXCHANGE_SPOT_BASE_QUOTE ids. The default is on an
+// exchange none of the direct sources fetch.
+pub fn coinapi_symbol_id() -> String {
+    std::env::var("COINAPI_SYMBOL_ID").unwrap_or("BITSTAMP_SPOT_ETH_USD".to_string())
+}
+
+// The direct source fetching the same market as a CoinAPI symbol id, whose prices CoinAPI
+// would count a second time. `direct_symbol` gives each direct source's configured symbol.
+pub fn coinapi_duplicate(
+    symbol_id: &str,
+    direct_symbol: impl Fn(Source) -> Option<String>,
+) -> Option<Source> {
+    let venue = coinapi_venue(symbol_id);
+    let (base, quote) = coinapi_pair(symbol_id)?;
+    [
+        Source::Kraken,
+        Source::Binance,
+        Source::Coinbase,
+        Source::Bitfinex,
+    ]
+    .into_iter()
+    .filter(|source| source.venue().eq_ignore_ascii_case(&venue))
+    .find(|&source| {
+        direct_symbol(source).is_some_and(|symbol| {
+            // Bitfinex prefixes trading pairs with t, the other formats only add separators
+            let symbol = match source {
+                Source::Bitfinex => symbol.strip_prefix('t').unwrap_or(&symbol).to_string(),
+                _ => symbol,
+            };
+            let symbol: String = symbol.chars().filter(char::is_ascii_alphanumeric).collect();
+            symbol.eq_ignore_ascii_case(&format!("{}{}", base, quote))
+        })
+    })
+}
+
+// Symbol each direct exchange source is configured to fetch
+pub fn direct_symbol(source: Source) -> Option<String> {
+    match source {
+        Source::Kraken => Some(super::kraken::ASSET_ID.to_string()),
+        Source::Binance => super::binance::binance_symbol().ok(),
+        Source::Coinbase => super::coinbase::coinbase_product().ok(),
+        Source::Bitfinex => super::bitfinex::bitfinex_symbol().ok(),
+        _ => None,
+    }
+}
+
+// Base and quote of a CoinAPI symbol id, e.g. (ETH, USD) for BITSTAMP_SPOT_ETH_USD
+pub fn coinapi_pair(symbol_id: &str) -> Option<(String, String)> {
+    let mut parts = symbol_id.split('_').skip(2);
+    match (parts.next(), parts.next()) {
+        (Some(base), Some(quote)) if !base.is_empty() && !quote.is_empty() => {
+            Some((base.to_uppercase(), quote.to_uppercase()))
+        }
+        _ => None,
+    }
+}
+
+// Exchange part of a CoinAPI symbol id, e.g. Bitstamp for BITSTAMP_SPOT_ETH_USD
+pub fn coinapi_venue(symbol_id: &str) -> String {
+    let exchange = symbol_id.split('_').next().unwrap_or(symbol_id);
+    let mut chars = exchange.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => exchange.to_string(),
+    }
+}
@@ -0,0 +1,72 @@
+use super::sources::{coinapi_duplicate, coinapi_pair, coinapi_venue, Source};
+
+// The direct sources' default symbols for ETH/USD
+fn default_symbol(source: Source) -> Option<String> {
+    match source {
+        Source::Kraken => Some("ETHPYUSD".to_string()),
+        Source::Binance => Some("ETHUSDT".to_string()),
+        Source::Coinbase => Some("ETH-USD".to_string()),
+        Source::Bitfinex => Some("tETHUSD".to_string()),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_coinapi_venue_from_symbol_id() {
+    assert_eq!(coinapi_venue("BITFINEX_SPOT_ETH_USD"), "Bitfinex");
+    assert_eq!(coinapi_venue("BINANCEUS_SPOT_ETH_USD"), "Binanceus");
+}
+
+#[test]
+fn test_coinapi_pair_from_symbol_id() {
+    assert_eq!(
+        coinapi_pair("KRAKEN_SPOT_ETH_PYUSD"),
+        Some(("ETH".to_string(), "PYUSD".to_string()))
+    );
+    assert_eq!(coinapi_pair("KRAKEN"), None);
+}
+
+#[test]
+fn test_coinapi_duplicate_of_a_direct_source() {
+    assert_eq!(
+        coinapi_duplicate("BITFINEX_SPOT_ETH_USD", default_symbol),
+        Some(Source::Bitfinex)
+    );
+    assert_eq!(
+        coinapi_duplicate("COINBASE_SPOT_ETH_USD", default_symbol),
+        Some(Source::Coinbase)
+    );
+    assert_eq!(
+        coinapi_duplicate("BINANCE_SPOT_ETH_USDT", default_symbol),
+        Some(Source::Binance)
+    );
+    assert_eq!(
+        coinapi_duplicate("KRAKEN_SPOT_ETH_PYUSD", default_symbol),
+        Some(Source::Kraken)
+    );
+    assert_eq!(
+        coinapi_duplicate("BITSTAMP_SPOT_ETH_USD", default_symbol),
+        None
+    );
+    assert_eq!(
+        coinapi_duplicate("BINANCEUS_SPOT_ETH_USD", default_symbol),
+        None
+    );
+}
+
+#[test]
+fn test_coinapi_on_another_pair_is_not_a_duplicate() {
+    // Kraken is fetched directly, but as ETH/PYUSD rather than ETH/USD
+    assert_eq!(
+        coinapi_duplicate("KRAKEN_SPOT_ETH_USD", default_symbol),
+        None
+    );
+    assert_eq!(
+        coinapi_duplicate("BINANCE_SPOT_ETH_USD", default_symbol),
+        None
+    );
+    assert_eq!(
+        coinapi_duplicate("BITFINEX_SPOT_BTC_USD", default_symbol),
+        None
+    );
+}
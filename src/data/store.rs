@@ -0,0 +1,347 @@
+use super::cache::CacheEntry;
+use super::calculate_volatility::SourceVolatility;
+use super::quality::SourceQuality;
+use super::quorum::QuorumPolicy;
+use super::rounding::period_duration;
+use super::sources::Source;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use log::debug;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::path::Path;
+use std::sync::Mutex;
+
+// Times are epoch milliseconds, as in the bar cache
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS bar_coverage (
+    source TEXT NOT NULL,
+    symbol TEXT NOT NULL,
+    time_period TEXT NOT NULL,
+    covered_from INTEGER NOT NULL,
+    covered_to INTEGER NOT NULL,
+    PRIMARY KEY (source, symbol, time_period)
+);
+CREATE TABLE IF NOT EXISTS bars (
+    source TEXT NOT NULL,
+    symbol TEXT NOT NULL,
+    time_period TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    price REAL NOT NULL,
+    fetched_at INTEGER NOT NULL,
+    PRIMARY KEY (source, symbol, time_period, timestamp)
+);
+CREATE TABLE IF NOT EXISTS bar_revisions (
+    source TEXT NOT NULL,
+    symbol TEXT NOT NULL,
+    time_period TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    price REAL NOT NULL,
+    fetched_at INTEGER NOT NULL,
+    new_price REAL,
+    revised_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS estimates (
+    id INTEGER PRIMARY KEY,
+    run_at INTEGER NOT NULL,
+    window_end INTEGER NOT NULL,
+    time_period TEXT NOT NULL,
+    no_of_periods INTEGER NOT NULL,
+    quorum_min_sources INTEGER NOT NULL,
+    quorum_tolerance_bps REAL,
+    quorum_on_fail TEXT NOT NULL,
+    exclude_partial_bar INTEGER NOT NULL,
+    volatility REAL
+);
+CREATE TABLE IF NOT EXISTS estimate_prices (
+    estimate_id INTEGER NOT NULL REFERENCES estimates (id),
+    timestamp INTEGER NOT NULL,
+    composite REAL,
+    vol REAL,
+    PRIMARY KEY (estimate_id, timestamp)
+);
+CREATE TABLE IF NOT EXISTS estimate_sources (
+    estimate_id INTEGER NOT NULL REFERENCES estimates (id),
+    source TEXT NOT NULL,
+    bars_received INTEGER,
+    bars_merged INTEGER,
+    outside_window INTEGER,
+    timestamp_failures INTEGER,
+    coverage REAL NOT NULL,
+    volatility REAL,
+    PRIMARY KEY (estimate_id, source)
+);
+";
+
+// One run's estimate along with what it was computed from
+#[derive(Debug)]
+pub struct EstimateRecord {
+    pub run_at: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+    pub time_period: String,
+    pub no_of_periods: usize,
+    pub quorum: QuorumPolicy,
+    pub exclude_partial_bar: bool,
+    pub volatility: Option<f64>,
+    pub prices: Vec<(DateTime<Utc>, Option<f64>, Option<f64>)>, // Composite and interpolated price
+    pub quality: BTreeMap<Source, SourceQuality>,
+    pub source_volatilities: Vec<SourceVolatility>,
+}
+
+// Summary of a stored estimate, for `history`
+#[derive(Debug)]
+pub struct StoredEstimate {
+    pub id: i64,
+    pub run_at: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+    pub time_period: String,
+    pub no_of_periods: usize,
+    pub volatility: Option<f64>,
+}
+
+// Embedded SQLite store of source bars and estimates
+pub struct Store {
+    conn: Mutex<Connection>,
+}
+
+impl Store {
+    // Open or create the database and its tables
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let conn = Connection::open(path)
+            .map_err(|e| anyhow!("Failed to open SQLite store {:?}: {}", path, e))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Store {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    // Load SQLITE_PATH, None when no store is configured
+    pub fn from_env() -> Result<Option<Self>> {
+        match env::var("SQLITE_PATH") {
+            Ok(path) => Ok(Some(Store::open(path)?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    // The stored bars of one source, symbol and period with the range they cover
+    pub fn load_bars(
+        &self,
+        source: Source,
+        symbol: &str,
+        time_period: &str,
+    ) -> Result<Option<CacheEntry>> {
+        let conn = self.conn.lock().unwrap();
+        let key = params![source.label(), symbol, time_period];
+        let coverage = conn
+            .query_row(
+                "SELECT covered_from, covered_to FROM bar_coverage
+                 WHERE source = ?1 AND symbol = ?2 AND time_period = ?3",
+                key,
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((covered_from, covered_to)) = coverage else {
+            return Ok(None);
+        };
+
+        let mut statement = conn.prepare(
+            "SELECT timestamp, price FROM bars
+             WHERE source = ?1 AND symbol = ?2 AND time_period = ?3 ORDER BY timestamp",
+        )?;
+        let bars = statement
+            .query_map(key, |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(i64, f64)>>>()?;
+        Ok(Some(CacheEntry {
+            covered_from,
+            covered_to,
+            bars,
+        }))
+    }
+
+    // Save a refreshed entry. Bars from `edge_start` on were just fetched, a stored bar they
+    // change or no longer include is kept in `bar_revisions` unless it was still open when it
+    // was fetched, as the open bar changes on every fetch.
+    pub fn save_bars(
+        &self,
+        source: Source,
+        symbol: &str,
+        time_period: &str,
+        entry: &CacheEntry,
+        edge_start: i64,
+    ) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let label = source.label();
+        let now = entry.covered_to;
+        let period = period_duration(time_period).num_milliseconds();
+        let was_closed = |timestamp: i64, fetched_at: i64| timestamp + period <= fetched_at;
+
+        tx.execute(
+            "INSERT INTO bar_coverage (source, symbol, time_period, covered_from, covered_to)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (source, symbol, time_period)
+             DO UPDATE SET covered_from = excluded.covered_from, covered_to = excluded.covered_to",
+            params![
+                label,
+                symbol,
+                time_period,
+                entry.covered_from,
+                entry.covered_to
+            ],
+        )?;
+
+        let mut stored: HashMap<i64, (f64, i64)> = {
+            let mut statement = tx.prepare(
+                "SELECT timestamp, price, fetched_at FROM bars
+                 WHERE source = ?1 AND symbol = ?2 AND time_period = ?3 AND timestamp >= ?4",
+            )?;
+            let rows = statement
+                .query_map(params![label, symbol, time_period, edge_start], |row| {
+                    Ok((row.get(0)?, (row.get(1)?, row.get(2)?)))
+                })?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        let mut revised = 0;
+        for &(timestamp, price) in entry.bars.iter().filter(|(t, _)| *t >= edge_start) {
+            match stored.remove(&timestamp) {
+                Some((old_price, _)) if old_price == price => continue,
+                Some((old_price, fetched_at)) if was_closed(timestamp, fetched_at) => {
+                    revised += 1;
+                    tx.execute(
+                        "INSERT INTO bar_revisions
+                         (source, symbol, time_period, timestamp, price, fetched_at, new_price, revised_at)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                        params![label, symbol, time_period, timestamp, old_price, fetched_at, price, now],
+                    )?;
+                }
+                _ => {}
+            }
+            tx.execute(
+                "INSERT OR REPLACE INTO bars (source, symbol, time_period, timestamp, price, fetched_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![label, symbol, time_period, timestamp, price, now],
+            )?;
+        }
+
+        // Bars the source no longer returns
+        for (timestamp, (old_price, fetched_at)) in stored {
+            if was_closed(timestamp, fetched_at) {
+                revised += 1;
+                tx.execute(
+                    "INSERT INTO bar_revisions
+                     (source, symbol, time_period, timestamp, price, fetched_at, new_price, revised_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, NULL, ?7)",
+                    params![
+                        label,
+                        symbol,
+                        time_period,
+                        timestamp,
+                        old_price,
+                        fetched_at,
+                        now
+                    ],
+                )?;
+            }
+            tx.execute(
+                "DELETE FROM bars
+                 WHERE source = ?1 AND symbol = ?2 AND time_period = ?3 AND timestamp = ?4",
+                params![label, symbol, time_period, timestamp],
+            )?;
+        }
+
+        tx.commit()?;
+        if revised > 0 {
+            debug!("{} revised {} stored bar(s)", label, revised);
+        }
+        Ok(())
+    }
+
+    // Save an estimate with its prices and per-source stats, returning its id
+    pub fn save_estimate(&self, record: &EstimateRecord) -> Result<i64> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO estimates
+             (run_at, window_end, time_period, no_of_periods, quorum_min_sources,
+              quorum_tolerance_bps, quorum_on_fail, exclude_partial_bar, volatility)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                record.run_at.timestamp_millis(),
+                record.window_end.timestamp_millis(),
+                record.time_period,
+                record.no_of_periods as i64,
+                record.quorum.min_sources as i64,
+                record.quorum.tolerance_bps,
+                record.quorum.action.as_str(),
+                record.exclude_partial_bar,
+                record.volatility,
+            ],
+        )?;
+        let id = tx.last_insert_rowid();
+
+        for &(timestamp, composite, vol) in &record.prices {
+            tx.execute(
+                "INSERT INTO estimate_prices (estimate_id, timestamp, composite, vol)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![id, timestamp.timestamp_millis(), composite, vol],
+            )?;
+        }
+
+        // Sources that reported in this run
+        for source_vol in &record.source_volatilities {
+            let quality = record.quality.get(&source_vol.source);
+            if quality.is_none() && source_vol.coverage == 0.0 {
+                continue;
+            }
+            tx.execute(
+                "INSERT INTO estimate_sources
+                 (estimate_id, source, bars_received, bars_merged, outside_window,
+                  timestamp_failures, coverage, volatility)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    id,
+                    source_vol.source.label(),
+                    quality.map(|q| q.bars_received as i64),
+                    quality.map(|q| q.bars_merged as i64),
+                    quality.map(|q| q.outside_window as i64),
+                    quality.map(|q| q.timestamp_failures as i64),
+                    source_vol.coverage,
+                    source_vol.volatility,
+                ],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(id)
+    }
+
+    // The latest `limit` estimates, newest first
+    pub fn recent_estimates(&self, limit: usize) -> Result<Vec<StoredEstimate>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT id, run_at, window_end, time_period, no_of_periods, volatility
+             FROM estimates ORDER BY id DESC LIMIT ?1",
+        )?;
+        let estimates = statement
+            .query_map([limit as i64], |row| {
+                Ok(StoredEstimate {
+                    id: row.get(0)?,
+                    run_at: millis_to_time(row.get(1)?),
+                    window_end: millis_to_time(row.get(2)?),
+                    time_period: row.get(3)?,
+                    no_of_periods: row.get::<_, i64>(4)? as usize,
+                    volatility: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(estimates)
+    }
+}
+
+fn millis_to_time(millis: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(millis).unwrap_or_default()
+}
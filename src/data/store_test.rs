@@ -0,0 +1,175 @@
+use super::bars::SourceBars;
+use super::cache::{BarCache, CacheEntry, CacheMode};
+use super::calculate_volatility::SourceVolatility;
+use super::quality::SourceQuality;
+use super::quorum::QuorumPolicy;
+use super::sources::Source;
+use super::store::{EstimateRecord, Store};
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::Connection;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+fn store_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "volatility_estimator_store_{}_{}.sqlite",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn test_keeps_revised_and_dropped_bars() {
+    let path = store_path("revisions");
+    let store = Store::open(&path).unwrap();
+    assert!(store
+        .load_bars(Source::Kraken, "ETHPYUSD", "hour")
+        .unwrap()
+        .is_none());
+
+    // Hour bars, the last still open when first fetched
+    let first = CacheEntry {
+        covered_from: 0,
+        covered_to: 12_000_000,
+        bars: vec![
+            (0, 2500.0),
+            (3_600_000, 2501.0),
+            (7_200_000, 2502.0),
+            (10_800_000, 2503.0),
+        ],
+    };
+    store
+        .save_bars(Source::Kraken, "ETHPYUSD", "hour", &first, 0)
+        .unwrap();
+
+    // The refetched edge revises one closed bar, no longer has another and completes the
+    // open bar, which isn't a revision
+    let second = CacheEntry {
+        covered_from: 0,
+        covered_to: 20_000_000,
+        bars: vec![(0, 2500.0), (3_600_000, 2499.5), (10_800_000, 2504.0)],
+    };
+    store
+        .save_bars(Source::Kraken, "ETHPYUSD", "hour", &second, 3_600_000)
+        .unwrap();
+
+    let loaded = store
+        .load_bars(Source::Kraken, "ETHPYUSD", "hour")
+        .unwrap()
+        .unwrap();
+    assert_eq!(loaded.covered_to, 20_000_000);
+    assert_eq!(loaded.bars, second.bars);
+
+    let conn = Connection::open(&path).unwrap();
+    let mut statement = conn
+        .prepare("SELECT timestamp, price, new_price FROM bar_revisions ORDER BY timestamp")
+        .unwrap();
+    let revisions: Vec<(i64, f64, Option<f64>)> = statement
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        revisions,
+        vec![(3_600_000, 2501.0, Some(2499.5)), (7_200_000, 2502.0, None)]
+    );
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_saves_estimates_with_their_inputs() {
+    let path = store_path("estimates");
+    let store = Store::open(&path).unwrap();
+    let window_end = DateTime::from_timestamp(1717200000, 0).unwrap();
+
+    for volatility in [Some(0.012), None] {
+        store
+            .save_estimate(&EstimateRecord {
+                run_at: window_end,
+                window_end,
+                time_period: "minute".to_string(),
+                no_of_periods: 2,
+                quorum: QuorumPolicy::default(),
+                exclude_partial_bar: true,
+                volatility,
+                prices: vec![
+                    (
+                        window_end - Duration::minutes(2),
+                        Some(2500.0),
+                        Some(2500.0),
+                    ),
+                    (window_end - Duration::minutes(1), None, Some(2500.0)),
+                ],
+                quality: BTreeMap::from([(Source::Kraken, SourceQuality::default())]),
+                source_volatilities: vec![
+                    SourceVolatility {
+                        source: Source::Kraken,
+                        coverage: 0.5,
+                        volatility: None,
+                    },
+                    SourceVolatility {
+                        source: Source::Dune,
+                        coverage: 0.0,
+                        volatility: None,
+                    },
+                ],
+            })
+            .unwrap();
+    }
+
+    let estimates = store.recent_estimates(10).unwrap();
+    assert_eq!(estimates.len(), 2);
+    assert_eq!(estimates[0].volatility, None);
+    assert_eq!(estimates[1].volatility, Some(0.012));
+    assert_eq!(estimates[1].window_end, window_end);
+
+    // Sources that didn't report are left out
+    let conn = Connection::open(&path).unwrap();
+    let counts: (i64, i64) = conn
+        .query_row(
+            "SELECT (SELECT COUNT(*) FROM estimate_prices WHERE composite IS NULL),
+                    (SELECT COUNT(*) FROM estimate_sources)",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!(counts, (2, 2));
+    let _ = fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_later_runs_read_stored_bars() {
+    let path = store_path("cache");
+    let now = Utc::now();
+    let calls = Cell::new(0);
+
+    // A new store and cache per run, as separate processes would have
+    for _ in 0..2 {
+        let store = Arc::new(Store::open(&path).unwrap());
+        let cache = BarCache::new(CacheMode::On, "unused", Duration::seconds(60)).with_store(store);
+        let bars = cache
+            .fetch(Source::Kraken, "hour", 24, now, |periods| {
+                calls.set(calls.get() + 1);
+                async move {
+                    let bars = (0..=periods)
+                        .map(|i| (now - Duration::hours(i), 2500.0 + i as f64))
+                        .collect();
+                    Ok(SourceBars {
+                        bars,
+                        timestamp_failures: 0,
+                        truncated: false,
+                    })
+                }
+            })
+            .await
+            .unwrap();
+        assert!(bars.bars.len() >= 24, "{}", bars.bars.len());
+    }
+    assert_eq!(calls.get(), 1);
+    let _ = fs::remove_file(&path);
+}
@@ -0,0 +1,81 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::BTreeMap;
+
+// A single trade: (time, price, volume)
+pub type Trade = (DateTime<Utc>, f64, f64);
+
+// Bar aggregated from raw trades
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradeBar {
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub vwap: f64,
+    pub volume: f64,
+    pub trade_count: usize,
+}
+
+impl TradeBar {
+    // Average of open, high, low and close, the price used for exchange OHLC bars
+    pub fn ohlc_average(&self) -> f64 {
+        (self.open + self.high + self.low + self.close) / 4.0
+    }
+}
+
+// Bucket trades into bars of `bar_duration` aligned to the epoch, keyed by bar open time.
+// Works for any bar length, including sub-minute bars no exchange OHLC endpoint serves.
+pub fn build_trade_bars(
+    trades: &[Trade],
+    bar_duration: Duration,
+) -> BTreeMap<DateTime<Utc>, TradeBar> {
+    let bar_millis = bar_duration.num_milliseconds().max(1);
+
+    // Sort a copy so open and close are right even if pages arrive out of order
+    let mut trades = trades.to_vec();
+    trades.sort_by_key(|&(timestamp, _, _)| timestamp);
+
+    let mut bars: BTreeMap<DateTime<Utc>, TradeBar> = BTreeMap::new();
+    // Running sum of price * volume per bar, divided out once all trades are in
+    let mut notional: BTreeMap<DateTime<Utc>, f64> = BTreeMap::new();
+
+    for (timestamp, price, volume) in trades {
+        if !price.is_finite() || !volume.is_finite() {
+            continue;
+        }
+        let millis = timestamp.timestamp_millis();
+        let Some(bar_open) =
+            DateTime::from_timestamp_millis(millis - millis.rem_euclid(bar_millis))
+        else {
+            continue;
+        };
+
+        bars.entry(bar_open)
+            .and_modify(|bar| {
+                bar.high = bar.high.max(price);
+                bar.low = bar.low.min(price);
+                bar.close = price;
+                bar.volume += volume;
+                bar.trade_count += 1;
+            })
+            .or_insert(TradeBar {
+                open: price,
+                high: price,
+                low: price,
+                close: price,
+                vwap: price,
+                volume,
+                trade_count: 1,
+            });
+        *notional.entry(bar_open).or_insert(0.0) += price * volume;
+    }
+
+    for (bar_open, bar) in bars.iter_mut() {
+        // Zero volume trades leave the first trade's price as the VWAP
+        if bar.volume > 0.0 {
+            bar.vwap = notional[bar_open] / bar.volume;
+        }
+    }
+
+    bars
+}
@@ -0,0 +1,48 @@
+use super::trade_bars::build_trade_bars;
+use chrono::{Duration, TimeZone, Utc};
+
+#[test]
+fn test_build_second_bars_from_trades() {
+    let base = Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap();
+    let trades = vec![
+        (base + Duration::milliseconds(100), 2000.0, 1.0),
+        (base + Duration::milliseconds(900), 2004.0, 3.0),
+        (base + Duration::milliseconds(500), 1998.0, 1.0),
+        // Next second, a single trade
+        (base + Duration::milliseconds(1200), 2010.0, 0.5),
+    ];
+
+    let bars = build_trade_bars(&trades, Duration::seconds(1));
+    assert_eq!(bars.len(), 2);
+
+    // Trades are ordered by time before the open and close are taken
+    let first = &bars[&base];
+    assert_eq!(first.open, 2000.0);
+    assert_eq!(first.high, 2004.0);
+    assert_eq!(first.low, 1998.0);
+    assert_eq!(first.close, 2004.0);
+    assert_eq!(first.volume, 5.0);
+    assert_eq!(first.trade_count, 3);
+    assert!((first.vwap - 2002.0).abs() < 1e-9);
+    assert!((first.ohlc_average() - 2001.5).abs() < 1e-9);
+
+    let second = &bars[&(base + Duration::seconds(1))];
+    assert_eq!(second.vwap, 2010.0);
+    assert_eq!(second.trade_count, 1);
+}
+
+#[test]
+fn test_build_sub_minute_bars() {
+    let base = Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap();
+    let trades = vec![
+        (base + Duration::seconds(3), 100.0, 1.0),
+        (base + Duration::seconds(14), 102.0, 1.0),
+        (base + Duration::seconds(16), 104.0, 1.0),
+    ];
+
+    // 15 second bars split at :15
+    let bars = build_trade_bars(&trades, Duration::seconds(15));
+    let opens: Vec<_> = bars.keys().copied().collect();
+    assert_eq!(opens, vec![base, base + Duration::seconds(15)]);
+    assert_eq!(bars[&base].vwap, 101.0);
+}
@@ -0,0 +1,244 @@
+use super::bars::SourceBars;
+use super::eth_rpc::{
+    abi_words, hex_to_u64, is_log_range_error, signed_word_to_f64, word_to_f64, EthRpc, RpcLog,
+};
+use super::http::Http;
+use super::rounding::{period_duration, periods_before, BarStamp};
+use super::sources::Source;
+use super::timestamp::parse_epoch;
+use super::trade_bars::{build_trade_bars, Trade};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+
+// keccak256("Swap(address,uint256,uint256,uint256,uint256,address)")
+const SWAP_V2_TOPIC: &str = "0xd78ad95fa46c994b6551d0da85fc275fe613ce37657fb8d5e3d130840159d822";
+
+// keccak256("Swap(address,address,int256,int256,uint160,uint128,int24)")
+const SWAP_V3_TOPIC: &str = "0xc42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67";
+
+// Uniswap v3 USDC / WETH 0.05% pool on Ethereum
+const DEFAULT_POOL: &str = "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640";
+
+// Block timestamps are fetched in JSON-RPC batches of this size
+const TIMESTAMP_BATCH: usize = 100;
+
+// Bars are built from swaps, keyed by the bar they fall in
+pub const BAR_STAMP: BarStamp = BarStamp::Open;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PoolVersion {
+    V2,
+    V3,
+}
+
+// The pool read and how its token amounts become a price
+#[derive(Debug, Clone)]
+pub struct UniswapPool {
+    pub address: String,
+    pub version: PoolVersion,
+    pub token0_decimals: i32,
+    pub token1_decimals: i32,
+    pub base_is_token1: bool, // Price token1 in token0 (WETH in USDC for the default pool)
+}
+
+impl UniswapPool {
+    // Load UNISWAP_POOL, UNISWAP_VERSION, UNISWAP_TOKEN0_DECIMALS, UNISWAP_TOKEN1_DECIMALS and
+    // UNISWAP_BASE_TOKEN, defaulting to the USDC / WETH 0.05% pool
+    pub fn from_env() -> Result<Self> {
+        let version = match env::var("UNISWAP_VERSION")
+            .unwrap_or("v3".to_string())
+            .as_str()
+        {
+            "v2" => PoolVersion::V2,
+            "v3" => PoolVersion::V3,
+            _ => return Err(anyhow!("UNISWAP_VERSION must be v2 or v3")),
+        };
+        let base_is_token1 = match env::var("UNISWAP_BASE_TOKEN")
+            .unwrap_or("token1".to_string())
+            .as_str()
+        {
+            "token0" => false,
+            "token1" => true,
+            _ => return Err(anyhow!("UNISWAP_BASE_TOKEN must be token0 or token1")),
+        };
+        Ok(UniswapPool {
+            address: env::var("UNISWAP_POOL").unwrap_or(DEFAULT_POOL.to_string()),
+            version,
+            token0_decimals: env::var("UNISWAP_TOKEN0_DECIMALS")
+                .unwrap_or("6".to_string())
+                .parse()
+                .expect("UNISWAP_TOKEN0_DECIMALS must be a valid integer"),
+            token1_decimals: env::var("UNISWAP_TOKEN1_DECIMALS")
+                .unwrap_or("18".to_string())
+                .parse()
+                .expect("UNISWAP_TOKEN1_DECIMALS must be a valid integer"),
+            base_is_token1,
+        })
+    }
+
+    fn swap_topic(&self) -> &'static str {
+        match self.version {
+            PoolVersion::V2 => SWAP_V2_TOPIC,
+            PoolVersion::V3 => SWAP_V3_TOPIC,
+        }
+    }
+}
+
+// Read the pool's Swap logs over the window from ETH_RPC_URL and build VWAP bars from them.
+// Works down to second bars, limited only by the chain's block time.
+pub async fn get_uniswap_data(
+    http: &Http,
+    time_period: &str,
+    no_of_periods: i64,
+) -> Result<SourceBars> {
+    let rpc_url = env::var("ETH_RPC_URL").map_err(|_| anyhow!("ETH_RPC_URL must be set"))?;
+    let pool = UniswapPool::from_env()?;
+    // Many hosted nodes cap the block range of a single eth_getLogs call
+    let block_range: u64 = env::var("UNISWAP_BLOCK_RANGE")
+        .unwrap_or("2000".to_string())
+        .parse()
+        .expect("UNISWAP_BLOCK_RANGE must be a valid integer");
+
+    let window_start = periods_before(Utc::now(), time_period, no_of_periods)?;
+
+    read_uniswap_bars(
+        http,
+        &rpc_url,
+        &pool,
+        block_range,
+        time_period,
+        window_start,
+    )
+    .await
+}
+
+// Read the pool's swaps from `window_start` to the latest block into bars
+pub async fn read_uniswap_bars(
+    http: &Http,
+    rpc_url: &str,
+    pool: &UniswapPool,
+    mut block_range: u64,
+    time_period: &str,
+    window_start: DateTime<Utc>,
+) -> Result<SourceBars> {
+    let rpc = EthRpc::new(http, Source::Uniswap, rpc_url);
+    let period = period_duration(time_period);
+
+    let mut from_block = rpc.block_at_or_after(window_start).await?;
+    let latest_block = rpc.block_number().await?;
+    debug!(
+        "Reading {} swaps from block {} to {}",
+        pool.address, from_block, latest_block
+    );
+
+    let mut trades: Vec<Trade> = vec![];
+    let mut timestamp_failures = 0;
+    let mut block_times: HashMap<u64, DateTime<Utc>> = HashMap::new();
+
+    while from_block <= latest_block {
+        let to_block = (from_block + block_range.max(1) - 1).min(latest_block);
+        let logs = match rpc
+            .get_logs(&pool.address, &[pool.swap_topic()], from_block, to_block)
+            .await
+        {
+            Ok(logs) => logs,
+            // Too many results or too wide a range, retry with half the range
+            Err(e) if block_range > 1 && is_log_range_error(&e) => {
+                block_range /= 2;
+                warn!(
+                    "eth_getLogs failed ({}), retrying with a range of {} blocks",
+                    e, block_range
+                );
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        // Only ask the node for timestamps the logs didn't carry
+        let missing: BTreeSet<u64> = logs
+            .iter()
+            .filter(|log| log.block_timestamp.is_none())
+            .filter_map(|log| hex_to_u64(&log.block_number).ok())
+            .filter(|block| !block_times.contains_key(block))
+            .collect();
+        let missing: Vec<u64> = missing.into_iter().collect();
+        for blocks in missing.chunks(TIMESTAMP_BATCH) {
+            block_times.extend(rpc.block_timestamps(blocks).await?);
+        }
+
+        for log in &logs {
+            let Some((price, volume)) = decode_swap(log, pool) else {
+                continue;
+            };
+            let Some(timestamp) = log_time(log, &block_times) else {
+                timestamp_failures += 1;
+                continue;
+            };
+            trades.push((timestamp, price, volume));
+        }
+
+        from_block = to_block + 1;
+    }
+
+    // Blocks before the window start can't appear, but the window end is open
+    let bars = build_trade_bars(&trades, period)
+        .into_iter()
+        .filter(|&(bar_open, _)| bar_open + period > window_start)
+        .map(|(bar_open, bar)| (bar_open, bar.vwap))
+        .collect();
+    Ok(SourceBars {
+        bars,
+        timestamp_failures,
+        truncated: false,
+    })
+}
+
+fn log_time(log: &RpcLog, block_times: &HashMap<u64, DateTime<Utc>>) -> Option<DateTime<Utc>> {
+    match &log.block_timestamp {
+        Some(timestamp) => parse_epoch(hex_to_u64(timestamp).ok()? as i64),
+        None => block_times
+            .get(&hex_to_u64(&log.block_number).ok()?)
+            .copied(),
+    }
+}
+
+// Decode a Swap log into (price of the base token in the quote token, base volume).
+// Returns None for logs of another event or swaps with a zero side.
+pub fn decode_swap(log: &RpcLog, pool: &UniswapPool) -> Option<(f64, f64)> {
+    if !log
+        .topics
+        .first()
+        .is_some_and(|topic| topic.eq_ignore_ascii_case(pool.swap_topic()))
+    {
+        return None;
+    }
+    let words = abi_words(&log.data).ok()?;
+
+    let (amount0, amount1) = match pool.version {
+        // amount0In, amount1In, amount0Out, amount1Out
+        PoolVersion::V2 if words.len() >= 4 => (
+            word_to_f64(&words[0]) + word_to_f64(&words[2]),
+            word_to_f64(&words[1]) + word_to_f64(&words[3]),
+        ),
+        // Signed pool deltas: amount0, amount1, sqrtPriceX96, liquidity, tick
+        PoolVersion::V3 if words.len() >= 2 => (
+            signed_word_to_f64(&words[0]).abs(),
+            signed_word_to_f64(&words[1]).abs(),
+        ),
+        _ => return None,
+    };
+
+    let amount0 = amount0 / 10f64.powi(pool.token0_decimals);
+    let amount1 = amount1 / 10f64.powi(pool.token1_decimals);
+    if amount0 <= 0.0 || amount1 <= 0.0 {
+        return None;
+    }
+    if pool.base_is_token1 {
+        Some((amount0 / amount1, amount1))
+    } else {
+        Some((amount1 / amount0, amount0))
+    }
+}
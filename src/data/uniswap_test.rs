@@ -0,0 +1,54 @@
+use super::eth_rpc::{abi_words, signed_word_to_f64, RpcLog};
+use super::uniswap::{decode_swap, PoolVersion, UniswapPool};
+
+fn pool(version: PoolVersion) -> UniswapPool {
+    UniswapPool {
+        address: "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640".to_string(),
+        version,
+        token0_decimals: 6,
+        token1_decimals: 18,
+        base_is_token1: true,
+    }
+}
+
+fn recorded_logs() -> Vec<RpcLog> {
+    serde_json::from_str(include_str!("fixtures/uniswap_logs.json")).unwrap()
+}
+
+#[test]
+fn test_decode_v3_swap() {
+    let logs = recorded_logs();
+
+    // 2500.25 USDC in for 1 WETH out
+    let (price, volume) = decode_swap(&logs[0], &pool(PoolVersion::V3)).unwrap();
+    assert!((price - 2500.25).abs() < 1e-9);
+    assert!((volume - 1.0).abs() < 1e-12);
+
+    // Pricing the other token inverts the price
+    let mut inverted = pool(PoolVersion::V3);
+    inverted.base_is_token1 = false;
+    let (price, volume) = decode_swap(&logs[0], &inverted).unwrap();
+    assert!((price - 1.0 / 2500.25).abs() < 1e-15);
+    assert!((volume - 2500.25).abs() < 1e-9);
+}
+
+#[test]
+fn test_decode_v2_swap() {
+    let logs = recorded_logs();
+
+    // 2 WETH in for 5001.1 USDC out
+    let (price, volume) = decode_swap(&logs[1], &pool(PoolVersion::V2)).unwrap();
+    assert!((price - 2500.55).abs() < 1e-9);
+    assert!((volume - 2.0).abs() < 1e-12);
+
+    // A v2 log isn't a v3 Swap event
+    assert!(decode_swap(&logs[1], &pool(PoolVersion::V3)).is_none());
+}
+
+#[test]
+fn test_signed_words() {
+    let words = abi_words(&recorded_logs()[0].data).unwrap();
+    assert_eq!(words.len(), 5);
+    assert_eq!(signed_word_to_f64(&words[0]), 2_500_250_000.0);
+    assert_eq!(signed_word_to_f64(&words[1]), -1e18);
+}
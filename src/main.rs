@@ -1,9 +1,10 @@
 use anyhow::Result;
-use chrono::{Duration, NaiveDateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use dotenv::dotenv;
 use log::debug;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
+use std::future::Future;
 use std::sync::{Arc, RwLock};
 use tokio::task;
 
@@ -13,16 +14,54 @@ use coinapi::get_coin_api_data;
 
 #[path = "./calc/calculate_volatility.rs"]
 mod calculate_volatility;
-use calculate_volatility::calculate_volatility;
+use calculate_volatility::{calculate_source_volatilities, calculate_volatility, composite_price};
+
+#[path = "./calc/rolling.rs"]
+mod rolling;
+use rolling::RollingVolatility;
+
+#[path = "./calc/basis.rs"]
+mod basis;
+use basis::calculate_basis;
+
+#[path = "./calc/quality.rs"]
+mod quality;
+use quality::{print_quality_report, QualityReport, SourceQuality};
+
+#[path = "./calc/quorum.rs"]
+mod quorum;
+use quorum::QuorumPolicy;
 
 #[path = "./util/rounding.rs"]
 mod rounding;
-use rounding::round_to_period;
+use rounding::{period_duration, round_to_period, BarStamp};
+
+#[path = "./util/schedule.rs"]
+mod schedule;
+use schedule::{next_boundary, Shutdown};
+
+#[path = "./util/timestamp.rs"]
+mod timestamp;
+use timestamp::parse_timestamp;
+
+#[path = "./data/bars.rs"]
+mod bars;
+use bars::SourceBars;
+
+#[path = "./data/candles.rs"]
+mod candles;
+
+#[path = "./data/trade_bars.rs"]
+mod trade_bars;
 
 #[path = "./data/dune.rs"]
 mod dune;
 use dune::fetch_dune_data;
 
+#[path = "./data/dune_sql.rs"]
+mod dune_sql;
+use dune_sql::{generate_dune_sql, DuneSqlSpec};
+
 #[path = "./data/kraken.rs"]
 mod kraken;
 use kraken::get_kraken_data;
@@ -31,24 +70,199 @@ use kraken::get_kraken_data;
 mod polygon2;
 use polygon2::get_polygon_data;
 
+#[path = "./data/binance.rs"]
+mod binance;
+use binance::get_binance_data;
+
+#[path = "./data/coinbase.rs"]
+mod coinbase;
+use coinbase::get_coinbase_data;
+
+#[path = "./data/bitfinex.rs"]
+mod bitfinex;
+use bitfinex::get_bitfinex_data;
+
+#[path = "./data/eth_rpc.rs"]
+mod eth_rpc;
+
+#[path = "./data/uniswap.rs"]
+mod uniswap;
+use uniswap::get_uniswap_data;
+
+#[path = "./data/chainlink.rs"]
+mod chainlink;
+use chainlink::get_chainlink_data;
+
+#[path = "./data/file_source.rs"]
+mod file_source;
+use file_source::{get_file_data, FileSource};
+
+#[path = "./data/sources.rs"]
+mod sources;
+use sources::{coinapi_duplicate, coinapi_symbol_id, direct_symbol, Source};
+
+#[path = "./data/http.rs"]
+mod http;
+use http::Http;
+
+#[path = "./data/http_client.rs"]
+mod http_client;
+
+#[path = "./data/retry.rs"]
+mod retry;
+
+#[path = "./data/cache.rs"]
+mod cache;
+use cache::{BarCache, CacheMode};
+
+#[path = "./data/store.rs"]
+mod store;
+use store::{EstimateRecord, Store};
+
+#[cfg(feature = "postgres")]
+#[path = "./data/postgres_sink.rs"]
+mod postgres_sink;
+#[cfg(feature = "postgres")]
+use postgres_sink::PostgresOutput;
+
 #[cfg(test)]
 #[path = "./calc/calculate_volatility_test.rs"]
 mod calculate_volatility_test;
 
-type ResultsMap = Arc<
-    RwLock<
-        HashMap<
-            NaiveDateTime,
-            (
-                Option<f64>,
-                Option<f64>,
-                Option<f64>,
-                Option<f64>,
-                Option<f64>,
-            ),
-        >,
-    >,
->;
+#[cfg(test)]
+#[path = "./calc/rolling_test.rs"]
+mod rolling_test;
+
+#[cfg(test)]
+#[path = "./calc/basis_test.rs"]
+mod basis_test;
+
+#[cfg(test)]
+#[path = "./calc/test_util.rs"]
+mod test_util;
+
+#[cfg(test)]
+#[path = "./util/rounding_test.rs"]
+mod rounding_test;
+
+#[cfg(test)]
+#[path = "./util/timestamp_test.rs"]
+mod timestamp_test;
+
+#[cfg(test)]
+#[path = "./util/schedule_test.rs"]
+mod schedule_test;
+
+#[cfg(test)]
+#[path = "./data/trade_bars_test.rs"]
+mod trade_bars_test;
+
+#[cfg(test)]
+#[path = "./data/dune_sql_test.rs"]
+mod dune_sql_test;
+
+#[cfg(test)]
+#[path = "./data/dune_test.rs"]
+mod dune_test;
+
+#[cfg(test)]
+#[path = "./data/binance_test.rs"]
+mod binance_test;
+
+#[cfg(test)]
+#[path = "./data/coinbase_test.rs"]
+mod coinbase_test;
+
+#[cfg(test)]
+#[path = "./data/bitfinex_test.rs"]
+mod bitfinex_test;
+
+#[cfg(test)]
+#[path = "./data/sources_test.rs"]
+mod sources_test;
+
+#[cfg(test)]
+#[path = "./data/uniswap_test.rs"]
+mod uniswap_test;
+
+#[cfg(test)]
+#[path = "./data/chainlink_test.rs"]
+mod chainlink_test;
+
+#[cfg(test)]
+#[path = "./data/file_source_test.rs"]
+mod file_source_test;
+
+#[cfg(test)]
+#[path = "./data/http_test.rs"]
+mod http_test;
+
+#[cfg(test)]
+#[path = "./data/http_client_test.rs"]
+mod http_client_test;
+
+#[cfg(test)]
+#[path = "./data/merge_test.rs"]
+mod merge_test;
+
+#[cfg(test)]
+#[path = "./data/retry_test.rs"]
+mod retry_test;
+
+#[cfg(test)]
+#[path = "./data/cache_test.rs"]
+mod cache_test;
+
+#[cfg(test)]
+#[path = "./data/store_test.rs"]
+mod store_test;
+
+#[cfg(all(test, feature = "postgres"))]
+#[path = "./data/postgres_sink_test.rs"]
+mod postgres_sink_test;
+
+// Each source's price and the composite VOL price for one bar
+#[derive(Debug, Default, Clone)]
+struct PriceRow {
+    prices: BTreeMap<Source, f64>, // Only sources that reported this bar
+    vol: Option<f64>,
+}
+
+type ResultsMap = Arc<RwLock<HashMap<DateTime<Utc>, PriceRow>>>;
+
+// Everything a run needs from the env, loaded once and reused by each daemon run
+#[derive(Debug, Clone)]
+struct RunSettings {
+    time_period: String,
+    no_of_periods: usize,
+    quorum: QuorumPolicy,
+    min_source_coverage: f64,
+    file_source: Option<FileSource>,
+    file_source_only: bool,
+    exclude_partial_bar: bool,
+}
+
+// Longest window accepted, e.g. about 11 days of second bars or 114 years of hourly ones
+const MAX_NO_OF_PERIODS: usize = 1_000_000;
+
+// Where each run's results are saved besides being printed
+struct Outputs {
+    store: Option<Arc<Store>>,
+    #[cfg(feature = "postgres")]
+    postgres: Option<PostgresOutput>,
+}
+
+impl Outputs {
+    #[cfg(feature = "postgres")]
+    fn has_postgres(&self) -> bool {
+        self.postgres.is_some()
+    }
+
+    #[cfg(not(feature = "postgres"))]
+    fn has_postgres(&self) -> bool {
+        false
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -65,9 +279,13 @@ async fn main() -> Result<()> {
         .expect("NO_OF_PERIODS must be a valid integer");
 
     // Check that NO_OF_PERIODS is in a reasonable range
-    if no_of_periods == 0 || no_of_periods >= 741 {
+    if no_of_periods == 0 {
+        return Err(anyhow::anyhow!("NO_OF_PERIODS must be greater than 0"));
+    }
+    if no_of_periods > MAX_NO_OF_PERIODS {
         return Err(anyhow::anyhow!(
-            "NO_OF_PERIODS must be greater than 0 and less than 741"
+            "NO_OF_PERIODS must be at most {}",
+            MAX_NO_OF_PERIODS
         ));
     }
 
@@ -80,22 +298,233 @@ async fn main() -> Result<()> {
         ));
     }
 
-    // Convert `no_of_periods` to `i64`
-    let no_of_periods_i64 = no_of_periods.try_into().unwrap();
+    // `dune sql` prints the Dune query for the configured period, pair, DEX and chain,
+    // `daemon` keeps re-estimating on every period boundary, `history` lists stored estimates
+    let args: Vec<String> = env::args().skip(1).collect();
+    let daemon = match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [] => false,
+        ["daemon"] => true,
+        ["dune", "sql"] => {
+            let spec = DuneSqlSpec::from_env(&time_period, no_of_periods)?;
+            println!("{}", generate_dune_sql(&spec)?);
+            return Ok(());
+        }
+        ["history", rest @ ..] if rest.len() <= 1 => {
+            let limit: usize = match rest.first() {
+                Some(limit) => limit
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("history takes a number of estimates"))?,
+                None => 20,
+            };
+            let store = Store::from_env()?
+                .ok_or_else(|| anyhow::anyhow!("history needs SQLITE_PATH to be set"))?;
+            for estimate in store.recent_estimates(limit)? {
+                println!(
+                    "#{} at {}: window to {}, {} {}s, volatility = {}",
+                    estimate.id,
+                    estimate.run_at,
+                    estimate.window_end,
+                    estimate.no_of_periods,
+                    estimate.time_period,
+                    estimate
+                        .volatility
+                        .map_or("none".to_string(), |v| format!("{:.6}", v))
+                );
+            }
+            return Ok(());
+        }
+        _ => {
+            return Err(anyhow::anyhow!(
+            "Unknown command: {}. Run with no arguments, `daemon`, `history [count]` or `dune sql`",
+            args.join(" ")
+        ))
+        }
+    };
 
-    // Determine the duration based on TIME_PERIOD
-    let time_duration = match time_period.as_str() {
-        "second" => Duration::seconds(1),
-        "minute" => Duration::minutes(1),
-        "hour" => Duration::hours(1),
-        "day" => Duration::days(1),
-        _ => Duration::hours(1), // Default to 'hour' if the provided value is invalid
+    // Load the per-bar source quorum, defaults to a single source
+    let quorum = QuorumPolicy::from_env()?;
+
+    // Minimum fraction of bars a source must cover to get its own volatility estimate
+    let min_source_coverage: f64 = env::var("SOURCE_MIN_COVERAGE")
+        .unwrap_or("0.8".to_string())
+        .parse()
+        .expect("SOURCE_MIN_COVERAGE must be a valid number");
+    if !(0.0..=1.0).contains(&min_source_coverage) {
+        return Err(anyhow::anyhow!(
+            "SOURCE_MIN_COVERAGE must be between 0 and 1"
+        ));
+    }
+
+    // Historical runs (e.g. from files) can end the window at WINDOW_END instead of now
+    let window_end = match env::var("WINDOW_END") {
+        Ok(raw) => Some(parse_timestamp(&raw)?),
+        Err(_) => None,
+    };
+
+    // Local CSV or Parquet data, merged with the live sources or used on its own
+    let file_source = FileSource::from_env()?;
+    let file_source_only: bool = env::var("FILE_SOURCE_ONLY")
+        .unwrap_or("false".to_string())
+        .parse()
+        .expect("FILE_SOURCE_ONLY must be true or false");
+    if file_source_only && file_source.is_none() {
+        return Err(anyhow::anyhow!(
+            "FILE_SOURCE_ONLY needs FILE_SOURCE_PATH to be set"
+        ));
+    }
+
+    // The bar containing `now` is still forming, by default only completed bars are used
+    let exclude_partial_bar: bool = env::var("EXCLUDE_PARTIAL_BAR")
+        .unwrap_or("true".to_string())
+        .parse()
+        .expect("EXCLUDE_PARTIAL_BAR must be true or false");
+
+    // Shared by every adapter, records or replays responses per HTTP_MODE
+    let http = Arc::new(Http::from_env()?);
+
+    // Bars and estimates kept in SQLite at SQLITE_PATH, if set
+    let store = Store::from_env()?.map(Arc::new);
+
+    // Bars already fetched by earlier runs, per CACHE_MODE. The daemon and a configured store
+    // cache by default so each run only fetches the new bars.
+    let default_cache_mode = if daemon || store.is_some() {
+        CacheMode::On
+    } else {
+        CacheMode::Off
+    };
+    let mut bar_cache = BarCache::from_env(default_cache_mode)?;
+    if let Some(store) = &store {
+        bar_cache = bar_cache.with_store(Arc::clone(store));
+    }
+    let bar_cache = Arc::new(bar_cache);
+
+    let settings = RunSettings {
+        time_period,
+        no_of_periods,
+        quorum,
+        min_source_coverage,
+        file_source,
+        file_source_only,
+        exclude_partial_bar,
+    };
+
+    if daemon && window_end.is_some() {
+        return Err(anyhow::anyhow!(
+            "WINDOW_END can't be used with `daemon`, which always estimates up to now"
+        ));
+    }
+    // The live adapters always fetch up to now, so a past window can only come from files or
+    // the cache
+    if window_end.is_some() && !file_source_only && bar_cache.mode() != CacheMode::Offline {
+        return Err(anyhow::anyhow!(
+            "WINDOW_END needs FILE_SOURCE_ONLY=true or CACHE_MODE=offline, the live sources \
+             only fetch up to now"
+        ));
+    }
+
+    let mut outputs = Outputs {
+        store,
+        // Dashboards' copy of the results at POSTGRES_URL, if set
+        #[cfg(feature = "postgres")]
+        postgres: PostgresOutput::from_env()?,
     };
 
-    // Initialize the starting timestamp (now - time_period)
-    let mut current_timestamp = Utc::now().naive_utc();
+    if daemon {
+        return run_daemon(&settings, &http, &bar_cache, &mut outputs).await;
+    }
+    run_estimate(
+        &settings,
+        &http,
+        &bar_cache,
+        &mut outputs,
+        window_end.unwrap_or_else(Utc::now),
+        None,
+    )
+    .await
+}
+
+// Re-estimate at every period boundary until SIGTERM or Ctrl-C. A failed run is reported
+// and the next boundary tried again, an interrupted run is finished before exiting.
+async fn run_daemon(
+    settings: &RunSettings,
+    http: &Arc<Http>,
+    bar_cache: &Arc<BarCache>,
+    outputs: &mut Outputs,
+) -> Result<()> {
+    // Sources need a moment after the boundary to publish the bar that just closed
+    let settle = Duration::milliseconds(
+        env::var("DAEMON_SETTLE_MS")
+            .unwrap_or("2000".to_string())
+            .parse()
+            .expect("DAEMON_SETTLE_MS must be a valid integer"),
+    );
+    let mut shutdown = Shutdown::new()?;
+    // Carried across runs so each one only applies the bars that are new or revised
+    let mut rolling = RollingVolatility::new(settings.no_of_periods);
+
+    loop {
+        let window_end = Utc::now();
+        println!("Estimating at {}", window_end);
+        if let Err(e) = run_estimate(
+            settings,
+            http,
+            bar_cache,
+            outputs,
+            window_end,
+            Some(&mut rolling),
+        )
+        .await
+        {
+            println!("Estimate at {} failed: {:?}", window_end, e);
+        }
+
+        let next_run = next_boundary(Utc::now(), &settings.time_period, settle);
+        debug!("Next estimate at {}", next_run);
+        let wait = (next_run - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = shutdown.recv() => {
+                println!("Shutting down");
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Fetch every source over the window ending at `window_end`, then estimate, print and save
+// to `outputs`. `rolling` is the daemon's running estimate, updated with this run's bars.
+async fn run_estimate(
+    settings: &RunSettings,
+    http: &Arc<Http>,
+    bar_cache: &Arc<BarCache>,
+    outputs: &mut Outputs,
+    window_end: DateTime<Utc>,
+    rolling: Option<&mut RollingVolatility>,
+) -> Result<()> {
+    let time_period = settings.time_period.clone();
+    let no_of_periods = settings.no_of_periods;
+    let no_of_periods_i64: i64 = no_of_periods.try_into().unwrap();
+    let quorum = settings.quorum.clone();
+    let min_source_coverage = settings.min_source_coverage;
+    let file_source = settings.file_source.clone();
+    let file_source_only = settings.file_source_only;
+    let exclude_partial_bar = settings.exclude_partial_bar;
+
+    // Determine the duration based on TIME_PERIOD
+    let time_duration = period_duration(&time_period);
+
+    // Open time of the most recent bar in the window, bars after it are discarded
+    let last_bar_open = last_bar_open(window_end, &time_period, exclude_partial_bar);
+
+    // Initialize the starting timestamp (the most recent bar's open time)
+    let mut current_timestamp = last_bar_open;
 
-    // Initialize a results_map with a 5 price vector
+    // Initialize a results_map with a row per bar
     // (includes price 'VOLPrice' used for calculation)
     let results_map: ResultsMap = Arc::new(RwLock::new(HashMap::new()));
 
@@ -108,164 +537,194 @@ async fn main() -> Result<()> {
             let rounded_timestamp = round_to_period(current_timestamp, &time_period);
 
             // Insert the rounded timestamp into the map with default values
-            map.insert(rounded_timestamp, (None, None, None, None, None));
+            map.insert(rounded_timestamp, PriceRow::default());
 
             // Move to the previous time period
-            current_timestamp = current_timestamp - time_duration;
+            current_timestamp -= time_duration;
 
             // Debug output to verify the timestamps
             debug!("{}", rounded_timestamp);
         }
     }
 
-    // THread safe for multiple sources with different response times
-    // Spawn tasks to fetch data asynchronously and update the results_map
-    let polygon_map = Arc::clone(&results_map);
-    let polygon_time_period = time_period.clone();
-    let polygon_task = tokio::spawn(async move {
-        println!("Fetching Polygon data...");
-        if let Err(e) = get_polygon_data(&polygon_time_period, no_of_periods_i64)
-            .await
-            .map(|polygon_data| {
-                // Store the results in a local vector first
-                let rounded_entries: Vec<_> = polygon_data
-                    .into_iter()
-                    .map(|(timestamp, vw)| {
-                        let rounded_time = round_to_period(timestamp, &polygon_time_period);
-                        (rounded_time, vw)
-                    })
-                    .collect();
-                // Lock the map only when updating it
-                {
-                    let mut map = polygon_map.write().unwrap();
-                    for (rounded_time, vw) in rounded_entries {
-                        if map.contains_key(&rounded_time) {
-                            debug!("Updating existing entry: {} with vw: {}", rounded_time, vw);
-                        } else {
-                            debug!("Inserting new entry: {} with vw: {}", rounded_time, vw);
-                        }
-
-                        map.entry(rounded_time)
-                            .and_modify(|e| e.0 = Some(vw))
-                            .or_insert((Some(vw), None, None, None, None)); // Set Polygon, others remain None
-                    }
-
-                    debug!("Map after insertion: {:?}", *map);
-                } // Lock is released here
-            })
-        {
-            println!("Failed to fetch Polygon data: {:?}", e);
+    // Per-source counts of received, merged and dropped bars
+    let quality_report: QualityReport = Arc::new(RwLock::new(BTreeMap::new()));
+
+    // The live APIs are skipped when the file source is used on its own
+    if !file_source_only {
+        // Shared by every source's fetch task, each merges into the results_map as it finishes
+        let fetcher = SourceFetcher {
+            results_map: &results_map,
+            quality_report: &quality_report,
+            http,
+            bar_cache,
+            time_period: &time_period,
+            no_of_periods: no_of_periods_i64,
+            window_end,
+            last_bar_open,
+        };
+
+        fetcher
+            .fetch(
+                Source::Polygon,
+                polygon2::BAR_STAMP,
+                |http, time_period, periods| async move {
+                    get_polygon_data(&http, &time_period, periods).await
+                },
+            )
+            .await;
+
+        fetcher
+            .fetch(
+                Source::Dune,
+                dune::BAR_STAMP,
+                |http, time_period, periods| async move {
+                    fetch_dune_data(&http, &time_period, periods).await
+                },
+            )
+            .await;
+
+        fetcher
+            .fetch(
+                Source::Kraken,
+                kraken::BAR_STAMP,
+                |http, time_period, periods| async move {
+                    get_kraken_data(&http, &time_period, periods).await
+                },
+            )
+            .await;
+
+        // CoinAPI on an exchange fetched directly would count its prices twice
+        let coin_api_symbol_id = coinapi_symbol_id();
+        if let Some(duplicate) = coinapi_duplicate(&coin_api_symbol_id, direct_symbol) {
+            println!(
+                "Skipping CoinAPI, {} is already fetched from {}",
+                coin_api_symbol_id,
+                duplicate.label()
+            );
+        } else {
+            fetcher
+                .fetch(
+                    Source::CoinApi,
+                    coinapi::BAR_STAMP,
+                    |http, time_period, periods| async move {
+                        get_coin_api_data(&http, &time_period, periods).await
+                    },
+                )
+                .await;
         }
-    });
 
-    // Await the polygon_task separately
-    if let Err(e) = polygon_task.await {
-        println!("Polygon task failed to complete: {:?}", e);
-    }
-
-    // Now proceed to start the Dune data fetch
-    let dune_map = Arc::clone(&results_map);
-    let dune_time_period = time_period.clone();
-    let dune_task = tokio::spawn(async move {
-        println!("Fetching Dune data...");
-        if let Err(e) = fetch_dune_data(&dune_time_period, no_of_periods.try_into().unwrap())
-            .await
-            .map(|dune_prices| {
-                {
-                    let mut map = dune_map.write().unwrap();
-                    for (day_str, aprice) in dune_prices {
-                        if let Ok(timestamp) =
-                            NaiveDateTime::parse_from_str(&day_str, "%Y-%m-%d %H:%M:%S%.f %Z")
-                        {
-                            let rounded_time = round_to_period(timestamp, &dune_time_period);
-                            debug!("Dune Time & Price: {}   {}", rounded_time, aprice);
-                            map.entry(rounded_time)
-                                .and_modify(|e| e.1 = Some(aprice))
-                                .or_insert((None, Some(aprice), None, None, None));
-                        } else {
-                            println!("Skipping insertion due to invalid timestamp.");
-                        }
-                    }
-                } // Lock is released here !
-            })
-        {
-            println!("Failed to fetch Dune data: {:?}", e);
+        fetcher
+            .fetch(
+                Source::Binance,
+                binance::BAR_STAMP,
+                |http, time_period, periods| async move {
+                    get_binance_data(&http, &time_period, periods).await
+                },
+            )
+            .await;
+
+        fetcher
+            .fetch(
+                Source::Coinbase,
+                coinbase::BAR_STAMP,
+                |http, time_period, periods| async move {
+                    get_coinbase_data(&http, &time_period, periods).await
+                },
+            )
+            .await;
+
+        fetcher
+            .fetch(
+                Source::Bitfinex,
+                bitfinex::BAR_STAMP,
+                |http, time_period, periods| async move {
+                    get_bitfinex_data(&http, &time_period, periods).await
+                },
+            )
+            .await;
+
+        // Swaps and oracle rounds read straight from a node, only when one is configured
+        if env::var("ETH_RPC_URL").is_ok() {
+            fetcher
+                .fetch(
+                    Source::Uniswap,
+                    uniswap::BAR_STAMP,
+                    |http, time_period, periods| async move {
+                        get_uniswap_data(&http, &time_period, periods).await
+                    },
+                )
+                .await;
+
+            fetcher
+                .fetch(
+                    Source::Chainlink,
+                    chainlink::BAR_STAMP,
+                    |http, time_period, periods| async move {
+                        get_chainlink_data(&http, &time_period, periods).await
+                    },
+                )
+                .await;
         }
-    });
-
-    // Await the dune_task separately
-    if let Err(e) = dune_task.await {
-        println!("Dune task failed to complete: {:?}", e);
     }
 
-    let kraken_map = Arc::clone(&results_map);
-    let kraken_time_period = time_period.clone();
-    let kraken_task = task::spawn(async move {
-        println!("Fetching Kraken data...");
-        match get_kraken_data(&kraken_time_period).await {
-            Ok(kraken_data) => {
-                let mut map = kraken_map.write().unwrap();
-                for (timestamp, average_price) in kraken_data {
-                    let rounded_timestamp = round_to_period(timestamp, &kraken_time_period);
-                    map.entry(rounded_timestamp)
-                        .and_modify(|e| e.2 = Some(average_price))
-                        .or_insert((None, None, Some(average_price), None, None));
-                    // Set Kraken price, others remain None
+    if let Some(file_source) = file_source {
+        let file_map = Arc::clone(&results_map);
+        let file_quality = Arc::clone(&quality_report);
+        let file_time_period = time_period.clone();
+        let file_task = task::spawn(async move {
+            println!("Reading file data...");
+            match get_file_data(&file_source, &file_time_period).await {
+                Ok(file_data) => {
+                    let mut map = file_map.write().unwrap();
+                    let quality = merge_source_bars(
+                        &mut map,
+                        file_data,
+                        BarStamp::Open, // Already aggregated onto bar opens
+                        &file_time_period,
+                        last_bar_open,
+                        Source::File,
+                    );
+                    file_quality.write().unwrap().insert(Source::File, quality);
+                } // Lock released here
+                Err(e) => {
+                    println!("Failed to read file data: {}", e);
                 }
-            } // Lock is Released here
-            Err(e) => {
-                println!("Failed to fetch Kraken data: {}", e);
             }
-        }
-    });
-
-    // Await the dune_task separately
-    if let Err(e) = kraken_task.await {
-        println!("Kraken task failed to complete: {:?}", e);
-    }
+        });
 
-    let coin_api_map = Arc::clone(&results_map);
-    let coin_api_time_period = time_period.clone();
-    let coin_api_task = task::spawn(async move {
-        println!("Fetching Coin API data...");
-        match get_coin_api_data(&coin_api_time_period).await {
-            Ok(coin_api_data) => {
-                let mut map = coin_api_map.write().unwrap();
-                for (timestamp, average_price) in coin_api_data {
-                    let rounded_timestamp = round_to_period(timestamp, &coin_api_time_period);
-                    map.entry(rounded_timestamp)
-                        .and_modify(|e| e.3 = Some(average_price))
-                        .or_insert((None, None, None, Some(average_price), None));
-                    // Set BitFinex price, others remain None
-                }
-            } // Lock released here
-            Err(e) => {
-                println!("Failed to fetch Coin API data: {}", e);
-            }
+        if let Err(e) = file_task.await {
+            println!("File task failed to complete: {:?}", e);
         }
-    });
-
-    // Await the dune_task separately
-    if let Err(e) = coin_api_task.await {
-        println!("CoinAPI task failed to complete: {:?}", e);
     }
 
+    print_quality_report(&quality_report);
+
     // Calculate volatility, then print all the sata
     let time_period_ref = time_period.clone(); // Use a reference for `time_period` here
-    if let Some(volatility) = calculate_volatility(
+    let estimate = calculate_volatility(
         &results_map, // Pass the Arc<RwLock<...>> reference only
         no_of_periods,
-    ) {
+        &quorum,
+    )?;
+    let mut source_volatilities = vec![];
+    if let Some(volatility) = estimate {
         // Reorder the map to print
         let map_read = results_map.read().unwrap(); // Longer-lived binding
         let mut sorted_results: Vec<_> = map_read.iter().collect();
         sorted_results.sort_by_key(|&(timestamp, _)| timestamp);
 
         // Print the ordered map with populated and interpolated final 'useabble' price values
-        for (timestamp, (vw, ap, kr, ca, vol)) in sorted_results {
+        for (timestamp, row) in sorted_results {
+            let prices: Vec<String> = Source::ALL
+                .iter()
+                .map(|source| format!("{}: {:?}", source.label(), row.prices.get(source)))
+                .collect();
             println!(
-                "Timestamp: {}, Polygon: {:?}, Dune: {:?}, Kraken: {:?}, BitFinex: {:?}, VOL_Price: {:?}",
-                timestamp, vw, ap, kr, ca, vol
+                "Timestamp: {}, {}, VOL_Price: {:?}",
+                timestamp,
+                prices.join(", "),
+                row.vol
             );
         }
 
@@ -273,9 +732,262 @@ async fn main() -> Result<()> {
             "Estimated Volatility over last {} {}s, ohlc avg & volume weighted = {:.6}",
             no_of_periods, time_period_ref, volatility
         );
+        drop(map_read);
+
+        // Compare each source's own volatility with the composite
+        println!(
+            "{:<10} {:<10} {:>9} {:>12} {:>14}",
+            "Source", "Venue", "Coverage", "Volatility", "vs Composite"
+        );
+        source_volatilities = calculate_source_volatilities(&results_map, min_source_coverage);
+        for source_vol in &source_volatilities {
+            match source_vol.volatility {
+                Some(source_volatility) => println!(
+                    "{:<10} {:<10} {:>8.1}% {:>12.6} {:>+14.6}",
+                    source_vol.source.label(),
+                    source_vol.source.venue(),
+                    source_vol.coverage * 100.0,
+                    source_volatility,
+                    source_volatility - volatility
+                ),
+                None => println!(
+                    "{:<10} {:<10} {:>8.1}% {:>12} {:>14}",
+                    source_vol.source.label(),
+                    source_vol.source.venue(),
+                    source_vol.coverage * 100.0,
+                    "low coverage",
+                    "-"
+                ),
+            }
+        }
     } else {
         println!("No data available to calculate volatility.");
     }
 
+    if let Some(rolling) = rolling {
+        let map_read = results_map.read().unwrap();
+        let mut sorted_results: Vec<_> = map_read.iter().collect();
+        sorted_results.sort_by_key(|&(timestamp, _)| timestamp);
+
+        // Bars unchanged since the last run are skipped, only new and revised ones are applied
+        let updated = sorted_results
+            .into_iter()
+            .filter(|&(timestamp, row)| {
+                let values: Vec<f64> = row.prices.values().copied().collect();
+                rolling.update(*timestamp, composite_price(&quorum, &values))
+            })
+            .count();
+        if let Some(volatility) = rolling.volatility() {
+            println!(
+                "Rolling volatility over last {} {}s = {:.6} ({} new or revised bar(s))",
+                no_of_periods, time_period, volatility, updated
+            );
+        }
+    }
+
+    let record = (outputs.store.is_some() || outputs.has_postgres()).then(|| {
+        let mut prices: Vec<_> = results_map
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(timestamp, row)| {
+                let values: Vec<f64> = row.prices.values().copied().collect();
+                let composite = composite_price(&quorum, &values);
+                (*timestamp, composite, row.vol)
+            })
+            .collect();
+        prices.sort_by_key(|&(timestamp, _, _)| timestamp);
+
+        EstimateRecord {
+            run_at: Utc::now(),
+            window_end,
+            time_period: time_period.clone(),
+            no_of_periods,
+            quorum: quorum.clone(),
+            exclude_partial_bar,
+            volatility: estimate,
+            prices,
+            quality: quality_report.read().unwrap().clone(),
+            source_volatilities,
+        }
+    });
+    if let (Some(store), Some(record)) = (&outputs.store, &record) {
+        let id = store.save_estimate(record)?;
+        debug!("Saved estimate {}", id);
+    }
+
+    // DEX-CEX basis between the on-chain price and the centralized venues
+    if let Some(basis) = calculate_basis(&results_map) {
+        for (timestamp, spread) in &basis.series {
+            println!("Timestamp: {}, Basis: {:.2} bps", timestamp, spread);
+        }
+        println!(
+            "DEX-CEX basis over {} bars: mean = {:.2} bps, volatility = {:.2} bps, max excursion = {:.2} bps at {}",
+            basis.series.len(),
+            basis.mean_bps,
+            basis.volatility_bps,
+            basis.max_excursion_bps,
+            basis.max_excursion_at
+        );
+        match basis.half_life_periods {
+            Some(half_life) => println!(
+                "Basis half-life of mean reversion = {:.2} {}s",
+                half_life, time_period
+            ),
+            None => println!("Basis is not mean reverting over this window"),
+        }
+    } else {
+        println!("Not enough overlapping DEX and CEX prices for a basis report.");
+    }
+
+    // The sink goes last, a database that is down only loses its copy of this run
+    #[cfg(feature = "postgres")]
+    if let (Some(postgres), Some(record)) = (&mut outputs.postgres, &record) {
+        let written = async {
+            // Bars are keyed by symbol, so another pair, pool or query doesn't overwrite them
+            let mut symbols = HashMap::new();
+            for source in record.quality.keys() {
+                symbols.insert(*source, cache::source_symbol(*source, &time_period)?);
+            }
+            let mut source_bars: Vec<_> = results_map
+                .read()
+                .unwrap()
+                .iter()
+                .flat_map(|(timestamp, row)| {
+                    row.prices.iter().map(|(&source, &price)| {
+                        let symbol = symbols.get(&source).cloned().unwrap_or_default();
+                        (*timestamp, source, symbol, price)
+                    })
+                })
+                .collect();
+            source_bars.sort_by_key(|&(timestamp, source, _, _)| (timestamp, source));
+            postgres.write(record, &source_bars).await
+        };
+        if let Err(e) = written.await {
+            println!("Failed to write the estimate to PostgreSQL: {}", e);
+        }
+    }
+
     Ok(())
 }
+
+// What every live source's fetch needs besides the source itself
+struct SourceFetcher<'a> {
+    results_map: &'a ResultsMap,
+    quality_report: &'a QualityReport,
+    http: &'a Arc<Http>,
+    bar_cache: &'a Arc<BarCache>,
+    time_period: &'a str,
+    no_of_periods: i64,
+    window_end: DateTime<Utc>,
+    last_bar_open: DateTime<Utc>,
+}
+
+impl SourceFetcher<'_> {
+    // Fetch one source through the bar cache in its own task, so a panicking adapter
+    // only loses that source, then merge its bars and record their quality
+    async fn fetch<F, Fut>(&self, source: Source, bar_stamp: BarStamp, fetch: F)
+    where
+        F: FnOnce(Arc<Http>, String, i64) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<SourceBars>> + Send,
+    {
+        let map = Arc::clone(self.results_map);
+        let quality_report = Arc::clone(self.quality_report);
+        let http = Arc::clone(self.http);
+        let cache = Arc::clone(self.bar_cache);
+        let time_period = self.time_period.to_string();
+        let no_of_periods = self.no_of_periods;
+        let window_end = self.window_end;
+        let last_bar_open = self.last_bar_open;
+        let source_task = task::spawn(async move {
+            println!("Fetching {} data...", source.label());
+            match cache
+                .fetch(source, &time_period, no_of_periods, window_end, |periods| {
+                    fetch(http, time_period.clone(), periods)
+                })
+                .await
+            {
+                Ok(source_bars) => {
+                    // Lock the map only when updating it
+                    let mut map = map.write().unwrap();
+                    let quality = merge_source_bars(
+                        &mut map,
+                        source_bars,
+                        bar_stamp,
+                        &time_period,
+                        last_bar_open,
+                        source,
+                    );
+                    quality_report.write().unwrap().insert(source, quality);
+                } // Lock released here
+                Err(e) => {
+                    println!("Failed to fetch {} data: {}", source.label(), e);
+                }
+            }
+        });
+
+        if let Err(e) = source_task.await {
+            println!("{} task failed to complete: {:?}", source.label(), e);
+        }
+    }
+}
+
+// Open time of the last bar in the window ending at `window_end`. The bar still open at
+// `window_end` is left out when `exclude_partial_bar` is set.
+fn last_bar_open(
+    window_end: DateTime<Utc>,
+    time_period: &str,
+    exclude_partial_bar: bool,
+) -> DateTime<Utc> {
+    let partial_bar_open = round_to_period(window_end, time_period);
+    if exclude_partial_bar {
+        partial_bar_open - period_duration(time_period)
+    } else {
+        partial_bar_open
+    }
+}
+
+// Merge one source's bars into the results map at their normalized bar open times,
+// under the source's column
+fn merge_source_bars(
+    map: &mut HashMap<DateTime<Utc>, PriceRow>,
+    source_bars: SourceBars,
+    bar_stamp: BarStamp,
+    time_period: &str,
+    last_bar_open: DateTime<Utc>,
+    source: Source,
+) -> SourceQuality {
+    let mut quality = SourceQuality {
+        bars_received: source_bars.bars.len() + source_bars.timestamp_failures,
+        timestamp_failures: source_bars.timestamp_failures,
+        ..Default::default()
+    };
+
+    for (timestamp, price) in source_bars.bars {
+        let rounded_time = bar_stamp.to_bar_open(timestamp, time_period);
+        if rounded_time > last_bar_open {
+            quality.outside_window += 1;
+            continue;
+        }
+
+        if map.contains_key(&rounded_time) {
+            debug!(
+                "Updating existing entry: {} with price: {}",
+                rounded_time, price
+            );
+        } else {
+            debug!(
+                "Inserting new entry: {} with price: {}",
+                rounded_time, price
+            );
+        }
+
+        map.entry(rounded_time)
+            .or_default()
+            .prices
+            .insert(source, price);
+        quality.bars_merged += 1;
+    }
+
+    quality
+}
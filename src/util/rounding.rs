@@ -1,25 +1,74 @@
-use chrono::{NaiveDateTime, Timelike};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Timelike, Utc};
 
 /// Rounds the timestamp to the nearest period (minute, hour, etc.)
 //  #[allow(dead_code)]
-pub fn round_to_period(timestamp: NaiveDateTime, period: &str) -> NaiveDateTime {
-    match period {
+pub fn round_to_period(timestamp: DateTime<Utc>, period: &str) -> DateTime<Utc> {
+    let rounded = match period {
         "second" => timestamp
-            .date()
+            .date_naive()
             .and_hms_opt(timestamp.hour(), timestamp.minute(), timestamp.second())
             .unwrap(),
         "minute" => timestamp
-            .date()
+            .date_naive()
             .and_hms_opt(timestamp.hour(), timestamp.minute(), 0)
             .unwrap(),
         "hour" => timestamp
-            .date()
+            .date_naive()
             .and_hms_opt(timestamp.hour(), 0, 0)
             .unwrap(),
-        "day" => timestamp.date().and_hms_opt(0, 0, 0).unwrap(),
+        "day" => timestamp.date_naive().and_hms_opt(0, 0, 0).unwrap(),
         _ => timestamp
-            .date()
+            .date_naive()
             .and_hms_opt(timestamp.hour(), 0, 0)
             .unwrap(), // Default to 'hour'
+    };
+    rounded.and_utc()
+}
+
+/// Length of one period, defaults to an hour like `round_to_period`
+pub fn period_duration(period: &str) -> Duration {
+    match period {
+        "second" => Duration::seconds(1),
+        "minute" => Duration::minutes(1),
+        "hour" => Duration::hours(1),
+        "day" => Duration::days(1),
+        _ => Duration::hours(1),
+    }
+}
+
+/// The time `no_of_periods` periods before `end`, an error rather than an overflow for windows
+/// reaching past what a `DateTime` can hold
+pub fn periods_before(
+    end: DateTime<Utc>,
+    period: &str,
+    no_of_periods: i64,
+) -> Result<DateTime<Utc>> {
+    i32::try_from(no_of_periods)
+        .ok()
+        .and_then(|periods| period_duration(period).checked_mul(periods))
+        .and_then(|window| end.checked_sub_signed(window))
+        .ok_or_else(|| anyhow!("A window of {} {}s is too long", no_of_periods, period))
+}
+
+/// Which edge of the bar a source stamps its bars with.
+/// The merged grid uses `Open`: a bar covers [open, open + period).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BarStamp {
+    /// Bar open time, start inclusive and end exclusive
+    Open,
+    /// Bar close time, end inclusive, so a stamp on a boundary closes the earlier bar
+    Close,
+}
+
+impl BarStamp {
+    /// Normalizes a source timestamp to the open time of the bar it belongs to
+    pub fn to_bar_open(self, timestamp: DateTime<Utc>, period: &str) -> DateTime<Utc> {
+        let bar_open = round_to_period(timestamp, period);
+        match self {
+            BarStamp::Open => bar_open,
+            BarStamp::Close if bar_open == timestamp => bar_open - period_duration(period),
+            BarStamp::Close => bar_open,
+        }
     }
 }
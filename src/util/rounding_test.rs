@@ -0,0 +1,54 @@
+use super::rounding::{periods_before, BarStamp};
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+fn parse(timestamp: &str) -> DateTime<Utc> {
+    NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S%.f")
+        .unwrap()
+        .and_utc()
+}
+
+#[test]
+fn test_open_stamped_bars_keep_their_open_time() {
+    assert_eq!(
+        BarStamp::Open.to_bar_open(parse("2024-09-01 10:00:00"), "hour"),
+        parse("2024-09-01 10:00:00")
+    );
+    assert_eq!(
+        BarStamp::Open.to_bar_open(parse("2024-09-01 10:59:59.999"), "hour"),
+        parse("2024-09-01 10:00:00")
+    );
+}
+
+#[test]
+fn test_close_stamped_bars_move_to_their_open_time() {
+    // A close stamp on the boundary belongs to the bar that ends there
+    assert_eq!(
+        BarStamp::Close.to_bar_open(parse("2024-09-01 11:00:00"), "hour"),
+        parse("2024-09-01 10:00:00")
+    );
+    // Close stamps just inside the bar, like 10:59:59.999
+    assert_eq!(
+        BarStamp::Close.to_bar_open(parse("2024-09-01 10:59:59.999"), "hour"),
+        parse("2024-09-01 10:00:00")
+    );
+    assert_eq!(
+        BarStamp::Close.to_bar_open(parse("2024-09-02 00:00:00"), "day"),
+        parse("2024-09-01 00:00:00")
+    );
+}
+
+#[test]
+fn test_periods_before_rejects_overflowing_windows() {
+    let end = parse("2024-09-01 00:00:00");
+    assert_eq!(
+        periods_before(end, "minute", 90).unwrap(),
+        parse("2024-08-31 22:30:00")
+    );
+    assert_eq!(
+        periods_before(end, "day", 366).unwrap(),
+        parse("2023-09-01 00:00:00")
+    );
+    // Past i32, and within i32 but before the earliest DateTime
+    assert!(periods_before(end, "second", i64::from(i32::MAX) + 1).is_err());
+    assert!(periods_before(end, "day", i64::from(i32::MAX)).is_err());
+}
@@ -0,0 +1,45 @@
+use super::rounding::{period_duration, round_to_period};
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+
+// When the daemon should next run: the start of the next period after `now`, plus `settle`
+pub fn next_boundary(now: DateTime<Utc>, time_period: &str, settle: Duration) -> DateTime<Utc> {
+    round_to_period(now, time_period) + period_duration(time_period) + settle
+}
+
+// SIGTERM or Ctrl-C. Signals that arrive while a run is in progress are kept until `recv`.
+pub struct Shutdown {
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    interrupt: tokio::signal::unix::Signal,
+}
+
+impl Shutdown {
+    #[cfg(unix)]
+    pub fn new() -> Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Shutdown {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new() -> Result<Self> {
+        Ok(Shutdown {})
+    }
+
+    #[cfg(unix)]
+    pub async fn recv(&mut self) {
+        tokio::select! {
+            _ = self.terminate.recv() => {}
+            _ = self.interrupt.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub async fn recv(&mut self) {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
@@ -0,0 +1,41 @@
+use super::schedule::next_boundary;
+use chrono::{DateTime, Duration};
+
+#[test]
+fn test_next_boundary_is_the_next_period_start() {
+    let now = DateTime::parse_from_rfc3339("2024-06-01T12:34:56.789Z")
+        .unwrap()
+        .to_utc();
+    let settle = Duration::seconds(2);
+
+    let expected = |time: &str| DateTime::parse_from_rfc3339(time).unwrap().to_utc();
+    assert_eq!(
+        next_boundary(now, "second", settle),
+        expected("2024-06-01T12:34:59Z")
+    );
+    assert_eq!(
+        next_boundary(now, "minute", settle),
+        expected("2024-06-01T12:35:02Z")
+    );
+    assert_eq!(
+        next_boundary(now, "hour", settle),
+        expected("2024-06-01T13:00:02Z")
+    );
+    assert_eq!(
+        next_boundary(now, "day", Duration::zero()),
+        expected("2024-06-02T00:00:00Z")
+    );
+}
+
+#[test]
+fn test_next_boundary_on_a_boundary_waits_a_full_period() {
+    let now = DateTime::parse_from_rfc3339("2024-06-01T13:00:00Z")
+        .unwrap()
+        .to_utc();
+    assert_eq!(
+        next_boundary(now, "hour", Duration::zero()),
+        DateTime::parse_from_rfc3339("2024-06-01T14:00:00Z")
+            .unwrap()
+            .to_utc()
+    );
+}
@@ -0,0 +1,75 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+// Epoch values above this are taken as milliseconds (seconds would be past the year 5000)
+const EPOCH_MILLIS_THRESHOLD: i64 = 100_000_000_000;
+
+/// Converts epoch seconds or milliseconds to a UTC timestamp
+pub fn parse_epoch(value: i64) -> Option<DateTime<Utc>> {
+    if value.abs() >= EPOCH_MILLIS_THRESHOLD {
+        DateTime::from_timestamp_millis(value)
+    } else {
+        DateTime::from_timestamp(value, 0)
+    }
+}
+
+/// Parses RFC 3339, epoch seconds/millis and Dune's "2024-09-01 10:00:00.000 UTC" format
+pub fn parse_timestamp(raw: &str) -> Result<DateTime<Utc>> {
+    let raw = raw.trim();
+
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(raw) {
+        return Ok(datetime.with_timezone(&Utc));
+    }
+
+    if let Ok(epoch) = raw.parse::<i64>() {
+        return parse_epoch(epoch).ok_or_else(|| anyhow!("Epoch timestamp out of range: {}", raw));
+    }
+
+    // Fractional epoch seconds, e.g. Kraken trade times
+    if let Ok(epoch) = raw.parse::<f64>() {
+        if epoch.is_finite() {
+            return DateTime::from_timestamp_micros((epoch * 1_000_000.0).round() as i64)
+                .ok_or_else(|| anyhow!("Epoch timestamp out of range: {}", raw));
+        }
+    }
+
+    // Dune renders timestamps with a zone abbreviation, which chrono can't resolve,
+    // so only UTC is accepted
+    if let Some(naive) = raw.strip_suffix(" UTC") {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(naive, "%Y-%m-%d %H:%M:%S%.f") {
+            return Ok(datetime.and_utc());
+        }
+    }
+
+    Err(anyhow!("Unrecognized timestamp: {}", raw))
+}
+
+/// Like `parse_timestamp`, but timestamps without an offset are read as local time in `timezone`
+pub fn parse_local_timestamp(raw: &str, timezone: Tz) -> Result<DateTime<Utc>> {
+    if let Ok(datetime) = parse_timestamp(raw) {
+        return Ok(datetime);
+    }
+
+    let raw = raw.trim();
+    for format in [
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M",
+    ] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(raw, format) {
+            // Ambiguous local times (clocks going back) take the earlier instant
+            return timezone
+                .from_local_datetime(&naive)
+                .earliest()
+                .map(|datetime| datetime.with_timezone(&Utc))
+                .ok_or_else(|| anyhow!("{} does not exist in {}", raw, timezone));
+        }
+    }
+    // Dates alone are midnight local time
+    if let Ok(date) = NaiveDate::parse_from_str(raw, "%Y-%m-%d") {
+        return parse_local_timestamp(&format!("{} 00:00:00", date), timezone);
+    }
+
+    Err(anyhow!("Unrecognized timestamp: {}", raw))
+}
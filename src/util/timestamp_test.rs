@@ -0,0 +1,61 @@
+use super::timestamp::{parse_epoch, parse_local_timestamp, parse_timestamp};
+use chrono::{TimeZone, Utc};
+
+#[test]
+fn test_parse_timestamp_formats() {
+    let expected = Utc.with_ymd_and_hms(2024, 9, 1, 10, 0, 0).unwrap();
+
+    // RFC 3339, with and without an offset other than UTC
+    assert_eq!(parse_timestamp("2024-09-01T10:00:00Z").unwrap(), expected);
+    assert_eq!(
+        parse_timestamp("2024-09-01T10:00:00.0000000Z").unwrap(),
+        expected
+    );
+    assert_eq!(
+        parse_timestamp("2024-09-01T12:00:00+02:00").unwrap(),
+        expected
+    );
+
+    // Epoch seconds and milliseconds
+    assert_eq!(parse_timestamp("1725184800").unwrap(), expected);
+    assert_eq!(parse_timestamp("1725184800000").unwrap(), expected);
+    assert_eq!(parse_epoch(1725184800000).unwrap(), expected);
+
+    // Dune's format
+    assert_eq!(
+        parse_timestamp("2024-09-01 10:00:00.000 UTC").unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn test_parse_timestamp_rejects_unknown_formats() {
+    assert!(parse_timestamp("2024-09-01 10:00:00.000 EST").is_err());
+    assert!(parse_timestamp("01/09/2024").is_err());
+    assert!(parse_timestamp("").is_err());
+}
+
+#[test]
+fn test_parse_local_timestamp() {
+    let expected = Utc.with_ymd_and_hms(2024, 9, 1, 14, 0, 0).unwrap();
+    let new_york = chrono_tz::America::New_York;
+
+    // Naive times are local, EDT is UTC-4 in September
+    assert_eq!(
+        parse_local_timestamp("2024-09-01 10:00:00", new_york).unwrap(),
+        expected
+    );
+    assert_eq!(
+        parse_local_timestamp("2024-09-01 10:00", new_york).unwrap(),
+        expected
+    );
+    // An explicit offset wins over the configured zone
+    assert_eq!(
+        parse_local_timestamp("2024-09-01T14:00:00Z", new_york).unwrap(),
+        expected
+    );
+    assert_eq!(
+        parse_local_timestamp("2024-09-01", new_york).unwrap(),
+        Utc.with_ymd_and_hms(2024, 9, 1, 4, 0, 0).unwrap()
+    );
+}